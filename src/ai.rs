@@ -0,0 +1,505 @@
+use crate::{
+    components::{Disposition, DoorState, Item},
+    darkness,
+    world::World,
+};
+use gridbugs::{
+    coord_2d::{Coord, Size},
+    direction::{CardinalDirection, Direction},
+    entity_table::Entity,
+    grid_search_cardinal::{
+        distance_map::{
+            DistanceMap, PopulateContext as DistanceMapPopulateContext,
+            SearchContext as DistanceMapSearchContext,
+        },
+        CanEnter,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, str::FromStr};
+
+/// How far from the player NPCs will try to find a path
+const APPROACH_DISTANCE: u32 = 20;
+const SEARCH_DEPTH: u32 = 8;
+/// NPCs with pack tactics hold back unless an ally is at least this close to the player
+const PACK_DISTANCE_SQUARED: u32 = 8;
+/// How much an NPC which knows about traps is put off stepping onto one
+pub const TRAP_RISK: u32 = 10;
+/// How much an NPC which shuns the light is put off stepping into a lit cell
+pub const LIGHT_RISK: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AiDifficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl AiDifficulty {
+    /// The behaviours which any NPC may use at this difficulty
+    pub fn behaviours(self) -> AiBehaviours {
+        match self {
+            Self::Easy => AiBehaviours::NONE,
+            Self::Normal => AiBehaviours {
+                flee: true,
+                open_doors: true,
+                avoid_traps: true,
+                ..AiBehaviours::NONE
+            },
+            Self::Hard => AiBehaviours::ALL,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Easy => "easy",
+            Self::Normal => "normal",
+            Self::Hard => "hard",
+        }
+    }
+}
+
+impl FromStr for AiDifficulty {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Self::Easy),
+            "normal" => Ok(Self::Normal),
+            "hard" => Ok(Self::Hard),
+            other => Err(format!(
+                "unknown difficulty \"{}\" (expected easy, normal or hard)",
+                other
+            )),
+        }
+    }
+}
+
+/// Optional behaviours which make NPCs more dangerous. An NPC only uses a behaviour if both
+/// its species and the difficulty allow it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AiBehaviours {
+    pub pack_tactics: bool,
+    pub open_doors: bool,
+    pub use_items: bool,
+    pub avoid_traps: bool,
+    pub flee: bool,
+}
+
+impl AiBehaviours {
+    pub const NONE: Self = Self {
+        pack_tactics: false,
+        open_doors: false,
+        use_items: false,
+        avoid_traps: false,
+        flee: false,
+    };
+
+    pub const ALL: Self = Self {
+        pack_tactics: true,
+        open_doors: true,
+        use_items: true,
+        avoid_traps: true,
+        flee: true,
+    };
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self {
+            pack_tactics: self.pack_tactics && other.pack_tactics,
+            open_doors: self.open_doors && other.open_doors,
+            use_items: self.use_items && other.use_items,
+            avoid_traps: self.avoid_traps && other.avoid_traps,
+            flee: self.flee && other.flee,
+        }
+    }
+}
+
+/// How an NPC's species gets about, which decides what it has to watch out for on the way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Movement {
+    /// Drifts above the ground, so traps don't go off under it
+    pub floats: bool,
+    /// Keeps to the dark where it can
+    pub shuns_light: bool,
+    /// The most risk the NPC will take in a step when there's no safe way to go
+    pub risk_tolerance: u32,
+}
+
+/// How much an NPC which moves in the given way is put off entering a cell, with 0 for cells
+/// which are safe. Traps only count for NPCs which know to look out for them.
+fn risk(world: &World, coord: Coord, movement: Movement, knows_traps: bool) -> u32 {
+    let mut risk = 0;
+    if knows_traps && !movement.floats && world.trap_at_coord(coord).is_some() {
+        risk += TRAP_RISK;
+    }
+    if movement.shuns_light && !darkness::is_unlit(world, coord) {
+        risk += LIGHT_RISK;
+    }
+    risk
+}
+
+pub enum NpcAction {
+    Wait,
+    Move(CardinalDirection),
+    Attack,
+    OpenDoor(Entity),
+    DrinkPotion(usize),
+}
+
+struct CanEnterIgnoreCharacters<'a> {
+    world: &'a World,
+    open_doors: bool,
+}
+
+impl<'a> CanEnter for CanEnterIgnoreCharacters<'a> {
+    fn can_enter(&self, coord: Coord) -> bool {
+        self.world.is_walkable_coord(coord)
+            || (self.open_doors && self.world.door_at_coord(coord, DoorState::Closed).is_some())
+    }
+}
+
+/// Which cells an NPC is willing to path through. NPCs which would take the same risks share
+/// a distance map to the player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Route {
+    open_doors: bool,
+    avoid_traps: bool,
+    movement: Movement,
+    max_risk: u32,
+}
+
+impl Route {
+    fn new(behaviours: AiBehaviours, movement: Movement, max_risk: u32) -> Self {
+        Self {
+            open_doors: behaviours.open_doors,
+            avoid_traps: behaviours.avoid_traps,
+            movement,
+            max_risk,
+        }
+    }
+
+    fn can_enter(self, world: &World) -> CanEnterAvoidCharacters<'_> {
+        CanEnterAvoidCharacters {
+            world,
+            open_doors: self.open_doors,
+            movement: self.movement,
+            avoid_traps: self.avoid_traps,
+            max_risk: self.max_risk,
+            avoid_characters: true,
+        }
+    }
+}
+
+struct CanEnterAvoidCharacters<'a> {
+    world: &'a World,
+    open_doors: bool,
+    movement: Movement,
+    avoid_traps: bool,
+    /// Cells riskier than this are treated as blocked
+    max_risk: u32,
+    /// Distance maps are shared by every NPC, so they path through characters
+    avoid_characters: bool,
+}
+
+impl<'a> CanEnterAvoidCharacters<'a> {
+    fn risk(&self, coord: Coord) -> u32 {
+        risk(self.world, coord, self.movement, self.avoid_traps)
+    }
+}
+
+impl<'a> CanEnter for CanEnterAvoidCharacters<'a> {
+    fn can_enter(&self, coord: Coord) -> bool {
+        CanEnterIgnoreCharacters {
+            world: self.world,
+            open_doors: self.open_doors,
+        }
+        .can_enter(coord)
+            && !(self.avoid_characters && self.world.is_character_at_coord(coord))
+            && self.risk(coord) <= self.max_risk
+    }
+}
+
+/// Pathfinding state shared between all NPCs. Distance maps towards the player leave out the
+/// cells too risky for the NPCs using them, so a long way round a known trap is still found.
+/// They're made when an NPC first needs one each turn, and then each NPC searches its map
+/// for its next step.
+pub struct AiContext {
+    size: Size,
+    player_coord: Option<Coord>,
+    distance_maps_to_player: HashMap<Route, DistanceMap>,
+    distance_map_populate_context: DistanceMapPopulateContext,
+    distance_map_search_context: DistanceMapSearchContext,
+}
+
+impl Default for AiContext {
+    fn default() -> Self {
+        Self::new(Size::new(0, 0))
+    }
+}
+
+fn is_low_health(world: &World, entity: Entity) -> bool {
+    world
+        .components
+        .hit_points
+        .get(entity)
+        .map(|hit_points| hit_points.current * 3 <= hit_points.max)
+        .unwrap_or(false)
+}
+
+/// Whether two cells touch, including diagonally. The player can attack in any of the eight
+/// directions, so NPCs can too.
+fn is_adjacent(a: Coord, b: Coord) -> bool {
+    Direction::all().any(|direction| a + direction.coord() == b)
+}
+
+/// True if another hostile NPC is close enough to the player to join an attack, or if there
+/// are no other hostile NPCs nearby to wait for
+fn has_pack_support(world: &World, entity: Entity, player_coord: Coord) -> bool {
+    let mut allies = world
+        .all_npcs()
+        .filter(|&(other, npc)| other != entity && npc.disposition == Disposition::Hostile)
+        .filter_map(|(other, _)| world.entity_coord(other))
+        .map(|coord| (coord - player_coord).magnitude2())
+        .filter(|&distance_squared| distance_squared <= APPROACH_DISTANCE * APPROACH_DISTANCE)
+        .peekable();
+    if allies.peek().is_none() {
+        return true;
+    }
+    allies.any(|distance_squared| distance_squared <= PACK_DISTANCE_SQUARED)
+}
+
+impl AiContext {
+    fn new(size: Size) -> Self {
+        Self {
+            size,
+            player_coord: None,
+            distance_maps_to_player: HashMap::new(),
+            distance_map_populate_context: DistanceMapPopulateContext::default(),
+            distance_map_search_context: DistanceMapSearchContext::new(size),
+        }
+    }
+
+    pub fn update_player_coord(&mut self, player_coord: Coord, world: &World) {
+        if self.size != world.size() {
+            *self = Self::new(world.size());
+        }
+        self.player_coord = Some(player_coord);
+        self.distance_maps_to_player.clear();
+    }
+
+    /// The direction an NPC at the given coord should move to get closer to the player, or
+    /// `None` if there is no way to get closer. A way which avoids every risk is preferred,
+    /// and only if there isn't one will the NPC take a risk it's willing to.
+    fn approach_player(
+        &mut self,
+        coord: Coord,
+        world: &World,
+        behaviours: AiBehaviours,
+        movement: Movement,
+    ) -> Option<CardinalDirection> {
+        self.approach_player_within_risk(coord, world, behaviours, movement, 0)
+            .or_else(|| {
+                if movement.risk_tolerance == 0 {
+                    return None;
+                }
+                self.approach_player_within_risk(
+                    coord,
+                    world,
+                    behaviours,
+                    movement,
+                    movement.risk_tolerance,
+                )
+            })
+    }
+
+    fn approach_player_within_risk(
+        &mut self,
+        coord: Coord,
+        world: &World,
+        behaviours: AiBehaviours,
+        movement: Movement,
+        max_risk: u32,
+    ) -> Option<CardinalDirection> {
+        let player_coord = self.player_coord?;
+        let route = Route::new(behaviours, movement, max_risk);
+        let size = self.size;
+        let populate_context = &mut self.distance_map_populate_context;
+        let distance_map = self
+            .distance_maps_to_player
+            .entry(route)
+            .or_insert_with(|| {
+                let mut distance_map = DistanceMap::new(size);
+                populate_context.add(player_coord);
+                populate_context.populate_approach(
+                    &CanEnterAvoidCharacters {
+                        avoid_characters: false,
+                        ..route.can_enter(world)
+                    },
+                    APPROACH_DISTANCE,
+                    &mut distance_map,
+                );
+                distance_map
+            });
+        self.distance_map_search_context.search_first(
+            &route.can_enter(world),
+            coord,
+            SEARCH_DEPTH,
+            distance_map,
+        )
+    }
+
+    /// The neighbouring cell which takes an NPC closest to its home, as long as it's closer
+    /// than where the NPC is now. The least risky such cell is chosen.
+    fn return_home(
+        &self,
+        coord: Coord,
+        world: &World,
+        behaviours: AiBehaviours,
+        movement: Movement,
+        home: Coord,
+    ) -> Option<CardinalDirection> {
+        let can_enter = CanEnterAvoidCharacters {
+            world,
+            open_doors: false,
+            movement,
+            avoid_traps: behaviours.avoid_traps,
+            max_risk: movement.risk_tolerance,
+            avoid_characters: true,
+        };
+        let current_distance_squared = (coord - home).magnitude2();
+        CardinalDirection::all()
+            .filter(|direction| can_enter.can_enter(coord + direction.coord()))
+            .map(|direction| {
+                let destination = coord + direction.coord();
+                let distance_squared = (destination - home).magnitude2();
+                (direction, can_enter.risk(destination), distance_squared)
+            })
+            .filter(|&(_, _, distance_squared)| distance_squared < current_distance_squared)
+            .min_by_key(|&(_, risk, distance_squared)| (risk, distance_squared))
+            .map(|(direction, _, _)| direction)
+    }
+
+    /// The neighbouring cell which takes an NPC furthest from the player, as long as it's
+    /// further than where the NPC is now. The least risky such cell is chosen.
+    fn flee_player(
+        &self,
+        coord: Coord,
+        world: &World,
+        behaviours: AiBehaviours,
+        movement: Movement,
+        player_coord: Coord,
+    ) -> Option<CardinalDirection> {
+        let can_enter = CanEnterAvoidCharacters {
+            world,
+            open_doors: false,
+            movement,
+            avoid_traps: behaviours.avoid_traps,
+            max_risk: movement.risk_tolerance,
+            avoid_characters: true,
+        };
+        let current_distance_squared = (coord - player_coord).magnitude2();
+        CardinalDirection::all()
+            .filter(|direction| can_enter.can_enter(coord + direction.coord()))
+            .map(|direction| {
+                let destination = coord + direction.coord();
+                let distance_squared = (destination - player_coord).magnitude2();
+                (direction, can_enter.risk(destination), distance_squared)
+            })
+            .filter(|&(_, _, distance_squared)| distance_squared > current_distance_squared)
+            .min_by_key(|&(_, risk, distance_squared)| (risk, std::cmp::Reverse(distance_squared)))
+            .map(|(direction, _, _)| direction)
+    }
+
+    pub fn choose_action(
+        &mut self,
+        entity: Entity,
+        world: &World,
+        behaviours: AiBehaviours,
+        player_coord: Coord,
+    ) -> NpcAction {
+        let coord = match world.entity_coord(entity) {
+            Some(coord) => coord,
+            None => return NpcAction::Wait,
+        };
+        let movement = world
+            .components
+            .npc
+            .get(entity)
+            .map(|npc| npc.movement)
+            .unwrap_or_default();
+        let distance_squared = (player_coord - coord).magnitude2();
+        if is_low_health(world, entity) {
+            if behaviours.use_items {
+                if let Some(index) = world.inventory_index_of(entity, Item::Potion) {
+                    return NpcAction::DrinkPotion(index);
+                }
+            }
+            if behaviours.flee {
+                if let Some(direction) =
+                    self.flee_player(coord, world, behaviours, movement, player_coord)
+                {
+                    return NpcAction::Move(direction);
+                }
+            }
+        }
+        if is_adjacent(coord, player_coord) {
+            return NpcAction::Attack;
+        }
+        let territory = world.components.territory.get(entity).cloned();
+        if let Some(territory) = territory {
+            if !territory.contains(player_coord) {
+                return match self.return_home(coord, world, behaviours, movement, territory.home) {
+                    Some(direction) => NpcAction::Move(direction),
+                    None => NpcAction::Wait,
+                };
+            }
+        }
+        if behaviours.pack_tactics
+            && distance_squared <= PACK_DISTANCE_SQUARED
+            && !has_pack_support(world, entity, player_coord)
+        {
+            return NpcAction::Wait;
+        }
+        match self.approach_player(coord, world, behaviours, movement) {
+            Some(direction) => {
+                let destination = coord + direction.coord();
+                if let Some(territory) = territory {
+                    if !territory.contains(destination) {
+                        return NpcAction::Wait;
+                    }
+                }
+                match world.door_at_coord(destination, DoorState::Closed) {
+                    Some(door) => NpcAction::OpenDoor(door),
+                    None => NpcAction::Move(direction),
+                }
+            }
+            None => NpcAction::Wait,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::terrain::Terrain;
+
+    #[test]
+    fn npcs_take_the_long_way_round_known_traps() {
+        let terrain = Terrain::parse(concat!(
+            "###########\n",
+            "#...g^@...#\n",
+            "#.#######.#\n",
+            "#.........#\n",
+            "###########\n",
+        ))
+        .unwrap();
+        let mut world = terrain.world;
+        let player_coord = world.entity_coord(terrain.player_entity.unwrap()).unwrap();
+        let goblin = world.character_at_coord(Coord::new(4, 1)).unwrap();
+        // let it roam beyond its territory so the only thing in its way is the trap
+        world.components.territory.remove(goblin);
+        let mut ai_context = AiContext::default();
+        ai_context.update_player_coord(player_coord, &world);
+        let action = ai_context.choose_action(goblin, &world, AiBehaviours::ALL, player_coord);
+        assert!(matches!(action, NpcAction::Move(CardinalDirection::West)));
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{components::Tile, settings::AnimationSpeed};
+use gridbugs::{coord_2d::Coord, entity_table::Entity};
+use std::time::Duration;
+
+/// Time a projectile spends in each cell at normal animation speed
+pub const PROJECTILE_FRAME_DURATION: Duration = Duration::from_millis(40);
+/// Durations of the character animations at normal animation speed
+const LUNGE_DURATION: Duration = Duration::from_millis(80);
+const HURT_DURATION: Duration = Duration::from_millis(240);
+const DEATH_FADE_DURATION: Duration = Duration::from_millis(400);
+const FLASH_DURATION: Duration = Duration::from_millis(120);
+/// Number of times a hurt character flashes
+const HURT_FLASHES: u32 = 2;
+
+/// A tile which moves across the map one cell per frame. Input is ignored while an animation
+/// is playing.
+pub struct Projectile {
+    path: Vec<Coord>,
+    tile: Tile,
+    /// A transient light which travels with the projectile
+    light: Option<Entity>,
+    frame: usize,
+    elapsed: Duration,
+}
+
+impl Projectile {
+    pub fn new(path: Vec<Coord>, tile: Tile) -> Self {
+        Self {
+            path,
+            tile,
+            light: None,
+            frame: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    pub fn with_light(self, light: Entity) -> Self {
+        Self {
+            light: Some(light),
+            ..self
+        }
+    }
+
+    pub fn light(&self) -> Option<Entity> {
+        self.light
+    }
+
+    /// The cell the projectile currently occupies
+    pub fn current(&self) -> Option<(Coord, Tile)> {
+        self.path.get(self.frame).map(|&coord| (coord, self.tile))
+    }
+
+    /// Advances the animation by the time since the last tick. A `frame_duration` of `None`
+    /// skips to the end. Returns true once the projectile has reached the end of its path.
+    pub fn tick(&mut self, since_last_tick: Duration, frame_duration: Option<Duration>) -> bool {
+        let frame_duration = match frame_duration {
+            Some(frame_duration) => frame_duration,
+            None => {
+                self.frame = self.path.len();
+                return true;
+            }
+        };
+        self.elapsed += since_last_tick;
+        self.frame = (self.elapsed.as_nanos() / frame_duration.as_nanos().max(1)) as usize;
+        self.frame >= self.path.len()
+    }
+}
+
+/// Something which happened to a character that should be animated. The game queues these as
+/// they happen, and the real-time layer plays them without holding up input.
+#[derive(Debug, Clone, Copy)]
+pub enum AnimationCue {
+    /// An attacker striking the character in the target cell
+    Lunge { entity: Entity, target: Coord },
+    /// A character taking damage and surviving
+    Hurt(Entity),
+    /// A character dying, which has already been removed from the world
+    Death { coord: Coord, tile: Tile },
+    /// A flash of light, which is shown with a transient light rather than by a character
+    Flash(Coord),
+}
+
+impl AnimationCue {
+    fn duration(self) -> Duration {
+        match self {
+            Self::Lunge { .. } => LUNGE_DURATION,
+            Self::Hurt(_) => HURT_DURATION,
+            Self::Death { .. } => DEATH_FADE_DURATION,
+            Self::Flash(_) => FLASH_DURATION,
+        }
+    }
+}
+
+/// How an animated character differs from how it would usually be drawn in the current frame
+pub enum Pose {
+    /// Drawn in the adjacent cell towards the target instead of its own
+    Displaced { entity: Entity, target: Coord },
+    /// Drawn in the colour of an injury
+    Flash(Entity),
+    /// Still drawn where it died, with its colour scaled by `brightness` out of 255
+    Fading {
+        coord: Coord,
+        tile: Tile,
+        brightness: u8,
+    },
+}
+
+struct Playing {
+    cue: AnimationCue,
+    duration: Duration,
+    elapsed: Duration,
+}
+
+/// Lunges, flashes and fades of characters, which are only for show and end on their own
+#[derive(Default)]
+pub struct CharacterAnimations {
+    playing: Vec<Playing>,
+}
+
+impl CharacterAnimations {
+    /// Starts animating a cue, unless animations are set to complete instantly
+    pub fn start(&mut self, cue: AnimationCue, animation_speed: AnimationSpeed) {
+        if let Some(duration) = animation_speed.scale(cue.duration()) {
+            self.playing.push(Playing {
+                cue,
+                duration,
+                elapsed: Duration::ZERO,
+            });
+        }
+    }
+
+    /// Advances every animation, dropping those which have finished
+    pub fn tick(&mut self, since_last_tick: Duration) {
+        for playing in self.playing.iter_mut() {
+            playing.elapsed += since_last_tick;
+        }
+        self.playing
+            .retain(|playing| playing.elapsed < playing.duration);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.playing.is_empty()
+    }
+
+    pub fn poses(&self) -> impl Iterator<Item = Pose> + '_ {
+        self.playing.iter().filter_map(|playing| {
+            let progress = playing.elapsed.as_secs_f64() / playing.duration.as_secs_f64();
+            match playing.cue {
+                AnimationCue::Lunge { entity, target } => Some(Pose::Displaced { entity, target }),
+                AnimationCue::Hurt(entity) => {
+                    // alternates between flashing and not, starting with a flash
+                    let phase = (progress * (HURT_FLASHES * 2) as f64) as u32;
+                    phase.is_multiple_of(2).then_some(Pose::Flash(entity))
+                }
+                AnimationCue::Death { coord, tile } => Some(Pose::Fading {
+                    coord,
+                    tile,
+                    brightness: (255.0 * (1.0 - progress)) as u8,
+                }),
+                AnimationCue::Flash(_) => None,
+            }
+        })
+    }
+}
+
+/// Transient lights which go out on their own after a moment
+#[derive(Default)]
+pub struct Flashes {
+    lit: Vec<(Entity, Duration)>,
+}
+
+impl Flashes {
+    /// Returns how long a flash lasts, or `None` if animations are set to complete instantly
+    /// and it shouldn't be shown at all
+    pub fn duration(animation_speed: AnimationSpeed) -> Option<Duration> {
+        animation_speed.scale(FLASH_DURATION)
+    }
+
+    pub fn start(&mut self, light: Entity, duration: Duration) {
+        self.lit.push((light, duration));
+    }
+
+    /// Counts down each flash, returning the lights of those which have gone out
+    pub fn tick(&mut self, since_last_tick: Duration) -> Vec<Entity> {
+        let mut out = Vec::new();
+        self.lit.retain_mut(|(light, remaining)| {
+            *remaining = remaining.saturating_sub(since_last_tick);
+            if remaining.is_zero() {
+                out.push(*light);
+            }
+            !remaining.is_zero()
+        });
+        out
+    }
+}
@@ -0,0 +1,132 @@
+//! A small arena where the player fights waves of chosen monsters with chosen equipment,
+//! for balance testing and quick games. A new wave arrives a few turns after the last
+//! monster of the previous one dies, and each wave is larger than the last.
+use crate::components::{Item, Tile};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const ARENA_MAP: &str = include_str!("./arena.txt");
+/// Turns between a wave being cleared and the next one arriving
+const WAVE_DELAY: u32 = 5;
+/// Monsters in the first wave. Each later wave has one more.
+const FIRST_WAVE_SIZE: usize = 1;
+/// Monsters can't arrive closer than this to the player
+pub const MIN_SPAWN_DISTANCE: u32 = 5;
+
+fn parse_list<T>(s: &str, parse: impl Fn(&str) -> Option<T>, kind: &str) -> Result<Vec<T>, String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| parse(name).ok_or_else(|| format!("unknown {} \"{}\"", kind, name)))
+        .collect()
+}
+
+/// Items the player starts an arena fight with, as a comma separated list of item names
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Equipment(pub Vec<Item>);
+
+impl FromStr for Equipment {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_list(s, Item::from_name, "item").map(Self)
+    }
+}
+
+/// Monsters which make up the waves, as a comma separated list of monster names
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Monsters(pub Vec<Tile>);
+
+impl FromStr for Monsters {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let monster = |name: &str| {
+            [Tile::Goblin, Tile::Orc, Tile::Shade]
+                .into_iter()
+                .find(|tile| tile.name() == name)
+        };
+        let monsters = parse_list(s, monster, "monster")?;
+        if monsters.is_empty() {
+            return Err("the arena needs at least one monster".to_string());
+        }
+        Ok(Self(monsters))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArenaConfig {
+    pub equipment: Equipment,
+    pub monsters: Monsters,
+}
+
+impl Default for ArenaConfig {
+    fn default() -> Self {
+        Self {
+            equipment: Equipment(vec![Item::Sword, Item::Shield, Item::Potion]),
+            monsters: Monsters(vec![Tile::Goblin, Tile::Orc]),
+        }
+    }
+}
+
+pub struct ArenaArgs {
+    /// Start in the arena instead of the main menu
+    pub start: bool,
+    pub config: ArenaConfig,
+}
+
+impl ArenaArgs {
+    pub fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                start = flag("arena").desc("start in the arena, fighting waves of monsters");
+                equipment = opt_opt::<Equipment, _>("ITEMS", "arena-equipment")
+                    .desc("comma separated items to fight with in the arena (e.g. sword,shield)");
+                monsters = opt_opt::<Monsters, _>("MONSTERS", "arena-monsters")
+                    .desc("comma separated monsters to fight in the arena (e.g. goblin,orc)");
+            } in {
+                Self {
+                    start,
+                    config: ArenaConfig {
+                        equipment: equipment.unwrap_or_else(|| ArenaConfig::default().equipment),
+                        monsters: monsters.unwrap_or_else(|| ArenaConfig::default().monsters),
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Schedules the waves of an arena fight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Arena {
+    monsters: Vec<Tile>,
+    /// Number of waves which have arrived so far
+    pub wave: u32,
+    /// Turns until the next wave, counted down once the current wave is cleared
+    countdown: u32,
+}
+
+impl Arena {
+    pub fn new(config: &ArenaConfig) -> Self {
+        Self {
+            monsters: config.monsters.0.clone(),
+            wave: 0,
+            countdown: 1,
+        }
+    }
+
+    /// Called once per turn with the number of monsters still alive. Returns the monsters of
+    /// the next wave when it's time for it to arrive.
+    pub fn turn(&mut self, remaining: usize) -> Option<Vec<Tile>> {
+        if remaining > 0 {
+            return None;
+        }
+        self.countdown = self.countdown.saturating_sub(1);
+        if self.countdown > 0 {
+            return None;
+        }
+        self.countdown = WAVE_DELAY;
+        let size = FIRST_WAVE_SIZE + self.wave as usize;
+        self.wave += 1;
+        Some(self.monsters.iter().cloned().cycle().take(size).collect())
+    }
+}
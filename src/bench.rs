@@ -0,0 +1,151 @@
+use crate::{
+    components::DoorState, fov::Fov, terrain::Maps, visibility::VisibilityGrid, world::World,
+};
+use gridbugs::{coord_2d::Coord, entity_table::Entity};
+use std::time::{Duration, Instant};
+
+/// Number of times the player walks over every floor cell of each level
+const ROUNDS: usize = 10;
+
+/// A door is opened or closed after this many steps, so the cost of invalidating the FOVs
+/// which it affects is part of the timings
+const DOOR_TOGGLE_INTERVAL: usize = 8;
+
+/// The player's walk over every floor cell of a level, opening and closing its doors in turn
+struct Tour {
+    world: World,
+    path: Vec<Coord>,
+    doors: Vec<Entity>,
+}
+
+impl Tour {
+    fn new(world: World) -> Self {
+        let path = world
+            .size()
+            .coord_iter_row_major()
+            .filter(|&coord| world.is_walkable_coord(coord))
+            .collect();
+        let doors = world.components.door_state.entities().collect();
+        Self { world, path, doors }
+    }
+
+    /// Moves to the `step`th cell of the path, toggling a door first if it's time to, and
+    /// returns the player's coord along with the coords whose opacity changed
+    fn step(&mut self, step: usize) -> (Coord, Vec<Coord>) {
+        if step.is_multiple_of(DOOR_TOGGLE_INTERVAL) && !self.doors.is_empty() {
+            let door = self.doors[(step / DOOR_TOGGLE_INTERVAL) % self.doors.len()];
+            match self.world.components.door_state.get(door) {
+                Some(DoorState::Open) => self.world.close_door(door),
+                Some(DoorState::Closed) => self.world.open_door(door),
+                None => (),
+            }
+        }
+        let coord = self.path[step % self.path.len()];
+        (coord, self.world.take_opacity_changes())
+    }
+}
+
+fn time_tour(world: World, use_cache: bool) -> (Duration, usize) {
+    let mut tour = Tour::new(world);
+    let mut visibility_grid = VisibilityGrid::new(tour.world.size());
+    let mut fov = Fov::default();
+    let steps = tour.path.len() * ROUNDS;
+    let start = Instant::now();
+    for step in 0..steps {
+        let (coord, opacity_changes) = tour.step(step);
+        if !use_cache {
+            visibility_grid.clear_fov_cache();
+        }
+        visibility_grid.update(
+            coord,
+            &tour.world,
+            &opacity_changes,
+            fov.strategy_mut(),
+            None,
+            None,
+        );
+    }
+    (start.elapsed(), steps)
+}
+
+/// Compares updating visibility with and without the per-light FOV cache by walking the
+/// player over every floor cell of each level while its doors open and close, and prints the
+/// timings
+pub fn visibility() {
+    let maps = Maps::builtin();
+    let uncached_terrains = maps.parse();
+    let cached_terrains = maps.parse();
+    for (i, (uncached_terrain, cached_terrain)) in uncached_terrains
+        .into_iter()
+        .zip(cached_terrains)
+        .enumerate()
+    {
+        let (uncached, steps) = time_tour(uncached_terrain.world, false);
+        let (cached, _) = time_tour(cached_terrain.world, true);
+        println!(
+            "level {}: {} steps, uncached {:?} ({:?}/step), cached {:?} ({:?}/step), {:.1}x",
+            i + 1,
+            steps,
+            uncached,
+            uncached / steps as u32,
+            cached,
+            cached / steps as u32,
+            uncached.as_secs_f64() / cached.as_secs_f64(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cached_visibility_matches_uncached_as_doors_toggle() {
+        let maps = Maps::builtin();
+        for (uncached_terrain, cached_terrain) in maps.parse().into_iter().zip(maps.parse()) {
+            let mut uncached_tour = Tour::new(uncached_terrain.world);
+            let mut cached_tour = Tour::new(cached_terrain.world);
+            assert!(!cached_tour.doors.is_empty());
+            let size = cached_tour.world.size();
+            let mut uncached_grid = VisibilityGrid::new(size);
+            let mut cached_grid = VisibilityGrid::new(size);
+            let mut fov = Fov::default();
+            for step in 0..cached_tour.path.len() {
+                let (coord, opacity_changes) = uncached_tour.step(step);
+                uncached_grid.clear_fov_cache();
+                uncached_grid.update(
+                    coord,
+                    &uncached_tour.world,
+                    &opacity_changes,
+                    fov.strategy_mut(),
+                    None,
+                    None,
+                );
+                let (coord, opacity_changes) = cached_tour.step(step);
+                cached_grid.update(
+                    coord,
+                    &cached_tour.world,
+                    &opacity_changes,
+                    fov.strategy_mut(),
+                    None,
+                    None,
+                );
+                for coord in size.coord_iter_row_major() {
+                    assert_eq!(
+                        uncached_grid
+                            .cell(coord)
+                            .unwrap()
+                            .visibility(uncached_grid.count()),
+                        cached_grid
+                            .cell(coord)
+                            .unwrap()
+                            .visibility(cached_grid.count()),
+                        "step {}: {:?}",
+                        step,
+                        coord,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Knowledge about monsters which persists between runs. Detailed stats for a species are
+//! unlocked once it has been encountered enough times.
+use crate::{
+    components::Tile,
+    journal::{GameEvent, JournalEntry},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io};
+
+const BESTIARY_PATH: &str = "bestiary.json";
+const ENCOUNTERS_TO_UNLOCK: u32 = 3;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MonsterStats {
+    pub max_hit_points: u32,
+    pub damage: u32,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct BestiaryEntry {
+    pub encounters: u32,
+    pub kills: u32,
+    pub stats: Option<MonsterStats>,
+}
+
+impl BestiaryEntry {
+    pub fn is_unlocked(&self) -> bool {
+        self.encounters >= ENCOUNTERS_TO_UNLOCK
+    }
+
+    pub fn description(&self, name: &str) -> String {
+        match self.stats {
+            Some(stats) if self.is_unlocked() => format!(
+                "{}: hp {}, damage {} (seen {}, killed {})",
+                name, stats.max_hit_points, stats.damage, self.encounters, self.kills
+            ),
+            _ => format!(
+                "{}: ??? (seen {}, killed {})",
+                name, self.encounters, self.kills
+            ),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Bestiary {
+    entries: BTreeMap<String, BestiaryEntry>,
+}
+
+impl Bestiary {
+    /// Loads the bestiary from disk, starting a new one if there isn't one yet
+    pub fn load() -> Self {
+        fs::read_to_string(BESTIARY_PATH)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(BESTIARY_PATH, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BestiaryEntry> {
+        self.entries.get(name)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &BestiaryEntry)> {
+        self.entries
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+    }
+
+    /// Updates the bestiary with any monster-related journal entries. Returns true if
+    /// anything changed.
+    pub fn record(&mut self, entries: &[JournalEntry]) -> bool {
+        let mut changed = false;
+        for entry in entries {
+            match entry.event {
+                GameEvent::Encountered { monster, stats } => {
+                    let entry = self.entries.entry(monster.name().to_string()).or_default();
+                    entry.encounters += 1;
+                    entry.stats = Some(stats);
+                    changed = true;
+                }
+                GameEvent::Died {
+                    victim: Some(victim),
+                    killer: Some(Tile::Player),
+                } => {
+                    let entry = self.entries.entry(victim.name().to_string()).or_default();
+                    entry.kills += 1;
+                    changed = true;
+                }
+                _ => (),
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,56 @@
+//! Bundles everything needed to reproduce a problem into a zip file which players can attach
+//! to bug reports
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zip::{write::FileOptions, ZipWriter};
+
+const REPORTS_DIR: &str = "reports";
+
+pub struct BugReport {
+    pub save: Vec<u8>,
+    pub journal: String,
+    pub frame: String,
+    pub info: String,
+}
+
+impl BugReport {
+    /// Writes the report to a new zip file in the reports directory, returning its path. An
+    /// existing report is never overwritten, even one written in the same millisecond.
+    pub fn write(&self) -> io::Result<PathBuf> {
+        fs::create_dir_all(REPORTS_DIR)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let mut attempt = 0;
+        let (path, file) = loop {
+            let name = match attempt {
+                0 => format!("report-{}.zip", timestamp),
+                n => format!("report-{}-{}.zip", timestamp, n),
+            };
+            let path = PathBuf::from(REPORTS_DIR).join(name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        let mut zip = ZipWriter::new(file);
+        let files: [(&str, &[u8]); 4] = [
+            ("save.bin", &self.save),
+            ("journal.txt", self.journal.as_bytes()),
+            ("frame.txt", self.frame.as_bytes()),
+            ("info.txt", self.info.as_bytes()),
+        ];
+        for (name, contents) in files {
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(contents)?;
+        }
+        zip.finish()?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,197 @@
+//! Procedurally generated cave levels for endless descent runs. Caves are grown with a
+//! cellular automaton, trimmed to their largest connected area, and stocked with more and
+//! tougher monsters the deeper they are.
+use crate::{
+    components::{HitPoints, Item, Spawner, Tile, Trap},
+    world::World,
+};
+use gridbugs::{
+    coord_2d::{Coord, Size},
+    direction::Direction,
+    entity_table::Entity,
+    grid_2d::Grid,
+};
+use rand::{seq::SliceRandom, Rng};
+use std::collections::VecDeque;
+
+const CAVE_SIZE: Size = Size::new_u16(60, 30);
+/// Chance out of 100 that a cell starts as wall before smoothing
+const INITIAL_WALL_PERCENT: u32 = 45;
+const SMOOTHING_STEPS: usize = 4;
+/// Caves whose connected area is smaller than this fraction of the map are thrown away
+const MIN_OPEN_FRACTION_PERCENT: usize = 35;
+/// Monsters aren't placed this close to where the player arrives
+const SAFE_DISTANCE: u32 = 8;
+/// Monsters gain a hit point for every this many levels of depth
+const DEPTHS_PER_EXTRA_HIT_POINT: u32 = 2;
+const ITEMS: &[Item] = &[
+    Item::Potion,
+    Item::Potion,
+    Item::Potion,
+    Item::Dagger,
+    Item::Sword,
+    Item::Greataxe,
+    Item::Shield,
+];
+
+/// A freshly generated level and where the player arrives on it
+pub struct Cave {
+    pub world: World,
+    pub arrival: Coord,
+}
+
+fn wall_neighbours(walls: &Grid<bool>, coord: Coord) -> usize {
+    Direction::all()
+        .filter(|direction| *walls.get(coord + direction.coord()).unwrap_or(&true))
+        .count()
+}
+
+fn grow_walls<R: Rng>(rng: &mut R) -> Grid<bool> {
+    let mut walls = Grid::new_fn(CAVE_SIZE, |coord| {
+        let border = coord.x == 0
+            || coord.y == 0
+            || coord.x == CAVE_SIZE.width() as i32 - 1
+            || coord.y == CAVE_SIZE.height() as i32 - 1;
+        border || rng.gen_range(0..100) < INITIAL_WALL_PERCENT
+    });
+    for _ in 0..SMOOTHING_STEPS {
+        walls = Grid::new_fn(CAVE_SIZE, |coord| {
+            let neighbours = wall_neighbours(&walls, coord);
+            if *walls.get_checked(coord) {
+                neighbours >= 4
+            } else {
+                neighbours >= 5
+            }
+        });
+    }
+    walls
+}
+
+/// Distances by walking from `start` to every open cell reachable from it
+fn distances(walls: &Grid<bool>, start: Coord) -> Grid<Option<u32>> {
+    let mut distances = Grid::new_clone(walls.size(), None);
+    *distances.get_checked_mut(start) = Some(0);
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(coord) = queue.pop_front() {
+        let distance = (*distances.get_checked(coord)).unwrap_or(0);
+        for direction in Direction::all() {
+            let neighbour = coord + direction.coord();
+            if walls.get(neighbour) == Some(&false) {
+                let cell = distances.get_checked_mut(neighbour);
+                if cell.is_none() {
+                    *cell = Some(distance + 1);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+    }
+    distances
+}
+
+/// Grows caves until one has a large enough connected area, filling in the rest. Returns
+/// the walls along with the distance of each open cell from the arrival point.
+fn connected_cave<R: Rng>(rng: &mut R) -> (Grid<bool>, Coord, Grid<Option<u32>>) {
+    loop {
+        let walls = grow_walls(rng);
+        let open = walls
+            .enumerate()
+            .filter(|(_, &wall)| !wall)
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
+        let arrival = match open.choose(rng) {
+            Some(&arrival) => arrival,
+            None => continue,
+        };
+        let distances = distances(&walls, arrival);
+        let reachable = distances.iter().filter(|d| d.is_some()).count();
+        if reachable * 100 < CAVE_SIZE.count() * MIN_OPEN_FRACTION_PERCENT {
+            continue;
+        }
+        let walls = Grid::new_fn(CAVE_SIZE, |coord| distances.get_checked(coord).is_none());
+        return (walls, arrival, distances);
+    }
+}
+
+/// Adds to the hit points of a monster so deeper monsters are tougher
+fn toughen(world: &mut World, monster: Entity, depth: u32) {
+    let extra = (depth - 1) / DEPTHS_PER_EXTRA_HIT_POINT;
+    if let Some(hit_points) = world.components.hit_points.get_mut(monster) {
+        *hit_points = HitPoints::new_full(hit_points.max + extra);
+    }
+}
+
+/// Generates the cave at the given depth. Caves below the first have stairs up where the
+/// player arrives, and every cave has stairs down as far from there as possible.
+pub fn generate<R: Rng>(depth: u32, rng: &mut R) -> Cave {
+    let (walls, arrival, distances) = connected_cave(rng);
+    let mut world = World::new(CAVE_SIZE);
+    for (coord, &wall) in walls.enumerate() {
+        if wall {
+            world.spawn_wall(coord);
+        } else {
+            world.spawn_floor(coord);
+        }
+    }
+    if depth > 1 {
+        world.spawn_stairs(arrival, Tile::StairsUp);
+    }
+    let (stairs_down, _) = distances
+        .enumerate()
+        .filter_map(|(coord, distance)| distance.map(|distance| (coord, distance)))
+        .max_by_key(|&(_, distance)| distance)
+        .expect("cave has no open cells");
+    world.spawn_stairs(stairs_down, Tile::StairsDown);
+    let mut free = distances
+        .enumerate()
+        .filter(|&(coord, _)| coord != arrival && coord != stairs_down)
+        .filter_map(|(coord, distance)| distance.map(|distance| (coord, distance)))
+        .collect::<Vec<_>>();
+    free.shuffle(rng);
+    let mut far = free
+        .iter()
+        .filter(|&&(_, distance)| distance >= SAFE_DISTANCE)
+        .map(|&(coord, _)| coord)
+        .collect::<Vec<_>>();
+    let mut near = free
+        .iter()
+        .filter(|&&(_, distance)| distance < SAFE_DISTANCE)
+        .map(|&(coord, _)| coord)
+        .collect::<Vec<_>>();
+    let orc_percent = (depth * 10).min(70);
+    for _ in 0..(2 + depth) {
+        let coord = match far.pop() {
+            Some(coord) => coord,
+            None => break,
+        };
+        let monster = if rng.gen_range(0..100) < orc_percent {
+            world.spawn_orc(coord)
+        } else {
+            world.spawn_goblin(coord)
+        };
+        toughen(&mut world, monster, depth);
+    }
+    let spawners = [(3, Spawner::Nest), (5, Spawner::Portal)];
+    for (min_depth, spawner) in spawners {
+        if depth >= min_depth && rng.gen_ratio(1, 2) {
+            if let Some(coord) = far.pop() {
+                world.spawn_spawner(coord, spawner);
+            }
+        }
+    }
+    for _ in 0..(depth / 2) {
+        if let Some(coord) = far.pop() {
+            world.spawn_trap(coord, Trap::Spikes);
+        }
+    }
+    for _ in 0..rng.gen_range(1..=3) {
+        let coord = match near.pop().or_else(|| far.pop()) {
+            Some(coord) => coord,
+            None => break,
+        };
+        if let Some(&item) = ITEMS.choose(rng) {
+            world.spawn_item(coord, item);
+        }
+    }
+    Cave { world, arrival }
+}
@@ -0,0 +1,146 @@
+//! Lets viewers of a stream vote on effects by sending commands in an IRC channel (e.g.
+//! twitch chat). Votes are tallied over a fixed window, and at most one effect is triggered
+//! per window.
+use crate::effect::Effect;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+/// Twitch accepts any nick of this form as a read-only anonymous login
+const ANONYMOUS_NICK: &str = "justinfan31415";
+const TOKEN_ENV_VAR: &str = "RL1_CHAT_TOKEN";
+const VOTE_WINDOW: Duration = Duration::from_secs(30);
+
+pub struct ChatConfig {
+    addr: String,
+    channel: String,
+    nick: String,
+}
+
+impl ChatConfig {
+    pub fn parser() -> impl meap::Parser<Item = Option<Self>> {
+        meap::let_map! {
+            let {
+                addr = opt_opt::<String, _>("HOST:PORT", "chat-irc")
+                    .desc("let viewers vote on events from an IRC channel (e.g. irc.chat.twitch.tv:6667)");
+                channel = opt_opt::<String, _>("CHANNEL", "chat-channel")
+                    .desc("IRC channel to read votes from (without the leading '#')");
+                nick = opt_opt::<String, _>("NICK", "chat-nick")
+                    .desc("nick to connect as (password is read from RL1_CHAT_TOKEN)");
+            } in {
+                addr.map(|addr| Self {
+                    addr,
+                    channel: channel.unwrap_or_else(|| "rl1".to_string()),
+                    nick: nick.unwrap_or_else(|| ANONYMOUS_NICK.to_string()),
+                })
+            }
+        }
+    }
+}
+
+struct Vote {
+    user: String,
+    effect: Effect,
+}
+
+fn parse_vote(line: &str) -> Option<Vote> {
+    // :user!user@host PRIVMSG #channel :!spawn
+    let rest = line.strip_prefix(':')?;
+    let (prefix, rest) = rest.split_once(' ')?;
+    let user = prefix.split('!').next()?;
+    let rest = rest.strip_prefix("PRIVMSG ")?;
+    let (_channel, message) = rest.split_once(" :")?;
+    let command = message.split_whitespace().next()?;
+    let effect = Effect::ALL
+        .iter()
+        .cloned()
+        .find(|effect| effect.command() == command)?;
+    Some(Vote {
+        user: user.to_string(),
+        effect,
+    })
+}
+
+fn run_connection(config: &ChatConfig, sender: &mpsc::Sender<Vote>) -> io::Result<()> {
+    let mut stream = TcpStream::connect(&config.addr)?;
+    if let Ok(token) = std::env::var(TOKEN_ENV_VAR) {
+        write!(stream, "PASS {}\r\n", token)?;
+    }
+    write!(stream, "NICK {}\r\n", config.nick)?;
+    write!(stream, "JOIN #{}\r\n", config.channel)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(server) = line.strip_prefix("PING ") {
+            write!(stream, "PONG {}\r\n", server)?;
+        } else if let Some(vote) = parse_vote(&line) {
+            if sender.send(vote).is_err() {
+                // the game has exited
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub struct Chat {
+    receiver: mpsc::Receiver<Vote>,
+    enabled: bool,
+    voters: HashSet<String>,
+    tally: HashMap<Effect, u32>,
+    since_window_start: Duration,
+}
+
+impl Chat {
+    /// Connects to the chat server on a background thread
+    pub fn spawn(config: ChatConfig) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(e) = run_connection(&config, &sender) {
+                eprintln!("chat connection failed: {}", e);
+            }
+        });
+        Self {
+            receiver,
+            enabled: true,
+            voters: HashSet::new(),
+            tally: HashMap::new(),
+            since_window_start: Duration::ZERO,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// Collects votes received since the last tick. Returns the winning effect when a voting
+    /// window closes. Votes received while disabled are discarded.
+    pub fn tick(&mut self, since_last_tick: Duration) -> Option<Effect> {
+        for Vote { user, effect } in self.receiver.try_iter() {
+            if self.enabled && self.voters.insert(user) {
+                *self.tally.entry(effect).or_insert(0) += 1;
+            }
+        }
+        self.since_window_start += since_last_tick;
+        if self.since_window_start < VOTE_WINDOW {
+            return None;
+        }
+        self.since_window_start = Duration::ZERO;
+        self.voters.clear();
+        let winner = self
+            .tally
+            .drain()
+            .max_by_key(|&(_, count)| count)
+            .map(|(effect, _)| effect);
+        if self.enabled {
+            winner
+        } else {
+            None
+        }
+    }
+}
@@ -1,20 +1,528 @@
-use crate::visibility::Light;
-use gridbugs::entity_table;
+use crate::{
+    ai::{AiBehaviours, Movement},
+    visibility::{Flicker, Light},
+};
+use gridbugs::{
+    coord_2d::Coord,
+    entity_table::{self, Entity},
+    rgb_int::Rgb24,
+};
+use serde::{Deserialize, Serialize};
 
 entity_table::declare_entity_module! {
     components {
         tile: Tile,
+        colour_hint: ColourHint,
         opacity: u8,
         solid: (),
         light: Light,
+        // Lights which never move or change, such as wall sconces
+        static_light: (),
+        flicker: Flicker,
+        npc: Npc,
+        hit_points: HitPoints,
+        stamina: Stamina,
+        // Energy gained each tick. Characters act whenever they have enough energy, so faster
+        // characters act more often.
+        speed: u32,
+        energy: u32,
+        // Scheduler ticks until a frozen character starts gaining energy again
+        frozen: u64,
+        // Number of cells away a character can see without any light
+        darkvision: u32,
+        // Scheduler ticks until a confused character can walk straight again
+        confused: u64,
+        // Scheduler ticks until a charmed NPC goes back to being hostile
+        charmed: u64,
+        damage: u32,
+        item: Item,
+        // Number of coins in a pile of gold
+        gold: u32,
+        inventory: Inventory,
+        // Which hand an item in a character's inventory is held in, if it's equipped
+        equipped: Hand,
+        door_state: DoorState,
+        trap: Trap,
+        territory: Territory,
+        spawner: Spawner,
+        // Turns until a spawner next creates a monster
+        spawn_countdown: u32,
+        description: Description,
     }
 }
 pub use components::Components;
 pub use components::EntityData;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Tile {
     Player,
+    Goblin,
+    Orc,
+    Shade,
     Wall,
     Floor,
+    Potion,
+    Key,
+    Dagger,
+    Sword,
+    Greataxe,
+    Shield,
+    Corpse,
+    Gold,
+    StairsDown,
+    StairsUp,
+    DoorClosed,
+    DoorOpen,
+    Trap,
+    Nest,
+    Portal,
+    Boulder,
+    Plate,
+    Brazier,
+    BrazierLit,
+}
+
+impl Tile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Player => "player",
+            Self::Goblin => "goblin",
+            Self::Orc => "orc",
+            Self::Shade => "shade",
+            Self::Wall => "wall",
+            Self::Floor => "floor",
+            Self::Potion => "potion",
+            Self::Key => "key",
+            Self::Dagger => "dagger",
+            Self::Sword => "sword",
+            Self::Greataxe => "greataxe",
+            Self::Shield => "shield",
+            Self::Corpse => "corpse",
+            Self::Gold => "gold",
+            Self::StairsDown => "staircase leading down",
+            Self::StairsUp => "staircase leading up",
+            Self::DoorClosed => "closed door",
+            Self::DoorOpen => "open door",
+            Self::Trap => "spike trap",
+            Self::Nest => "goblin nest",
+            Self::Portal => "portal",
+            Self::Boulder => "boulder",
+            Self::Plate => "pressure plate",
+            Self::Brazier => "unlit brazier",
+            Self::BrazierLit => "lit brazier",
+        }
+    }
+}
+
+/// Colours an entity is drawn with. Cells without a background keep the background of the
+/// layer beneath.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ColourHint {
+    pub foreground: Rgb24,
+    pub background: Option<Rgb24>,
+}
+
+impl ColourHint {
+    const fn foreground(foreground: Rgb24) -> Self {
+        Self {
+            foreground,
+            background: None,
+        }
+    }
+}
+
+impl Tile {
+    /// The usual colours for entities with this tile
+    pub fn colour_hint(self) -> ColourHint {
+        match self {
+            Self::Player => ColourHint::foreground(Rgb24::new_grey(255)),
+            Self::Goblin => ColourHint::foreground(Rgb24::new(0, 187, 0)),
+            Self::Orc => ColourHint::foreground(Rgb24::new(187, 94, 0)),
+            Self::Shade => ColourHint::foreground(Rgb24::new(111, 63, 159)),
+            Self::Wall => ColourHint {
+                foreground: Rgb24::new_grey(187),
+                background: Some(Rgb24::new_grey(63)),
+            },
+            Self::Floor => ColourHint {
+                foreground: Rgb24::new_grey(95),
+                background: Some(Rgb24::new_grey(15)),
+            },
+            Self::Potion => ColourHint::foreground(Rgb24::new(255, 0, 63)),
+            Self::Key => ColourHint::foreground(Rgb24::new(255, 215, 0)),
+            Self::Dagger | Self::Sword | Self::Greataxe => {
+                ColourHint::foreground(Rgb24::new(159, 191, 223))
+            }
+            Self::Shield => ColourHint::foreground(Rgb24::new(159, 111, 63)),
+            Self::Corpse => ColourHint::foreground(Rgb24::new(127, 31, 31)),
+            Self::Gold => ColourHint::foreground(Rgb24::new(255, 191, 0)),
+            Self::StairsDown | Self::StairsUp => ColourHint::foreground(Rgb24::new(0, 187, 187)),
+            Self::DoorClosed | Self::DoorOpen => ColourHint::foreground(Rgb24::new(150, 75, 0)),
+            Self::Trap => ColourHint::foreground(Rgb24::new_grey(191)),
+            Self::Nest => ColourHint::foreground(Rgb24::new(127, 94, 47)),
+            Self::Portal => ColourHint::foreground(Rgb24::new(187, 0, 255)),
+            Self::Boulder => ColourHint::foreground(Rgb24::new(143, 127, 111)),
+            Self::Plate => ColourHint {
+                foreground: Rgb24::new_grey(159),
+                background: Some(Rgb24::new_grey(15)),
+            },
+            Self::Brazier => ColourHint::foreground(Rgb24::new_grey(127)),
+            Self::BrazierLit => ColourHint::foreground(Rgb24::new(255, 127, 0)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Disposition {
+    Hostile,
+    /// Fights on the player's side
+    Friendly,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Npc {
+    pub disposition: Disposition,
+    /// Behaviours this species is capable of
+    pub behaviours: AiBehaviours,
+    pub movement: Movement,
+}
+
+/// The region an NPC guards. It won't chase the player outside it, and heads back home when
+/// the player leaves.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Territory {
+    pub home: Coord,
+    pub radius: u32,
+}
+
+impl Territory {
+    pub fn contains(&self, coord: Coord) -> bool {
+        (coord - self.home).magnitude2() <= self.radius * self.radius
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HitPoints {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl HitPoints {
+    pub fn new_full(max: u32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+/// Spent on exertions such as sprinting and power attacks, and recovered by resting
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Stamina {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Stamina {
+    pub fn new_full(max: u32) -> Self {
+        Self { current: max, max }
+    }
+
+    /// Returns false without spending anything if there isn't enough stamina
+    pub fn spend(&mut self, cost: u32) -> bool {
+        if self.current < cost {
+            return false;
+        }
+        self.current -= cost;
+        true
+    }
+
+    pub fn recover(&mut self, amount: u32) {
+        self.current = (self.current + amount).min(self.max);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Item {
+    Potion,
+    Key,
+    Dagger,
+    Sword,
+    Greataxe,
+    Shield,
+    Corpse,
+    Gold,
+}
+
+impl Item {
+    pub const ALL: &'static [Self] = &[
+        Self::Potion,
+        Self::Key,
+        Self::Dagger,
+        Self::Sword,
+        Self::Greataxe,
+        Self::Shield,
+        Self::Corpse,
+        Self::Gold,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Potion => "potion",
+            Self::Key => "key",
+            Self::Dagger => "dagger",
+            Self::Sword => "sword",
+            Self::Greataxe => "greataxe",
+            Self::Shield => "shield",
+            Self::Corpse => "corpse",
+            Self::Gold => "gold",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|item| item.name() == name)
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Potion => "A vial of murky red liquid.",
+            Self::Key => "A small iron key.",
+            Self::Dagger => "A short blade, light enough to wield in either hand.",
+            Self::Sword => "A well-balanced arming sword.",
+            Self::Greataxe => "A heavy axe which takes both hands to swing.",
+            Self::Shield => "A round wooden shield with an iron boss.",
+            Self::Corpse => "The remains of something which crossed your path.",
+            Self::Gold => "A pile of gold coins.",
+        }
+    }
+
+    pub fn tile(self) -> Tile {
+        match self {
+            Self::Potion => Tile::Potion,
+            Self::Key => Tile::Key,
+            Self::Dagger => Tile::Dagger,
+            Self::Sword => Tile::Sword,
+            Self::Greataxe => Tile::Greataxe,
+            Self::Shield => Tile::Shield,
+            Self::Corpse => Tile::Corpse,
+            Self::Gold => Tile::Gold,
+        }
+    }
+
+    /// How the item is held, or `None` if it can't be equipped
+    pub fn grip(self) -> Option<Grip> {
+        match self {
+            Self::Potion | Self::Key | Self::Corpse | Self::Gold => None,
+            Self::Dagger | Self::Sword => Some(Grip::OneHanded),
+            Self::Greataxe => Some(Grip::TwoHanded),
+            Self::Shield => Some(Grip::Shield),
+        }
+    }
+
+    /// Extra damage dealt by attacks with this item
+    pub fn damage_bonus(self) -> u32 {
+        match self {
+            Self::Dagger => 1,
+            Self::Sword => 2,
+            Self::Greataxe => 4,
+            Self::Potion | Self::Key | Self::Shield | Self::Corpse | Self::Gold => 0,
+        }
+    }
+
+    /// How heavy the item is to carry, counting towards the carrier's capacity
+    pub fn weight(self) -> u32 {
+        match self {
+            Self::Gold => 0,
+            Self::Potion | Self::Key => 1,
+            Self::Dagger => 2,
+            Self::Sword => 4,
+            Self::Shield => 5,
+            Self::Greataxe => 8,
+            Self::Corpse => 10,
+        }
+    }
+
+    /// The category the item belongs to for auto-pickup, or `None` if it's never picked up
+    /// automatically
+    pub fn category(self) -> Option<ItemCategory> {
+        match self {
+            Self::Gold => Some(ItemCategory::Gold),
+            Self::Potion => Some(ItemCategory::Potions),
+            Self::Key => Some(ItemCategory::Keys),
+            Self::Dagger | Self::Sword | Self::Greataxe | Self::Shield => {
+                Some(ItemCategory::Equipment)
+            }
+            Self::Corpse => None,
+        }
+    }
+}
+
+/// Kinds of item the player can choose to pick up automatically by walking over them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ItemCategory {
+    Gold,
+    Potions,
+    Keys,
+    Equipment,
+}
+
+impl ItemCategory {
+    pub const ALL: &'static [Self] = &[Self::Gold, Self::Potions, Self::Keys, Self::Equipment];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gold => "gold",
+            Self::Potions => "potions",
+            Self::Keys => "keys",
+            Self::Equipment => "equipment",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Grip {
+    OneHanded,
+    TwoHanded,
+    /// Held in the off hand, occasionally blocking attacks
+    Shield,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Hand {
+    Main,
+    Off,
+    /// A two-handed weapon occupies both hands
+    Both,
+}
+
+impl Hand {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Main => "main hand",
+            Self::Off => "off hand",
+            Self::Both => "both hands",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Inventory {
+    items: Vec<Entity>,
+    capacity: usize,
+}
+
+impl Inventory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: Vec::new(),
+            capacity,
+        }
+    }
+
+    pub fn items(&self) -> &[Entity] {
+        &self.items
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.items.len() >= self.capacity
+    }
+
+    /// Returns false if the inventory is full
+    pub fn insert(&mut self, item: Entity) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.items.push(item);
+        true
+    }
+
+    /// Empties the inventory, returning the items it contained
+    pub fn take_items(&mut self) -> Vec<Entity> {
+        std::mem::take(&mut self.items)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Entity> {
+        if index < self.items.len() {
+            Some(self.items.remove(index))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Trap {
+    Spikes,
+}
+
+impl Trap {
+    pub fn damage(self) -> u32 {
+        match self {
+            Self::Spikes => 2,
+        }
+    }
+}
+
+/// A feature which periodically creates monsters until it's destroyed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Spawner {
+    Nest,
+    Portal,
+}
+
+impl Spawner {
+    pub fn tile(self) -> Tile {
+        match self {
+            Self::Nest => Tile::Nest,
+            Self::Portal => Tile::Portal,
+        }
+    }
+
+    pub fn monster(self) -> Tile {
+        match self {
+            Self::Nest => Tile::Goblin,
+            Self::Portal => Tile::Orc,
+        }
+    }
+
+    /// Turns between monsters being created
+    pub fn interval(self) -> u32 {
+        match self {
+            Self::Nest => 15,
+            Self::Portal => 25,
+        }
+    }
+
+    pub fn hit_points(self) -> u32 {
+        match self {
+            Self::Nest => 6,
+            Self::Portal => 10,
+        }
+    }
+
+    /// Experience awarded to the player for destroying it
+    pub fn experience(self) -> u32 {
+        match self {
+            Self::Nest => 10,
+            Self::Portal => 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DoorState {
+    Open,
+    Closed,
+}
+
+/// What the player is told about an entity when examining it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Description {
+    pub name: String,
+    pub text: String,
+}
+
+impl Description {
+    pub fn new(name: &str, text: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            text: text.to_string(),
+        }
+    }
 }
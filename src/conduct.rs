@@ -0,0 +1,65 @@
+//! Conducts are restrictions a player can choose to keep to for a whole run, like never
+//! killing a monster. Rather than being tracked separately they're worked out from the
+//! journal, so anything which could break one must be recorded there when it happens.
+use crate::{
+    components::Tile,
+    journal::{GameEvent, Journal},
+};
+use serde::{Deserialize, Serialize};
+
+/// Which conducts the player has broken
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Conduct {
+    /// Drank a potion or threw an item
+    pub used_items: bool,
+    pub took_damage: bool,
+    pub killed: bool,
+    pub picked_up: bool,
+}
+
+impl Conduct {
+    pub fn from_journal(journal: &Journal) -> Self {
+        let mut conduct = Self::default();
+        for entry in journal.entries() {
+            match entry.event {
+                GameEvent::Drank {
+                    drinker: Some(Tile::Player),
+                }
+                | GameEvent::Thrown {
+                    thrower: Some(Tile::Player),
+                    ..
+                } => conduct.used_items = true,
+                GameEvent::Attack {
+                    victim: Some(Tile::Player),
+                    damage,
+                    ..
+                } if damage > 0 => conduct.took_damage = true,
+                GameEvent::Died {
+                    killer: Some(Tile::Player),
+                    ..
+                } => conduct.killed = true,
+                GameEvent::PickedUp(_) => conduct.picked_up = true,
+                _ => (),
+            }
+        }
+        conduct
+    }
+
+    /// Descriptions of the conducts which haven't been broken
+    pub fn kept(self) -> Vec<&'static str> {
+        [
+            (self.killed, "Pacifist: never killed a monster"),
+            (self.took_damage, "Unscathed: never took damage"),
+            (
+                self.used_items,
+                "Self-reliant: never drank or threw an item",
+            ),
+            (self.picked_up, "Ascetic: never picked anything up"),
+        ]
+        .into_iter()
+        .filter(|&(broken, _)| !broken)
+        .map(|(_, description)| description)
+        .collect()
+    }
+}
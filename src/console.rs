@@ -0,0 +1,149 @@
+//! A console for poking at the game while debugging it. Entities are referred to by ids which
+//! the console hands out as it lists them, or by tags given to them with the `tag` command,
+//! e.g. `tag 42 boss` followed by `goto @boss`. Tags are kept with the game, so they survive
+//! saving and loading, and other tooling can look entities up by tag too. Each level's world
+//! hands out its own entities, so ids and tags remember the depth they were given on and
+//! only refer to anything there.
+use gridbugs::entity_table::Entity;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const HELP: &str =
+    "commands: ls, tags, tag ID|@TAG NAME, untag NAME, kill ID|@TAG, goto ID|@TAG";
+
+/// An entity as it's written in a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Id(u32),
+    Tag(String),
+}
+
+impl Target {
+    fn parse(s: &str) -> Result<Self, String> {
+        if let Some(tag) = s.strip_prefix('@') {
+            return Ok(Self::Tag(tag.to_string()));
+        }
+        s.parse()
+            .map(Self::Id)
+            .map_err(|_| format!("expected an id or @tag, found \"{}\"", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Lists the characters on the current level with their ids
+    List,
+    /// Lists every tag and what it refers to
+    Tags,
+    Tag {
+        target: Target,
+        name: String,
+    },
+    Untag(String),
+    Kill(Target),
+    /// Moves the player next to the target
+    Goto(Target),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let command = match words.as_slice() {
+            ["ls"] => Self::List,
+            ["tags"] => Self::Tags,
+            ["tag", target, name] => Self::Tag {
+                target: Target::parse(target)?,
+                name: name.trim_start_matches('@').to_string(),
+            },
+            ["untag", name] => Self::Untag(name.trim_start_matches('@').to_string()),
+            ["kill", target] => Self::Kill(Target::parse(target)?),
+            ["goto", target] => Self::Goto(Target::parse(target)?),
+            _ => return Err(HELP.to_string()),
+        };
+        Ok(command)
+    }
+}
+
+/// An entity along with the depth of the level whose world it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelEntity {
+    pub depth: u32,
+    pub entity: Entity,
+}
+
+/// Ids and tags the console has given to entities
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Tags {
+    /// Each entity which has been given an id, indexed by it
+    ids: Vec<LevelEntity>,
+    tags: BTreeMap<String, LevelEntity>,
+}
+
+impl Tags {
+    /// The id of an entity, giving it a new one if it doesn't have one yet
+    pub fn id(&mut self, depth: u32, entity: Entity) -> u32 {
+        let level_entity = LevelEntity { depth, entity };
+        let index = match self.ids.iter().position(|&e| e == level_entity) {
+            Some(index) => index,
+            None => {
+                self.ids.push(level_entity);
+                self.ids.len() - 1
+            }
+        };
+        index as u32
+    }
+
+    pub fn tag(&mut self, name: String, depth: u32, entity: Entity) {
+        self.tags.insert(name, LevelEntity { depth, entity });
+    }
+
+    /// Removes a tag, returning whether there was one with that name
+    pub fn untag(&mut self, name: &str) -> bool {
+        self.tags.remove(name).is_some()
+    }
+
+    /// The entity with the given tag, on whichever level it was tagged
+    pub fn get(&self, name: &str) -> Option<LevelEntity> {
+        self.tags.get(name).cloned()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, LevelEntity)> {
+        self.tags
+            .iter()
+            .map(|(name, &level_entity)| (name.as_str(), level_entity))
+    }
+
+    /// The entity a target refers to, which must be on the level at `depth`
+    pub fn resolve(&self, target: &Target, depth: u32) -> Result<Entity, String> {
+        let level_entity = match target {
+            Target::Id(id) => self
+                .ids
+                .get(*id as usize)
+                .cloned()
+                .ok_or_else(|| format!("no entity has id {} (ls lists ids)", id))?,
+            Target::Tag(name) => self.get(name).ok_or_else(|| format!("no tag @{}", name))?,
+        };
+        if level_entity.depth != depth {
+            return Err(format!("that entity is on depth {}", level_entity.depth));
+        }
+        Ok(level_entity.entity)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gridbugs::entity_table::EntityAllocator;
+
+    #[test]
+    fn targets_only_resolve_on_their_own_level() {
+        let entity = EntityAllocator::default().alloc();
+        let mut tags = Tags::default();
+        let id = tags.id(1, entity);
+        tags.tag("boss".to_string(), 1, entity);
+        for target in [Target::Id(id), Target::Tag("boss".to_string())] {
+            assert_eq!(tags.resolve(&target, 1), Ok(entity));
+            assert!(tags.resolve(&target, 2).is_err());
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use gridbugs::{
+    chargrid::input::{keys, KeyboardInput},
+    direction::Direction,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, str::FromStr};
+
+const CONTROLS_PATH: &str = "controls.toml";
+
+/// Actions the player can take during the game, independent of the keys bound to them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppInput {
+    Move(Direction),
+    Wait,
+    Sprint,
+    PowerAttack,
+    AutoFight,
+    QuickMenu,
+    PickUp,
+    Drop,
+    Quaff,
+    Throw,
+    Fire,
+    Wield,
+    Inventory,
+    CloseDoor,
+    Descend,
+    Ascend,
+    Explore,
+    Travel,
+    Look,
+    Annotate,
+    Discoveries,
+    Bestiary,
+    LevelMap,
+    Options,
+    ToggleChat,
+    Save,
+    Load,
+    BugReport,
+    Console,
+    /// Looks again at whatever was last examined
+    RecallExamine,
+    /// Reopens the last prompt or menu which was closed
+    RecallPrompt,
+    AnimationFaster,
+    AnimationSlower,
+}
+
+impl FromStr for AppInput {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let app_input = match s {
+            "move_north" => Self::Move(Direction::North),
+            "move_east" => Self::Move(Direction::East),
+            "move_south" => Self::Move(Direction::South),
+            "move_west" => Self::Move(Direction::West),
+            "move_north_east" => Self::Move(Direction::NorthEast),
+            "move_south_east" => Self::Move(Direction::SouthEast),
+            "move_south_west" => Self::Move(Direction::SouthWest),
+            "move_north_west" => Self::Move(Direction::NorthWest),
+            "wait" => Self::Wait,
+            "sprint" => Self::Sprint,
+            "power_attack" => Self::PowerAttack,
+            "auto_fight" => Self::AutoFight,
+            "quick_menu" => Self::QuickMenu,
+            "pick_up" => Self::PickUp,
+            "drop" => Self::Drop,
+            "quaff" => Self::Quaff,
+            "throw" => Self::Throw,
+            "fire" => Self::Fire,
+            "wield" => Self::Wield,
+            "inventory" => Self::Inventory,
+            "close_door" => Self::CloseDoor,
+            "descend" => Self::Descend,
+            "ascend" => Self::Ascend,
+            "explore" => Self::Explore,
+            "travel" => Self::Travel,
+            "look" => Self::Look,
+            "annotate" => Self::Annotate,
+            "discoveries" => Self::Discoveries,
+            "bestiary" => Self::Bestiary,
+            "level_map" => Self::LevelMap,
+            "options" => Self::Options,
+            "toggle_chat" => Self::ToggleChat,
+            "save" => Self::Save,
+            "load" => Self::Load,
+            "bug_report" => Self::BugReport,
+            "console" => Self::Console,
+            "recall_examine" => Self::RecallExamine,
+            "recall_prompt" => Self::RecallPrompt,
+            "animation_faster" => Self::AnimationFaster,
+            "animation_slower" => Self::AnimationSlower,
+            other => return Err(format!("unknown action \"{}\"", other)),
+        };
+        Ok(app_input)
+    }
+}
+
+/// Parses a key name from the config file. Single characters stand for themselves, and
+/// other keys are named, e.g. "up", "return" or "f12".
+pub fn parse_key(s: &str) -> Result<KeyboardInput, String> {
+    let mut chars = s.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return Ok(KeyboardInput::Char(ch));
+    }
+    let key = match s {
+        "up" => KeyboardInput::Up,
+        "down" => KeyboardInput::Down,
+        "left" => KeyboardInput::Left,
+        "right" => KeyboardInput::Right,
+        "home" => KeyboardInput::Home,
+        "end" => KeyboardInput::End,
+        "page_up" => KeyboardInput::PageUp,
+        "page_down" => KeyboardInput::PageDown,
+        "return" => keys::RETURN,
+        "space" => KeyboardInput::Char(' '),
+        "delete" => KeyboardInput::Delete,
+        other => match other.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) => KeyboardInput::Function(n),
+            None => return Err(format!("unknown key \"{}\"", other)),
+        },
+    };
+    Ok(key)
+}
+
+/// The name of a key which `parse_key` turns back into the same key
+pub fn key_name(keyboard_input: KeyboardInput) -> String {
+    let name = match keyboard_input {
+        KeyboardInput::Char(ch) => return ch.to_string(),
+        KeyboardInput::Up => "up",
+        KeyboardInput::Down => "down",
+        KeyboardInput::Left => "left",
+        KeyboardInput::Right => "right",
+        KeyboardInput::Home => "home",
+        KeyboardInput::End => "end",
+        KeyboardInput::PageUp => "page_up",
+        KeyboardInput::PageDown => "page_down",
+        KeyboardInput::Delete => "delete",
+        KeyboardInput::Function(n) => return format!("f{}", n),
+    };
+    name.to_string()
+}
+
+#[derive(Deserialize)]
+struct ControlsFile {
+    keys: HashMap<String, String>,
+}
+
+/// Maps keys to the actions they perform during the game
+pub struct Controls {
+    keys: HashMap<KeyboardInput, AppInput>,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        use AppInput::*;
+        use Direction::*;
+        use KeyboardInput::Char;
+        let keys = [
+            (KeyboardInput::Up, Move(North)),
+            (KeyboardInput::Right, Move(East)),
+            (KeyboardInput::Down, Move(South)),
+            (KeyboardInput::Left, Move(West)),
+            (Char('k'), Move(North)),
+            (Char('l'), Move(East)),
+            (Char('j'), Move(South)),
+            (Char('h'), Move(West)),
+            (Char('u'), Move(NorthEast)),
+            (Char('n'), Move(SouthEast)),
+            (Char('b'), Move(SouthWest)),
+            (Char('y'), Move(NorthWest)),
+            (Char('8'), Move(North)),
+            (Char('6'), Move(East)),
+            (Char('2'), Move(South)),
+            (Char('4'), Move(West)),
+            (Char('9'), Move(NorthEast)),
+            (Char('3'), Move(SouthEast)),
+            (Char('1'), Move(SouthWest)),
+            (Char('7'), Move(NorthWest)),
+            (Char('5'), Wait),
+            (Char('.'), Wait),
+            (Char('s'), Sprint),
+            (Char('a'), PowerAttack),
+            (Char('F'), AutoFight),
+            (Char('Q'), QuickMenu),
+            (Char('g'), PickUp),
+            (Char('d'), Drop),
+            (Char('q'), Quaff),
+            (Char('t'), Throw),
+            (Char('f'), Fire),
+            (Char('w'), Wield),
+            (Char('i'), Inventory),
+            (Char('c'), CloseDoor),
+            (Char('>'), Descend),
+            (Char('<'), Ascend),
+            (Char('z'), Explore),
+            (Char('T'), Travel),
+            (Char('x'), Look),
+            (Char('N'), Annotate),
+            (Char('D'), Discoveries),
+            (Char('B'), Bestiary),
+            (Char('M'), LevelMap),
+            (Char('o'), Options),
+            (Char('C'), ToggleChat),
+            (Char('S'), Save),
+            (Char('L'), Load),
+            (KeyboardInput::Function(12), BugReport),
+            (Char('`'), Console),
+            (Char('X'), RecallExamine),
+            (Char('P'), RecallPrompt),
+            (Char('+'), AnimationFaster),
+            (Char('='), AnimationFaster),
+            (Char('-'), AnimationSlower),
+        ]
+        .into_iter()
+        .collect();
+        Self { keys }
+    }
+}
+
+impl Controls {
+    /// The default controls, with any bindings from the config file added on top. Problems
+    /// with the config file are reported and the offending bindings are ignored.
+    pub fn load() -> Self {
+        let mut controls = Self::default();
+        let contents = match fs::read_to_string(CONTROLS_PATH) {
+            Ok(contents) => contents,
+            Err(_) => return controls,
+        };
+        let file = match toml::from_str::<ControlsFile>(&contents) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("failed to parse {}: {}", CONTROLS_PATH, e);
+                return controls;
+            }
+        };
+        for (key, app_input) in file.keys {
+            match (parse_key(&key), app_input.parse::<AppInput>()) {
+                (Ok(key), Ok(app_input)) => {
+                    controls.keys.insert(key, app_input);
+                }
+                (Err(e), _) | (_, Err(e)) => eprintln!("{}: {}", CONTROLS_PATH, e),
+            }
+        }
+        controls
+    }
+
+    pub fn get(&self, keyboard_input: KeyboardInput) -> Option<AppInput> {
+        self.keys.get(&keyboard_input).cloned()
+    }
+}
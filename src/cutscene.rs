@@ -0,0 +1,136 @@
+//! Sequences of pages shown at the start of a run and when it ends. The pages are loaded from
+//! a data file, and each page's text is typed out a character at a time.
+use crate::settings::AnimationSpeed;
+use serde::Deserialize;
+use std::time::Duration;
+
+const CUTSCENES: &str = include_str!("./cutscenes.toml");
+
+/// Time taken to type each character at normal animation speed
+const CHAR_DURATION: Duration = Duration::from_millis(25);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Page {
+    #[serde(default)]
+    art: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+pub struct Cutscenes {
+    #[serde(default)]
+    intro: Vec<Page>,
+    #[serde(default)]
+    victory: Vec<Page>,
+    #[serde(default)]
+    defeat: Vec<Page>,
+}
+
+impl Default for Cutscenes {
+    fn default() -> Self {
+        toml::from_str(CUTSCENES).expect("invalid cutscenes")
+    }
+}
+
+impl Cutscenes {
+    /// Shown when a new run starts
+    pub fn intro(&self) -> Option<Sequence> {
+        Sequence::new(self.intro.clone())
+    }
+
+    /// Shown when a run ends
+    pub fn ending(&self, won: bool) -> Option<Sequence> {
+        Sequence::new(if won {
+            self.victory.clone()
+        } else {
+            self.defeat.clone()
+        })
+    }
+}
+
+/// Pages shown one after another, typing out the text of each
+pub struct Sequence {
+    pages: Vec<Page>,
+    page: usize,
+    /// Number of characters of the current page's text which have been typed
+    typed: usize,
+    elapsed: Duration,
+}
+
+impl Sequence {
+    /// Returns `None` if there are no pages
+    fn new(pages: Vec<Page>) -> Option<Self> {
+        if pages.is_empty() {
+            return None;
+        }
+        Some(Self {
+            pages,
+            page: 0,
+            typed: 0,
+            elapsed: Duration::ZERO,
+        })
+    }
+
+    fn text_len(&self) -> usize {
+        self.pages[self.page].text.chars().count()
+    }
+
+    /// Types more of the current page's text
+    pub fn tick(&mut self, since_last_tick: Duration, animation_speed: AnimationSpeed) {
+        let char_duration = match animation_speed.scale(CHAR_DURATION) {
+            Some(char_duration) => char_duration,
+            None => {
+                self.typed = self.text_len();
+                return;
+            }
+        };
+        self.elapsed += since_last_tick;
+        let typed = (self.elapsed.as_nanos() / char_duration.as_nanos().max(1)) as usize;
+        self.typed = typed.min(self.text_len());
+    }
+
+    /// Finishes typing the current page if it's still being typed, and otherwise turns to the
+    /// next one. Returns false if there are no more pages.
+    pub fn advance(&mut self) -> bool {
+        if self.typed < self.text_len() {
+            self.typed = self.text_len();
+            return true;
+        }
+        if self.page + 1 >= self.pages.len() {
+            return false;
+        }
+        self.page += 1;
+        self.typed = 0;
+        self.elapsed = Duration::ZERO;
+        true
+    }
+
+    /// The lines of the current page, as much of its text as has been typed, and which page
+    /// this is
+    pub fn lines(&self) -> Vec<String> {
+        let page = &self.pages[self.page];
+        let mut lines = page
+            .art
+            .lines()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>();
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        let typed = page.text.chars().take(self.typed).collect::<String>();
+        let mut text = typed
+            .lines()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>();
+        // keep the lines below the text still while it's typed
+        text.resize(page.text.lines().count(), String::new());
+        lines.extend(text);
+        lines.push(String::new());
+        lines.push(format!(
+            "({}/{}) enter: continue, escape: skip",
+            self.page + 1,
+            self.pages.len()
+        ));
+        lines
+    }
+}
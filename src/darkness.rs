@@ -0,0 +1,79 @@
+//! Hazards a level's map can set with a line such as `; hazard: darkness`. Darkness slowly
+//! puts out the lights on a level and sends shades out of whichever cells are left unlit, so
+//! the player has to keep braziers burning to keep the level safe.
+use crate::{visibility, world::World};
+use gridbugs::coord_2d::Coord;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Turns between the darkness dimming a light
+const DIM_INTERVAL: u32 = 8;
+/// Turns between shades emerging
+const SPAWN_INTERVAL: u32 = 15;
+/// How much of its brightness, out of 255, a light keeps each time it's dimmed
+pub const DIM_BRIGHTNESS: u8 = 191;
+/// Cells with less light than this, summed over the channels, count as unlit
+const UNLIT_CHANNEL_TOTAL: u32 = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Hazard {
+    Darkness,
+}
+
+impl FromStr for Hazard {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "darkness" => Ok(Self::Darkness),
+            other => Err(format!("unknown hazard \"{}\" (expected darkness)", other)),
+        }
+    }
+}
+
+/// What the darkness does on a turn
+pub struct DarknessTurn {
+    pub dim: bool,
+    pub spawn: bool,
+}
+
+/// Counts down to the darkness's next moves on a level
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Darkness {
+    until_dim: u32,
+    until_spawn: u32,
+}
+
+impl Default for Darkness {
+    fn default() -> Self {
+        Self {
+            until_dim: DIM_INTERVAL,
+            until_spawn: SPAWN_INTERVAL,
+        }
+    }
+}
+
+impl Darkness {
+    /// Called once per turn
+    pub fn turn(&mut self) -> DarknessTurn {
+        fn count_down(countdown: &mut u32, interval: u32) -> bool {
+            *countdown = countdown.saturating_sub(1);
+            if *countdown > 0 {
+                return false;
+            }
+            *countdown = interval;
+            true
+        }
+        DarknessTurn {
+            dim: count_down(&mut self.until_dim, DIM_INTERVAL),
+            spawn: count_down(&mut self.until_spawn, SPAWN_INTERVAL),
+        }
+    }
+}
+
+/// Whether too little light reaches a cell to keep the darkness out of it. Cells off the map
+/// don't count as unlit, as nothing can happen in them.
+pub fn is_unlit(world: &World, coord: Coord) -> bool {
+    visibility::light_level(world, coord)
+        .map(|light| u32::from(light.saturating_channel_total()) < UNLIT_CHANNEL_TOTAL)
+        .unwrap_or(false)
+}
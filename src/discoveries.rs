@@ -0,0 +1,51 @@
+use crate::{
+    components::Tile,
+    journal::{GameEvent, Journal},
+};
+use std::collections::BTreeMap;
+
+/// Item types found and monster species killed by the player this run, with counts
+pub struct Discoveries {
+    pub items: BTreeMap<&'static str, u32>,
+    pub kills: BTreeMap<&'static str, u32>,
+}
+
+impl Discoveries {
+    pub fn from_journal(journal: &Journal) -> Self {
+        let mut items = BTreeMap::new();
+        let mut kills = BTreeMap::new();
+        for entry in journal.entries() {
+            match entry.event {
+                GameEvent::PickedUp(item) => *items.entry(item.name()).or_insert(0) += 1,
+                GameEvent::Died {
+                    victim: Some(victim),
+                    killer: Some(Tile::Player),
+                } => *kills.entry(victim.name()).or_insert(0) += 1,
+                _ => (),
+            }
+        }
+        Self { items, kills }
+    }
+
+    /// Lines describing each discovery whose name contains the query, ignoring case
+    pub fn lines(&self, query: &str) -> Vec<String> {
+        let query = query.to_lowercase();
+        let matches = |name: &str| name.to_lowercase().contains(&query);
+        let mut lines = vec!["Items found:".to_string()];
+        lines.extend(
+            self.items
+                .iter()
+                .filter(|(name, _)| matches(name))
+                .map(|(name, count)| format!("  {} x{}", name, count)),
+        );
+        lines.push(String::new());
+        lines.push("Monsters killed:".to_string());
+        lines.extend(
+            self.kills
+                .iter()
+                .filter(|(name, _)| matches(name))
+                .map(|(name, count)| format!("  {} x{}", name, count)),
+        );
+        lines
+    }
+}
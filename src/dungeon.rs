@@ -0,0 +1,124 @@
+use crate::{
+    darkness::{Darkness, Hazard},
+    feeling::LevelFeeling,
+    objective::Objective,
+    travel::Exclusions,
+    visibility::VisibilityGrid,
+    world::World,
+};
+use gridbugs::{coord_2d::Coord, entity_table::ComponentTable};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Everything specific to a single level of the dungeon
+#[derive(Serialize, Deserialize)]
+pub struct Level {
+    pub world: World,
+    pub visibility_grid: VisibilityGrid,
+    pub annotations: HashMap<Coord, String>,
+    /// Regions marked by the player for travel to avoid
+    pub travel_exclusions: Exclusions,
+    /// NPCs the player has seen at least once
+    pub encountered_npcs: ComponentTable<()>,
+    pub feeling: LevelFeeling,
+    /// Whether the player has been here before
+    pub visited: bool,
+    /// A puzzle to solve, set by the level's map
+    pub objective: Option<Objective>,
+    /// Whether the objective has been met
+    pub solved: bool,
+    /// Set if darkness is creeping over the level
+    pub darkness: Option<Darkness>,
+}
+
+impl Level {
+    pub fn new(world: World, depth: u32) -> Self {
+        let visibility_grid = VisibilityGrid::new(world.size());
+        let feeling = LevelFeeling::analyse(&world, depth);
+        Self {
+            world,
+            visibility_grid,
+            annotations: HashMap::new(),
+            travel_exclusions: Exclusions::default(),
+            encountered_npcs: ComponentTable::default(),
+            feeling,
+            visited: false,
+            objective: None,
+            solved: false,
+            darkness: None,
+        }
+    }
+
+    /// Sets up a hazard named by the level's map
+    pub fn set_hazard(&mut self, hazard: Hazard) {
+        match hazard {
+            Hazard::Darkness => self.darkness = Some(Darkness::default()),
+        }
+    }
+}
+
+/// Holds every level of the dungeon other than the one the player is currently on. The
+/// current level is owned by the game while it is being played, and is swapped back in here
+/// when the player changes level.
+#[derive(Serialize, Deserialize)]
+pub struct Dungeon {
+    levels: Vec<Option<Level>>,
+    current_index: usize,
+}
+
+impl Dungeon {
+    /// Creates a dungeon from all its levels, returning it along with the first level
+    pub fn new(levels: Vec<Level>) -> (Self, Level) {
+        let mut levels = levels.into_iter().map(Some).collect::<Vec<_>>();
+        let first = levels[0].take().expect("dungeon has no levels");
+        (
+            Self {
+                levels,
+                current_index: 0,
+            },
+            first,
+        )
+    }
+
+    /// 1-indexed depth of the current level
+    pub fn depth(&self) -> u32 {
+        self.current_index as u32 + 1
+    }
+
+    pub fn has_level_below(&self) -> bool {
+        self.current_index + 1 < self.levels.len()
+    }
+
+    pub fn has_level_above(&self) -> bool {
+        self.current_index > 0 && self.levels[self.current_index - 1].is_some()
+    }
+
+    /// Adds a new level below the deepest one
+    pub fn push(&mut self, level: Level) {
+        self.levels.push(Some(level));
+    }
+
+    /// Forgets levels more than `keep` levels above the current one, so endless runs don't
+    /// hold on to every level they pass through
+    pub fn discard_levels_above(&mut self, keep: usize) {
+        let end = self.current_index.saturating_sub(keep);
+        for level in &mut self.levels[..end] {
+            *level = None;
+        }
+    }
+
+    /// Stores the current level and replaces it with the level at the given index
+    fn switch(&mut self, current: &mut Level, index: usize) {
+        let next = self.levels[index].take().expect("level is already in use");
+        self.levels[self.current_index] = Some(std::mem::replace(current, next));
+        self.current_index = index;
+    }
+
+    pub fn descend(&mut self, current: &mut Level) {
+        self.switch(current, self.current_index + 1);
+    }
+
+    pub fn ascend(&mut self, current: &mut Level) {
+        self.switch(current, self.current_index - 1);
+    }
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+/// Something that happens to the game from outside of the normal turn structure, such as
+/// an event triggered by chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Effect {
+    SpawnMonster,
+    Heal,
+    GiveItem,
+    FreezeEnemy,
+    ExtraTurn,
+    Confuse,
+    CharmEnemy,
+}
+
+impl Effect {
+    pub const ALL: &'static [Self] = &[
+        Self::SpawnMonster,
+        Self::Heal,
+        Self::GiveItem,
+        Self::FreezeEnemy,
+        Self::ExtraTurn,
+        Self::Confuse,
+        Self::CharmEnemy,
+    ];
+
+    /// The chat command which votes for this effect
+    pub fn command(self) -> &'static str {
+        match self {
+            Self::SpawnMonster => "!spawn",
+            Self::Heal => "!heal",
+            Self::GiveItem => "!gift",
+            Self::FreezeEnemy => "!freeze",
+            Self::ExtraTurn => "!haste",
+            Self::Confuse => "!confuse",
+            Self::CharmEnemy => "!charm",
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{components::Tile, world::World};
+use serde::{Deserialize, Serialize};
+
+/// Levels with at least this many items lying around feel rewarding
+const TREASURE_ITEM_COUNT: usize = 2;
+/// Threat expected per level of depth
+const THREAT_PER_DEPTH: u32 = 4;
+
+/// The shallowest depth a monster normally appears at
+fn native_depth(tile: Tile) -> u32 {
+    match tile {
+        Tile::Orc | Tile::Portal => 3,
+        _ => 1,
+    }
+}
+
+/// How much a monster or spawner adds to the danger of a level
+pub fn threat(tile: Tile) -> u32 {
+    match tile {
+        Tile::Goblin | Tile::Shade => 1,
+        Tile::Orc => 3,
+        Tile::Nest => 4,
+        Tile::Portal => 6,
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Danger {
+    Quiet,
+    Uneasy,
+    Dangerous,
+    Deadly,
+}
+
+/// An impression of a level, reported to the player when they first arrive
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LevelFeeling {
+    pub danger: Danger,
+    /// Whether the level contains monsters which don't normally appear this shallow
+    pub out_of_depth: bool,
+    pub treasure: bool,
+}
+
+impl LevelFeeling {
+    /// Analyses the contents of a freshly generated level
+    pub fn analyse(world: &World, depth: u32) -> Self {
+        let threats = world
+            .all_npcs()
+            .map(|(entity, _)| entity)
+            .chain(world.all_spawners().map(|(entity, _)| entity))
+            .filter_map(|entity| world.components.tile.get(entity).cloned())
+            .collect::<Vec<_>>();
+        let total_threat = threats.iter().map(|&tile| threat(tile)).sum::<u32>();
+        let out_of_depth = threats.iter().any(|&tile| native_depth(tile) > depth);
+        let expected_threat = depth * THREAT_PER_DEPTH;
+        let danger = if total_threat * 2 < expected_threat {
+            Danger::Quiet
+        } else if total_threat < expected_threat {
+            Danger::Uneasy
+        } else if total_threat < expected_threat * 2 {
+            Danger::Dangerous
+        } else {
+            Danger::Deadly
+        };
+        let floor_items = world
+            .components
+            .item
+            .iter()
+            .filter(|&(entity, _)| world.entity_coord(entity).is_some())
+            .count();
+        Self {
+            danger,
+            out_of_depth,
+            treasure: floor_items >= TREASURE_ITEM_COUNT,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        let mut message = match self.danger {
+            Danger::Quiet => "This place seems quiet.",
+            Danger::Uneasy => "You feel uneasy.",
+            Danger::Dangerous => "You sense danger here.",
+            Danger::Deadly => "This place reeks of death.",
+        }
+        .to_string();
+        if self.out_of_depth {
+            message.push_str(" Something here doesn't belong this shallow.");
+        }
+        if self.treasure {
+            message.push_str(" You catch a glint of something valuable.");
+        }
+        message
+    }
+}
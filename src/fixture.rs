@@ -0,0 +1,229 @@
+//! Small hand-made scenarios which pin down how the game behaves in cases which are easy to
+//! break, like a light in the corner of a room or a crowded doorway. A fixture is a map in the
+//! same format as the levels, with steps interleaved on lines starting with `>`. Steps are
+//! either actions the player takes or checks of the game's state, and run in order on a game
+//! started from the map with a fixed seed, so combat plays out the same way every time.
+//!
+//! ```text
+//! > walk east
+//! > at 3 1
+//! > visible 5 1
+//! > hp 4 1 2
+//! ```
+use crate::{
+    ai::AiDifficulty,
+    arena::ArenaConfig,
+    components::DoorState,
+    darkness,
+    mutator::Mutators,
+    ruleset::{Ruleset, RunMode},
+    terrain::Maps,
+    Game, Setup,
+};
+use gridbugs::{coord_2d::Coord, direction::Direction};
+use std::{fs, io, path::Path};
+
+const FIXTURE_SEED: u64 = 0;
+
+fn parse_direction(s: &str) -> Result<Direction, String> {
+    let direction = match s {
+        "north" => Direction::North,
+        "east" => Direction::East,
+        "south" => Direction::South,
+        "west" => Direction::West,
+        "north_east" => Direction::NorthEast,
+        "south_east" => Direction::SouthEast,
+        "south_west" => Direction::SouthWest,
+        "north_west" => Direction::NorthWest,
+        other => return Err(format!("unknown direction \"{}\"", other)),
+    };
+    Ok(direction)
+}
+
+fn parse_coord(x: &str, y: &str) -> Result<Coord, String> {
+    let parse = |s: &str| {
+        s.parse::<i32>()
+            .map_err(|_| format!("expected a number, found \"{}\"", s))
+    };
+    Ok(Coord::new(parse(x)?, parse(y)?))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Walk(Direction),
+    Wait,
+    /// The player is at the coord
+    At(Coord),
+    Visible(Coord),
+    Hidden(Coord),
+    Lit(Coord),
+    Unlit(Coord),
+    /// Some character is at the coord
+    Occupied(Coord),
+    Empty(Coord),
+    DoorOpen(Coord),
+    DoorClosed(Coord),
+    /// The character at the coord has this many hit points left
+    HitPoints(Coord, u32),
+    /// The player has been killed
+    Dead,
+}
+
+impl Step {
+    fn parse(line: &str) -> Result<Self, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let step = match words.as_slice() {
+            ["walk", direction] => Self::Walk(parse_direction(direction)?),
+            ["wait"] => Self::Wait,
+            ["dead"] => Self::Dead,
+            ["hp", x, y, hit_points] => {
+                let hit_points = hit_points
+                    .parse()
+                    .map_err(|_| format!("expected a number, found \"{}\"", hit_points))?;
+                Self::HitPoints(parse_coord(x, y)?, hit_points)
+            }
+            [check, x, y] => {
+                let coord = parse_coord(x, y)?;
+                match *check {
+                    "at" => Self::At(coord),
+                    "visible" => Self::Visible(coord),
+                    "hidden" => Self::Hidden(coord),
+                    "lit" => Self::Lit(coord),
+                    "unlit" => Self::Unlit(coord),
+                    "occupied" => Self::Occupied(coord),
+                    "empty" => Self::Empty(coord),
+                    "door_open" => Self::DoorOpen(coord),
+                    "door_closed" => Self::DoorClosed(coord),
+                    other => return Err(format!("unknown check \"{}\"", other)),
+                }
+            }
+            _ => return Err(format!("can't parse step \"{}\"", line)),
+        };
+        Ok(step)
+    }
+
+    /// Takes the action or makes the check, returning whether a check passed
+    fn run(self, game: &mut Game) -> bool {
+        let world = &game.level.world;
+        match self {
+            Self::Walk(direction) => {
+                game.player_walk(direction);
+                true
+            }
+            Self::Wait => {
+                game.player_wait();
+                true
+            }
+            Self::At(coord) => game.player_coord() == Some(coord),
+            Self::Visible(coord) => game.visibility_grid().is_currently_visible(coord),
+            Self::Hidden(coord) => !game.visibility_grid().is_currently_visible(coord),
+            Self::Lit(coord) => world.contains_coord(coord) && !darkness::is_unlit(world, coord),
+            Self::Unlit(coord) => darkness::is_unlit(world, coord),
+            Self::Occupied(coord) => world.is_character_at_coord(coord),
+            Self::Empty(coord) => !world.is_character_at_coord(coord),
+            Self::DoorOpen(coord) => world.door_at_coord(coord, DoorState::Open).is_some(),
+            Self::DoorClosed(coord) => world.door_at_coord(coord, DoorState::Closed).is_some(),
+            Self::HitPoints(coord, hit_points) => world
+                .character_at_coord(coord)
+                .and_then(|entity| world.components.hit_points.get(entity))
+                .map(|current| current.current == hit_points)
+                .unwrap_or(false),
+            Self::Dead => game.player_coord().is_none(),
+        }
+    }
+}
+
+/// Starts a game on a map with the fixtures' fixed seed
+pub fn new_game(map: &str) -> Result<Game, String> {
+    let maps = Maps::single(map.to_string()).map_err(|e| e.to_string())?;
+    let ruleset = Ruleset {
+        ai_difficulty: AiDifficulty::Normal,
+        seed: Some(FIXTURE_SEED),
+        mode: RunMode::Dungeon,
+        mutators: Mutators::default(),
+    };
+    let setup = Setup {
+        maps,
+        arena: ArenaConfig::default(),
+    };
+    Ok(Game::new(ruleset, &setup))
+}
+
+/// A fixture's map and the steps to run on it
+struct Fixture {
+    map: String,
+    /// Each step with the line it came from
+    steps: Vec<(usize, Step)>,
+}
+
+impl Fixture {
+    fn parse(s: &str) -> Result<Self, String> {
+        let mut map = String::new();
+        let mut steps = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            match line.strip_prefix('>') {
+                Some(step) => steps.push((i + 1, Step::parse(step.trim())?)),
+                None => {
+                    map.push_str(line);
+                    map.push('\n');
+                }
+            }
+        }
+        Ok(Self { map, steps })
+    }
+
+    /// Runs the steps in order, stopping at the first failed check
+    fn run(&self) -> Result<(), String> {
+        let mut game = new_game(&self.map)?;
+        for &(line, step) in &self.steps {
+            if !step.run(&mut game) {
+                return Err(format!("line {}: {:?} failed", line, step));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs every fixture in a directory and prints the results, returning whether they all
+/// passed
+pub fn check_all(dir: &Path) -> io::Result<bool> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+    let mut failures = 0;
+    for path in paths {
+        let result = Fixture::parse(&fs::read_to_string(&path)?).and_then(|f| f.run());
+        match result {
+            Ok(()) => println!("ok   {}", path.display()),
+            Err(e) => {
+                println!("FAIL {}: {}", path.display(), e);
+                failures += 1;
+            }
+        }
+    }
+    println!("{} failed", failures);
+    Ok(failures == 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fixtures_pass() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+        let mut paths = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let fixture = Fixture::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+            if let Err(e) = fixture.run() {
+                panic!("{}: {}", path.display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Algorithms for deciding which cells can be seen from a point. The player's view and every
+//! light are computed with the same algorithm.
+use crate::{visibility::Visibility, world::World};
+use gridbugs::{
+    coord_2d::Coord,
+    direction::Direction,
+    shadowcast::{
+        vision_distance::Circle, Context as ShadowcastContext, DirectionBitmap, VisionDistance,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FovAlgorithm {
+    #[default]
+    Shadowcast,
+    Permissive,
+    Digital,
+    Symmetric,
+}
+
+impl FovAlgorithm {
+    pub const ALL: &'static [Self] = &[
+        Self::Shadowcast,
+        Self::Permissive,
+        Self::Digital,
+        Self::Symmetric,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Shadowcast => "shadowcast",
+            Self::Permissive => "permissive",
+            Self::Digital => "digital",
+            Self::Symmetric => "symmetric",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&a| a == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    fn strategy(self) -> Box<dyn FovStrategy> {
+        match self {
+            Self::Shadowcast => Box::new(ShadowcastFov::default()),
+            Self::Permissive => Box::new(PermissiveFov),
+            Self::Digital => Box::new(DigitalFov),
+            Self::Symmetric => Box::new(SymmetricFov),
+        }
+    }
+}
+
+pub trait FovStrategy {
+    /// Calls `f` with each cell visible from `eye`, the directions from which it's visible,
+    /// and how visible it is from 0 to 255
+    fn for_each_visible(
+        &mut self,
+        eye: Coord,
+        world: &World,
+        vision_distance: Circle,
+        f: &mut dyn FnMut(Coord, DirectionBitmap, u8),
+    );
+}
+
+/// The chosen algorithm along with any state it keeps between updates
+pub struct Fov {
+    algorithm: FovAlgorithm,
+    strategy: Box<dyn FovStrategy>,
+}
+
+impl Default for Fov {
+    fn default() -> Self {
+        Self::new(FovAlgorithm::default())
+    }
+}
+
+impl Fov {
+    pub fn new(algorithm: FovAlgorithm) -> Self {
+        Self {
+            algorithm,
+            strategy: algorithm.strategy(),
+        }
+    }
+
+    pub fn algorithm(&self) -> FovAlgorithm {
+        self.algorithm
+    }
+
+    pub fn strategy_mut(&mut self) -> &mut dyn FovStrategy {
+        self.strategy.as_mut()
+    }
+}
+
+/// Recursive shadowcasting. Cells are visible if any part of them is visible from the centre
+/// of the eye's cell.
+#[derive(Default)]
+pub struct ShadowcastFov {
+    context: ShadowcastContext<u8>,
+}
+
+impl FovStrategy for ShadowcastFov {
+    fn for_each_visible(
+        &mut self,
+        eye: Coord,
+        world: &World,
+        vision_distance: Circle,
+        f: &mut dyn FnMut(Coord, DirectionBitmap, u8),
+    ) {
+        self.context
+            .for_each_visible(eye, &Visibility, world, vision_distance, 255, f);
+    }
+}
+
+/// How visible the point at `offset` from the centre of the cell at `to` is from the centre
+/// of the cell at `from`, reduced by the opacity of each cell the line passes through
+fn line_visibility(world: &World, from: Coord, to: Coord, offset: (f64, f64)) -> u8 {
+    let dx = to.x as f64 + offset.0 - from.x as f64;
+    let dy = to.y as f64 + offset.1 - from.y as f64;
+    let steps = (dx.abs().max(dy.abs()) * 2.).ceil() as i32;
+    let mut visibility = 255u8;
+    let mut previous = from;
+    for i in 1..steps {
+        let t = i as f64 / steps as f64;
+        let coord = Coord::new(
+            (from.x as f64 + dx * t).round() as i32,
+            (from.y as f64 + dy * t).round() as i32,
+        );
+        if coord == previous || coord == to {
+            continue;
+        }
+        previous = coord;
+        visibility = visibility.saturating_sub(world.get_opacity_at_coord(coord));
+        if visibility == 0 {
+            break;
+        }
+    }
+    visibility
+}
+
+/// The sides of a cell which can be seen from `eye`. Every side of a cell which lets light
+/// through can be seen, but only the sides of an opaque cell which face the eye and aren't
+/// against another opaque cell, so walls aren't lit from behind. If none of those sides can
+/// be seen then the corner facing the eye can be, as with shadowcasting.
+fn visible_faces(world: &World, eye: Coord, coord: Coord) -> DirectionBitmap {
+    if world.get_opacity_at_coord(coord) == 0 {
+        return DirectionBitmap::all();
+    }
+    let delta = eye - coord;
+    let facing = [
+        (Direction::North, delta.y < 0),
+        (Direction::East, delta.x > 0),
+        (Direction::South, delta.y > 0),
+        (Direction::West, delta.x < 0),
+    ];
+    let mut faces = DirectionBitmap::empty();
+    for (direction, faces_eye) in facing {
+        if faces_eye && world.get_opacity_at_coord(coord + direction.coord()) == 0 {
+            faces |= direction.bitmap();
+        }
+    }
+    if faces.is_empty() {
+        let corner = match (delta.x.signum(), delta.y.signum()) {
+            (1, -1) => Some(Direction::NorthEast),
+            (1, 1) => Some(Direction::SouthEast),
+            (-1, 1) => Some(Direction::SouthWest),
+            (-1, -1) => Some(Direction::NorthWest),
+            _ => None,
+        };
+        if let Some(corner) = corner {
+            faces |= corner.bitmap();
+        }
+    }
+    faces
+}
+
+/// Calls `f` with each cell in range of `eye` which `visibility` says is visible. Only the
+/// square around the eye which bounds its range is searched.
+fn for_each_in_range(
+    eye: Coord,
+    world: &World,
+    vision_distance: Circle,
+    visibility: impl Fn(Coord) -> u8,
+    f: &mut dyn FnMut(Coord, DirectionBitmap, u8),
+) {
+    let radius = (vision_distance.distance_squared() as f64).sqrt().ceil() as i32;
+    let size = world.size();
+    for y in (eye.y - radius).max(0)..=(eye.y + radius).min(size.height() as i32 - 1) {
+        for x in (eye.x - radius).max(0)..=(eye.x + radius).min(size.width() as i32 - 1) {
+            let coord = Coord::new(x, y);
+            if coord == eye {
+                f(coord, DirectionBitmap::all(), 255);
+                continue;
+            }
+            if !vision_distance.in_range(coord - eye) {
+                continue;
+            }
+            let visibility = visibility(coord);
+            if visibility > 0 {
+                f(coord, visible_faces(world, eye, coord), visibility);
+            }
+        }
+    }
+}
+
+/// Cells are visible if there's an unobstructed line from the centre of the eye's cell to
+/// any point near the edges of the cell. More generous than shadowcasting around corners.
+pub struct PermissiveFov;
+
+const PERMISSIVE_OFFSETS: &[(f64, f64)] = &[
+    (0., 0.),
+    (-0.45, -0.45),
+    (0.45, -0.45),
+    (-0.45, 0.45),
+    (0.45, 0.45),
+];
+
+impl FovStrategy for PermissiveFov {
+    fn for_each_visible(
+        &mut self,
+        eye: Coord,
+        world: &World,
+        vision_distance: Circle,
+        f: &mut dyn FnMut(Coord, DirectionBitmap, u8),
+    ) {
+        let visibility = |coord| {
+            PERMISSIVE_OFFSETS
+                .iter()
+                .map(|&offset| line_visibility(world, eye, coord, offset))
+                .max()
+                .unwrap_or(0)
+        };
+        for_each_in_range(eye, world, vision_distance, visibility, f);
+    }
+}
+
+/// Cells are visible if the digital line between the centres of the two cells, traced in
+/// either direction, is unobstructed
+pub struct DigitalFov;
+
+impl FovStrategy for DigitalFov {
+    fn for_each_visible(
+        &mut self,
+        eye: Coord,
+        world: &World,
+        vision_distance: Circle,
+        f: &mut dyn FnMut(Coord, DirectionBitmap, u8),
+    ) {
+        let visibility = |coord| {
+            line_visibility(world, eye, coord, (0., 0.)).max(line_visibility(
+                world,
+                coord,
+                eye,
+                (0., 0.),
+            ))
+        };
+        for_each_in_range(eye, world, vision_distance, visibility, f);
+    }
+}
+
+/// Cells are visible only if the lines between the centres of the two cells are unobstructed
+/// in both directions. Visibility doesn't depend on which cell is the eye, so if the player
+/// can see a monster then the monster can see the player.
+pub struct SymmetricFov;
+
+/// How visible each of two cells is from the other, which is the same in both directions
+fn symmetric_visibility(world: &World, a: Coord, b: Coord) -> u8 {
+    line_visibility(world, a, b, (0., 0.)).min(line_visibility(world, b, a, (0., 0.)))
+}
+
+impl FovStrategy for SymmetricFov {
+    fn for_each_visible(
+        &mut self,
+        eye: Coord,
+        world: &World,
+        vision_distance: Circle,
+        f: &mut dyn FnMut(Coord, DirectionBitmap, u8),
+    ) {
+        let visibility = |coord| symmetric_visibility(world, eye, coord);
+        for_each_in_range(eye, world, vision_distance, visibility, f);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cave, visibility::VISION_DISTANCE};
+    use rand::SeedableRng;
+    use rand_isaac::Isaac64Rng;
+    use std::collections::HashMap;
+
+    /// How visible each cell is from `eye`
+    fn visible_from(eye: Coord, world: &World) -> HashMap<Coord, u8> {
+        let mut visible = HashMap::new();
+        SymmetricFov.for_each_visible(eye, world, VISION_DISTANCE, &mut |coord, _, visibility| {
+            visible.insert(coord, visibility);
+        });
+        visible
+    }
+
+    #[test]
+    fn symmetric_fov_is_symmetric() {
+        for seed in 0..4 {
+            let mut rng = Isaac64Rng::seed_from_u64(seed);
+            let world = cave::generate(1, &mut rng).world;
+            let eyes = world
+                .size()
+                .coord_iter_row_major()
+                .filter(|&coord| world.get_opacity_at_coord(coord) == 0)
+                .step_by(5)
+                .collect::<Vec<_>>();
+            let visible = eyes
+                .iter()
+                .map(|&eye| (eye, visible_from(eye, &world)))
+                .collect::<HashMap<_, _>>();
+            for &a in &eyes {
+                for &b in &eyes {
+                    assert_eq!(
+                        visible[&a].get(&b),
+                        visible[&b].get(&a),
+                        "seed {}: {:?} and {:?} see each other differently",
+                        seed,
+                        a,
+                        b,
+                    );
+                }
+            }
+        }
+    }
+}
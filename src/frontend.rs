@@ -0,0 +1,300 @@
+use gridbugs::chargrid::control_flow::App;
+
+#[cfg(not(any(feature = "wgpu", feature = "terminal")))]
+compile_error!("at least one of the \"wgpu\" and \"terminal\" features must be enabled");
+
+/// The ways the game can be displayed. Each is only available if the crate was built with
+/// its feature.
+pub enum Frontend {
+    Wgpu,
+    AnsiTerminal,
+}
+
+/// Building with the terminal feature implies the terminal is wanted, even though the wgpu
+/// feature is on by default
+impl Default for Frontend {
+    fn default() -> Self {
+        if cfg!(feature = "terminal") {
+            Self::AnsiTerminal
+        } else {
+            Self::Wgpu
+        }
+    }
+}
+
+/// Which graphics adapter the wgpu frontend renders with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adapter {
+    /// The one wgpu picks by default
+    Primary,
+    /// Another adapter, for machines where the default one fails, e.g. laptops with two GPUs
+    Secondary,
+}
+
+impl Adapter {
+    pub fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                secondary = flag("secondary-adapter")
+                    .desc("render with a graphics adapter other than the default one");
+            } in {
+                if secondary {
+                    Self::Secondary
+                } else {
+                    Self::Primary
+                }
+            }
+        }
+    }
+}
+
+/// How big to draw everything. The window always holds the same grid of cells, and cells
+/// are sized so that each of the font's pixels covers a whole number of logical pixels. Sizes
+/// are in logical pixels, so winit applies the display's scale factor on top, and
+/// chargrid_wgpu resizes the window when that changes, e.g. when it's dragged to another
+/// monitor. The scale factor isn't known until chargrid_wgpu has made the window, so on
+/// displays with a fractional scale factor such as 1.25 or 1.5 the glyphs can still blur.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(not(feature = "wgpu"), allow(dead_code))]
+pub struct UiScale {
+    /// Applied on top of the display's scale factor
+    scale: Option<f64>,
+}
+
+impl UiScale {
+    pub fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                scale = opt_opt::<f64, _>("SCALE", "ui-scale")
+                    .desc("scale the window and everything in it, on top of the display's scale factor (default 1)");
+            } in {
+                Self { scale }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "wgpu")]
+impl UiScale {
+    /// Columns and rows of cells in the window, which stay the same whatever the scale
+    const GRID_WIDTH: u32 = 40;
+    const GRID_HEIGHT: u32 = 30;
+    /// Width and height of a cell in the font's own pixels
+    const CELL_FONT_PX: f64 = 6.;
+    /// Logical pixels per font pixel at the default scale
+    const BASE_PIXEL_SCALE: f64 = 4.;
+    const MIN: f64 = 0.25;
+    const MAX: f64 = 4.;
+
+    fn factor(self) -> f64 {
+        self.scale.unwrap_or(1.).clamp(Self::MIN, Self::MAX)
+    }
+
+    /// Logical pixels per font pixel, rounded to a whole number
+    fn pixel_scale(self) -> f64 {
+        (Self::BASE_PIXEL_SCALE * self.factor()).round().max(1.)
+    }
+
+    /// The width and height of a cell in logical pixels
+    fn cell_px(self) -> f64 {
+        Self::CELL_FONT_PX * self.pixel_scale()
+    }
+
+    /// The size of the window in logical pixels, which fits the grid exactly
+    fn window_px(self) -> (f64, f64) {
+        let cell_px = self.cell_px();
+        (
+            Self::GRID_WIDTH as f64 * cell_px,
+            Self::GRID_HEIGHT as f64 * cell_px,
+        )
+    }
+}
+
+impl Frontend {
+    pub fn parser() -> impl meap::Parser<Item = Self> {
+        meap::choose_at_most_one! {
+            flag("wgpu").some_if(Self::Wgpu),
+            flag("ansi-terminal").some_if(Self::AnsiTerminal),
+        }
+        .with_default_general(Self::default())
+    }
+
+    /// Runs the app. The scale and adapter only apply to the wgpu frontend.
+    pub fn run(self, app: App, ui_scale: UiScale, adapter: Adapter) {
+        match self {
+            Self::Wgpu => run_wgpu(app, ui_scale, adapter),
+            Self::AnsiTerminal => run_ansi_terminal(app),
+        }
+    }
+}
+
+#[cfg(not(all(feature = "wgpu", feature = "terminal")))]
+fn unavailable(feature: &str) -> ! {
+    eprintln!(
+        "this frontend is unavailable as rl1 was built without the \"{}\" feature",
+        feature
+    );
+    std::process::exit(1);
+}
+
+#[cfg(feature = "terminal")]
+fn run_ansi_terminal(app: App) {
+    use gridbugs::chargrid_ansi_terminal::*;
+
+    let context = Context::new().unwrap();
+    context.run(app, col_encode::XtermTrueColour);
+}
+
+#[cfg(not(feature = "terminal"))]
+fn run_ansi_terminal(_app: App) {
+    unavailable("terminal");
+}
+
+/// Prints the graphics adapters wgpu can find, to help choose one when the default fails
+#[cfg(feature = "wgpu")]
+pub fn list_adapters() {
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    let adapters = instance
+        .enumerate_adapters(wgpu::Backends::all())
+        .collect::<Vec<_>>();
+    if adapters.is_empty() {
+        println!("no graphics adapters found");
+    }
+    for adapter in adapters {
+        let info = adapter.get_info();
+        println!("{} ({:?}, {:?})", info.name, info.device_type, info.backend);
+    }
+}
+
+#[cfg(not(feature = "wgpu"))]
+pub fn list_adapters() {
+    unavailable("wgpu");
+}
+
+/// What to do after the wgpu frontend fails to start
+#[cfg(feature = "wgpu")]
+enum Fallback {
+    Retry(Adapter),
+    Terminal,
+    Quit,
+}
+
+/// Explains why the wgpu frontend didn't start and what else to try. If there's someone at
+/// a terminal to answer, they're asked which of the alternatives to go with.
+#[cfg(feature = "wgpu")]
+fn startup_failure(message: &str, adapter: Adapter) -> Fallback {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    eprintln!("Failed to start the graphics frontend: {}", message);
+    eprintln!("Things to try:");
+    if adapter == Adapter::Primary {
+        eprintln!("  rl1 --secondary-adapter  render with a different graphics adapter");
+    }
+    if cfg!(feature = "terminal") {
+        eprintln!("  rl1 --ansi-terminal      play in the terminal instead");
+    } else {
+        eprintln!("  build with the \"terminal\" feature to play in the terminal instead");
+    }
+    eprintln!("  rl1 --list-adapters      list the graphics adapters which were found");
+    if !std::io::stdin().is_terminal() {
+        return Fallback::Quit;
+    }
+    let mut options = Vec::new();
+    if adapter == Adapter::Primary {
+        options.push(('s', "retry with the secondary adapter"));
+    }
+    if cfg!(feature = "terminal") {
+        options.push(('t', "play in this terminal"));
+    }
+    if options.is_empty() {
+        return Fallback::Quit;
+    }
+    options.push(('q', "quit"));
+    for (key, description) in &options {
+        eprint!("[{}] {}  ", key, description);
+    }
+    eprint!("\n> ");
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return Fallback::Quit;
+    }
+    match answer.trim() {
+        "s" if adapter == Adapter::Primary => Fallback::Retry(Adapter::Secondary),
+        "t" if cfg!(feature = "terminal") => Fallback::Terminal,
+        _ => Fallback::Quit,
+    }
+}
+
+/// The text of a panic, for reporting failures inside the frontend library
+#[cfg(feature = "wgpu")]
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown error".to_string())
+}
+
+#[cfg(feature = "wgpu")]
+fn run_wgpu(app: App, ui_scale: UiScale, adapter: Adapter) {
+    let mut adapter = adapter;
+    loop {
+        match wgpu_context(ui_scale, adapter) {
+            Ok(context) => context.run(app),
+            Err(message) => match startup_failure(&message, adapter) {
+                Fallback::Retry(next) => adapter = next,
+                Fallback::Terminal => return run_ansi_terminal(app),
+                Fallback::Quit => std::process::exit(1),
+            },
+        }
+    }
+}
+
+/// Sets up the window and graphics adapter. The frontend library panics when this fails,
+/// so the panic is caught and turned into an error the player can act on.
+#[cfg(feature = "wgpu")]
+fn wgpu_context(
+    ui_scale: UiScale,
+    adapter: Adapter,
+) -> Result<gridbugs::chargrid_wgpu::Context, String> {
+    use gridbugs::chargrid_wgpu::*;
+    use std::panic;
+
+    let cell_px = ui_scale.cell_px();
+    let (window_width, window_height) = ui_scale.window_px();
+    let config = Config {
+        font_bytes: FontBytes {
+            normal: include_bytes!("./fonts/PxPlus_IBM_CGAthin-custom.ttf").to_vec(),
+            bold: include_bytes!("./fonts/PxPlus_IBM_CGA-custom.ttf").to_vec(),
+        },
+        title: "rl1".to_string(),
+        window_dimensions_px: Dimensions {
+            width: window_width,
+            height: window_height,
+        },
+        cell_dimensions_px: Dimensions {
+            width: cell_px,
+            height: cell_px,
+        },
+        font_scale: Dimensions {
+            width: cell_px,
+            height: cell_px,
+        },
+        underline_width_cell_ratio: 0.1,
+        underline_top_offset_cell_ratio: 0.8,
+        resizable: false,
+        force_secondary_adapter: adapter == Adapter::Secondary,
+    };
+    // the default hook would print the panic as if the game had crashed
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| ()));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| Context::new(config)));
+    panic::set_hook(hook);
+    result.map_err(|payload| panic_message(payload.as_ref()))
+}
+
+#[cfg(not(feature = "wgpu"))]
+fn run_wgpu(_app: App, _ui_scale: UiScale, _adapter: Adapter) {
+    unavailable("wgpu");
+}
@@ -0,0 +1,248 @@
+//! Results of finished runs, which persist between runs like the bestiary. Each combination
+//! of run mode, difficulty and mutators keeps its own streak of consecutive wins or losses and an
+//! Elo-like rating. A run is rated as a game against the difficulty it was played at, and
+//! its score, which rewards depth, speed and conduct, scales how far the rating moves: good
+//! runs gain more when they're won and lose less when they aren't.
+use crate::{ai::AiDifficulty, conduct::Conduct, mutator::Mutators, ruleset::Ruleset};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io};
+
+const HALL_OF_FAME_PATH: &str = "hall_of_fame.json";
+const INITIAL_RATING: f64 = 1000.0;
+/// How far a rating moves after a run with the par score
+const K_FACTOR: f64 = 32.0;
+const PAR_SCORE: u32 = 1000;
+const POINTS_PER_DEPTH: u32 = 100;
+/// Points for speed, less one for every `TURNS_PER_SPEED_POINT` turns the run took
+const MAX_SPEED_POINTS: u32 = 500;
+const TURNS_PER_SPEED_POINT: u64 = 4;
+const NO_ITEMS_POINTS: u32 = 250;
+const NO_DAMAGE_POINTS: u32 = 500;
+const WIN_POINTS: u32 = 1000;
+/// Number of best runs listed in the hall of fame
+const BEST_RUNS: usize = 10;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RunResult {
+    pub won: bool,
+    pub depth: u32,
+    pub turns: u64,
+    pub conduct: Conduct,
+}
+
+impl RunResult {
+    pub fn score(&self) -> u32 {
+        let speed = MAX_SPEED_POINTS.saturating_sub((self.turns / TURNS_PER_SPEED_POINT) as u32);
+        let mut score = self.depth * POINTS_PER_DEPTH + speed;
+        if !self.conduct.used_items {
+            score += NO_ITEMS_POINTS;
+        }
+        if !self.conduct.took_damage {
+            score += NO_DAMAGE_POINTS;
+        }
+        if self.won {
+            score += WIN_POINTS;
+        }
+        score
+    }
+
+    fn conduct_description(&self) -> String {
+        let mut kept = Vec::new();
+        if !self.conduct.used_items {
+            kept.push("no items");
+        }
+        if !self.conduct.took_damage {
+            kept.push("no damage");
+        }
+        kept.join(", ")
+    }
+}
+
+/// The rating of the opponent each difficulty stands in for
+fn difficulty_rating(difficulty: AiDifficulty) -> f64 {
+    match difficulty {
+        AiDifficulty::Easy => 800.0,
+        AiDifficulty::Normal => 1000.0,
+        AiDifficulty::Hard => 1200.0,
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Standing {
+    pub rating: f64,
+    /// Consecutive wins if positive, or consecutive losses if negative
+    pub streak: i32,
+    pub best_win_streak: u32,
+    pub runs: u32,
+}
+
+impl Default for Standing {
+    fn default() -> Self {
+        Self {
+            rating: INITIAL_RATING,
+            streak: 0,
+            best_win_streak: 0,
+            runs: 0,
+        }
+    }
+}
+
+impl Standing {
+    /// Updates the rating and streak with a run, returning how far the rating moved
+    fn record(&mut self, difficulty: AiDifficulty, result: &RunResult) -> f64 {
+        let opponent = difficulty_rating(difficulty);
+        let expected = 1.0 / (1.0 + 10f64.powf((opponent - self.rating) / 400.0));
+        let weight = (result.score() as f64 / PAR_SCORE as f64).clamp(0.25, 4.0);
+        let change = if result.won {
+            K_FACTOR * weight * (1.0 - expected)
+        } else {
+            -K_FACTOR / weight * expected
+        };
+        self.rating += change;
+        self.streak = match (result.won, self.streak) {
+            (true, streak) if streak > 0 => streak + 1,
+            (true, _) => 1,
+            (false, streak) if streak < 0 => streak - 1,
+            (false, _) => -1,
+        };
+        if self.streak > 0 {
+            self.best_win_streak = self.best_win_streak.max(self.streak as u32);
+        }
+        self.runs += 1;
+        change
+    }
+
+    pub fn describe_streak(&self) -> String {
+        match self.streak {
+            0 => "no runs".to_string(),
+            1 => "1 win".to_string(),
+            -1 => "1 loss".to_string(),
+            n if n > 0 => format!("{} wins", n),
+            n => format!("{} losses", -n),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BestRun {
+    category: String,
+    result: RunResult,
+}
+
+/// What recording a run did to the player's standing
+pub struct RatingChange {
+    pub score: u32,
+    pub rating: f64,
+    pub change: f64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct HallOfFame {
+    /// Keyed by run mode, difficulty and mutators
+    standings: BTreeMap<String, Standing>,
+    best_runs: Vec<BestRun>,
+    /// Why the file on disk couldn't be read, in which case it's never overwritten
+    #[serde(skip)]
+    load_error: Option<String>,
+}
+
+fn category(ruleset: &Ruleset) -> String {
+    let mode = ruleset.mode.name();
+    let difficulty = ruleset.ai_difficulty.name();
+    if ruleset.mutators == Mutators::default() {
+        format!("{} ({})", mode, difficulty)
+    } else {
+        format!("{} ({}; {})", mode, difficulty, ruleset.mutators.name())
+    }
+}
+
+impl HallOfFame {
+    /// Loads the hall of fame from disk, starting a new one if there isn't one yet. A file
+    /// which can't be read is left as it is, so runs are no longer recorded until it's fixed.
+    pub fn load() -> Self {
+        let contents = match fs::read_to_string(HALL_OF_FAME_PATH) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => return Self::unreadable(e.to_string()),
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| Self::unreadable(e.to_string()))
+    }
+
+    fn unreadable(error: String) -> Self {
+        eprintln!("failed to read {}: {}", HALL_OF_FAME_PATH, error);
+        Self {
+            load_error: Some(error),
+            ..Self::default()
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(error) = self.load_error.as_ref() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is unreadable ({}) so was left alone",
+                    HALL_OF_FAME_PATH, error
+                ),
+            ));
+        }
+        fs::write(HALL_OF_FAME_PATH, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn record(&mut self, ruleset: &Ruleset, result: RunResult) -> RatingChange {
+        let category = category(ruleset);
+        let standing = self.standings.entry(category.clone()).or_default();
+        let change = standing.record(ruleset.ai_difficulty, &result);
+        let rating = standing.rating;
+        self.best_runs.push(BestRun { category, result });
+        self.best_runs
+            .sort_by_key(|run| std::cmp::Reverse(run.result.score()));
+        self.best_runs.truncate(BEST_RUNS);
+        RatingChange {
+            score: result.score(),
+            rating,
+            change,
+        }
+    }
+
+    /// The lines of the hall of fame screen
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(error) = self.load_error.as_ref() {
+            lines.push(format!("Couldn't read {}: {}", HALL_OF_FAME_PATH, error));
+        }
+        for (category, standing) in &self.standings {
+            lines.push(format!(
+                "{}: rating {:.0}, {} runs, streak: {}, best: {} wins",
+                category,
+                standing.rating,
+                standing.runs,
+                standing.describe_streak(),
+                standing.best_win_streak
+            ));
+        }
+        if self.best_runs.is_empty() {
+            return lines;
+        }
+        lines.push(String::new());
+        lines.push("Best runs".to_string());
+        for (i, run) in self.best_runs.iter().enumerate() {
+            let result = &run.result;
+            let mut line = format!(
+                "{:2}. {:5} {} {}, depth {}, {} turns",
+                i + 1,
+                result.score(),
+                run.category,
+                if result.won { "won" } else { "lost" },
+                result.depth,
+                result.turns
+            );
+            let conduct = result.conduct_description();
+            if !conduct.is_empty() {
+                line.push_str(&format!(" ({})", conduct));
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
@@ -0,0 +1,100 @@
+//! Panel beside the map summarising the player's condition
+use crate::{speedrun::format_duration, visibility::CellVisibility, Game};
+use gridbugs::chargrid::{prelude::*, text::StyledString};
+
+/// Number of columns to the right of the map reserved for the HUD
+pub const HUD_WIDTH: u32 = 18;
+
+/// Describes how brightly lit the player's cell is
+fn light_description(game: &Game) -> &'static str {
+    let visibility_grid = game.visibility_grid();
+    let light_colour = game
+        .player_coord()
+        .and_then(|coord| visibility_grid.cell(coord))
+        .and_then(|cell| match cell.visibility(visibility_grid.count()) {
+            CellVisibility::CurrentlyVisibleWithLightColour(light_colour) => light_colour,
+            _ => None,
+        });
+    let brightness = light_colour.map(|c| c.r as u32 + c.g as u32 + c.b as u32);
+    match brightness {
+        None => "dark",
+        Some(0..=191) => "dim",
+        Some(192..=447) => "lit",
+        Some(_) => "bright",
+    }
+}
+
+pub(crate) struct Hud;
+
+impl Hud {
+    fn lines(game: &Game) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(hit_points) = game.player_hit_points() {
+            lines.push(format!("HP: {}/{}", hit_points.current, hit_points.max));
+        }
+        if let Some(stamina) = game.player_stamina() {
+            lines.push(format!("Stamina: {}/{}", stamina.current, stamina.max));
+        }
+        lines.push(format!("Depth: {}", game.dungeon.depth()));
+        lines.push(format!("Turn: {}", game.turn));
+        if game.show_timer {
+            lines.push(format!(
+                "Time: {}",
+                format_duration(game.run_timer.elapsed())
+            ));
+            if let Some(split) = game.run_timer.last_split() {
+                lines.push(format!(
+                    "Split {}: {}",
+                    split.depth,
+                    format_duration(split.time)
+                ));
+            }
+        }
+        lines.push(format!("XP: {}", game.experience));
+        lines.push(format!("Gold: {}", game.gold));
+        lines.push(format!("Light: {}", light_description(game)));
+        if game.player_is_confused() {
+            lines.push("Confused".to_string());
+        }
+        if let Some(objective) = game.level.objective {
+            if game.level.solved {
+                lines.push("Puzzle: solved".to_string());
+            } else {
+                let (done, total) = objective.progress(&game.level.world);
+                lines.push(format!("Puzzle: {}/{}", done, total));
+            }
+        }
+        lines.push(String::new());
+        lines.push("Carrying:".to_string());
+        let items = game.player_inventory();
+        if items.is_empty() {
+            lines.push(" nothing".to_string());
+        }
+        for name in items {
+            lines.push(format!(" {}", name));
+        }
+        lines
+    }
+}
+
+impl Component for Hud {
+    type Output = ();
+    type State = Game;
+
+    fn render(&self, state: &Self::State, ctx: Ctx, fb: &mut FrameBuffer) {
+        let style = Style::plain_text().with_foreground(Rgba32::new_grey(255));
+        for (i, line) in Self::lines(state).into_iter().enumerate() {
+            StyledString {
+                string: line,
+                style,
+            }
+            .render(&(), ctx.add_offset(Coord::new(1, i as i32)), fb);
+        }
+    }
+
+    fn update(&mut self, _state: &mut Self::State, _ctx: Ctx, _event: Event) -> Self::Output {}
+
+    fn size(&self, _state: &Self::State, ctx: Ctx) -> Size {
+        Size::new(HUD_WIDTH, ctx.bounding_box.size().height())
+    }
+}
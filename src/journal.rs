@@ -0,0 +1,69 @@
+use crate::{
+    bestiary::MonsterStats,
+    components::{Item, Spawner, Tile},
+    effect::Effect,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum GameEvent {
+    Attack {
+        attacker: Option<Tile>,
+        victim: Option<Tile>,
+        damage: u32,
+    },
+    Died {
+        victim: Option<Tile>,
+        killer: Option<Tile>,
+    },
+    /// The player saw a monster for the first time
+    Encountered {
+        monster: Tile,
+        stats: MonsterStats,
+    },
+    /// The player destroyed a spawner
+    Destroyed(Spawner),
+    PickedUp(Item),
+    Dropped(Item),
+    Drank {
+        drinker: Option<Tile>,
+    },
+    Thrown {
+        thrower: Option<Tile>,
+        item: Item,
+    },
+    Effect(Effect),
+    /// The player killed a monster in melee, holding the given weapon if any
+    KilledWith(Option<Item>),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub turn: u64,
+    pub event: GameEvent,
+}
+
+/// Record of everything notable that has happened during the current run
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    pub fn push(&mut self, turn: u64, event: GameEvent) {
+        self.entries.push(JournalEntry { turn, event });
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The most recent `count` entries, oldest first
+    pub fn recent(&self, count: usize) -> &[JournalEntry] {
+        &self.entries[self.entries.len().saturating_sub(count)..]
+    }
+}
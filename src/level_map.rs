@@ -0,0 +1,186 @@
+//! A full-screen map of everything the player has seen of the current level, with a legend
+//! of the symbols on it. The map has a camera of its own, which the player pans around, so
+//! looking at it doesn't move the view of the game.
+use crate::{components::Tile, remembered_colour, tile_char, visibility::EntityTile, Camera, Game};
+use gridbugs::{
+    chargrid::{prelude::*, text::StyledString},
+    direction::CardinalDirection,
+};
+use std::collections::BTreeSet;
+
+/// Number of cells the map moves with each press of a direction key
+pub const PAN_STEP: i32 = 8;
+const STAIRS_BACKGROUND: Rgba32 = Rgba32::new(127, 95, 0, 255);
+const NOTE_BACKGROUND: Rgba32 = Rgba32::new(0, 63, 127, 255);
+const HELP: &str = "arrows: pan, .: centre on you, M/enter: close";
+/// The legend never takes more than this fraction of the screen's rows from the map
+const MAX_LEGEND_FRACTION: u32 = 2;
+
+/// Moves the centre of the map by a step in a direction, keeping it on the level
+pub fn pan(game: &Game, focus: Coord, direction: CardinalDirection) -> Coord {
+    let size = game.level.world.size();
+    let step = direction.coord();
+    let focus = focus + Coord::new(step.x * PAN_STEP, step.y * PAN_STEP);
+    Coord::new(
+        focus.x.clamp(0, size.width() as i32 - 1),
+        focus.y.clamp(0, size.height() as i32 - 1),
+    )
+}
+
+/// The stairs in a cell the player knows about, even if there's an item on them
+fn stairs_tile(game: &Game, coord: Coord) -> Option<Tile> {
+    let layers = game.visibility_grid().cell(coord)?.tile_layers();
+    layers
+        .feature
+        .map(|EntityTile { tile, .. }| tile)
+        .filter(|&tile| matches!(tile, Tile::StairsDown | Tile::StairsUp))
+}
+
+/// What's drawn for a cell: whatever's on top, leaving out remembered characters as they've
+/// probably moved since
+fn cell_tile(game: &Game, coord: Coord) -> Option<Tile> {
+    let visibility_grid = game.visibility_grid();
+    let layers = visibility_grid.cell(coord)?.tile_layers();
+    let top = if visibility_grid.is_currently_visible(coord) {
+        layers.top()
+    } else {
+        layers.item.or(layers.feature).or(layers.floor)
+    };
+    top.map(|EntityTile { tile, .. }| tile)
+}
+
+/// The legend's lines, wrapped to fit in `width` columns and cut down to at most `max_lines`.
+/// If there are too many notes to list, the ones nearest `focus` are listed, so panning the
+/// map brings the others into the legend.
+fn legend(game: &Game, focus: Coord, width: usize, max_lines: usize) -> Vec<String> {
+    let tiles = game
+        .visibility_grid()
+        .enumerate()
+        .filter(|&(coord, _)| game.visibility_grid().is_known(coord))
+        .flat_map(|(coord, _)| [cell_tile(game, coord), stairs_tile(game, coord)])
+        .flatten()
+        .filter(|&tile| tile != Tile::Floor)
+        .map(|tile| (tile_char(tile), tile.name()))
+        .collect::<BTreeSet<_>>();
+    let mut entries = tiles
+        .into_iter()
+        .map(|(ch, name)| format!("{} {}", ch, name))
+        .collect::<Vec<_>>();
+    if !game.level.annotations.is_empty() {
+        entries.push("blue: note".to_string());
+    }
+    let mut lines = vec![String::new()];
+    for entry in entries {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.chars().count() + entry.chars().count() + 2 > width {
+            lines.push(entry);
+        } else {
+            if !line.is_empty() {
+                line.push_str("  ");
+            }
+            line.push_str(&entry);
+        }
+    }
+    // the help line is always shown
+    lines.truncate(max_lines.saturating_sub(1));
+    let mut notes = game.level.annotations.iter().collect::<Vec<_>>();
+    let note_lines = max_lines.saturating_sub(lines.len() + 1);
+    let too_many = notes.len() > note_lines;
+    if too_many {
+        notes.sort_by_key(|&(&coord, _)| (coord - focus).magnitude2());
+        // one line goes to saying how many aren't listed
+        notes.truncate(note_lines.saturating_sub(1));
+    }
+    notes.sort_by_key(|&(coord, _)| (coord.y, coord.x));
+    for (coord, note) in &notes {
+        lines.push(format!("note at {},{}: {}", coord.x, coord.y, note));
+    }
+    if too_many && note_lines > 0 {
+        lines.push(format!(
+            "({} more notes, pan to list them)",
+            game.level.annotations.len() - notes.len()
+        ));
+    }
+    lines.push(HELP.to_string());
+    lines
+}
+
+pub fn render(game: &Game, focus: Coord, ctx: Ctx, fb: &mut FrameBuffer) {
+    let size = ctx.bounding_box.size();
+    let legend = legend(
+        game,
+        focus,
+        size.width().saturating_sub(2) as usize,
+        (size.height() / MAX_LEGEND_FRACTION) as usize,
+    );
+    let map_height = size.height().saturating_sub(legend.len() as u32 + 1);
+    let view_size = Size::new(size.width(), map_height);
+    let camera = Camera::new(focus, game.level.world.size(), view_size);
+    let visibility_grid = game.visibility_grid();
+    for (coord, _) in visibility_grid.enumerate() {
+        if !visibility_grid.is_known(coord) {
+            continue;
+        }
+        let (screen_coord, tile) = match (camera.world_to_screen(coord), cell_tile(game, coord)) {
+            (Some(screen_coord), Some(tile)) => (screen_coord, tile),
+            _ => continue,
+        };
+        let foreground = tile.colour_hint().foreground;
+        let foreground = if visibility_grid.is_currently_visible(coord) {
+            foreground.to_rgba32(255)
+        } else {
+            remembered_colour(foreground)
+        };
+        let mut render_cell = RenderCell::default()
+            .with_character(tile_char(tile))
+            .with_foreground(foreground);
+        if game.annotation(coord).is_some() {
+            render_cell = render_cell.with_background(NOTE_BACKGROUND);
+        } else if stairs_tile(game, coord).is_some() {
+            render_cell = render_cell.with_background(STAIRS_BACKGROUND);
+        }
+        fb.set_cell_relative_to_ctx(ctx, screen_coord, 0, render_cell);
+    }
+    let style = Style::plain_text().with_foreground(Rgba32::new_grey(255));
+    for (i, line) in legend.iter().enumerate() {
+        StyledString {
+            string: line.clone(),
+            style,
+        }
+        .render(
+            &(),
+            ctx.add_offset(Coord::new(1, map_height as i32 + 1 + i as i32)),
+            fb,
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{components::Item, fixture};
+
+    #[test]
+    fn legend_fits_however_many_notes_there_are() {
+        let mut game = fixture::new_game("@.........\n").unwrap();
+        for x in 0..10 {
+            game.annotate(Coord::new(x, 0), "note");
+        }
+        let legend = legend(&game, Coord::new(0, 0), 40, 6);
+        assert_eq!(legend.len(), 6);
+        assert_eq!(legend.last().map(String::as_str), Some(HELP));
+        assert!(legend.iter().any(|line| line.starts_with("note at 0,0")));
+    }
+
+    #[test]
+    fn stairs_under_an_item_are_still_marked() {
+        let mut game = fixture::new_game("@.<\n").unwrap();
+        let stairs = Coord::new(2, 0);
+        game.level.world.spawn_item(stairs, Item::Potion);
+        game.player_wait();
+        assert_eq!(cell_tile(&game, stairs), Some(Tile::Potion));
+        assert_eq!(stairs_tile(&game, stairs), Some(Tile::StairsUp));
+        let legend = legend(&game, stairs, 40, 10);
+        assert!(legend[0].contains(Tile::StairsUp.name()));
+    }
+}
@@ -0,0 +1,26 @@
+use gridbugs::coord_2d::Coord;
+
+/// The cells on a straight line from `start` to `end` using Bresenham's algorithm, not
+/// including `start`
+pub fn cells(start: Coord, end: Coord) -> Vec<Coord> {
+    let delta = end - start;
+    let step = Coord::new(delta.x.signum(), delta.y.signum());
+    let dx = delta.x.abs();
+    let dy = -delta.y.abs();
+    let mut error = dx + dy;
+    let mut coord = start;
+    let mut cells = Vec::new();
+    while coord != end {
+        let error2 = error * 2;
+        if error2 >= dy {
+            error += dy;
+            coord.x += step.x;
+        }
+        if error2 <= dx {
+            error += dx;
+            coord.y += step.y;
+        }
+        cells.push(coord);
+    }
+    cells
+}
@@ -0,0 +1,104 @@
+//! Loot tables, loaded from a data file, describing what each kind of monster drops when it
+//! dies
+use crate::components::{Item, Tile};
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const LOOT_TABLES: &str = include_str!("./loot.toml");
+
+#[derive(Debug, Deserialize)]
+struct DropEntry {
+    item: String,
+    /// Percent chance of the drop
+    chance: u32,
+    #[serde(default)]
+    min: Option<u32>,
+    #[serde(default)]
+    max: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Loot {
+    Item(Item),
+    Gold(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DropKind {
+    Item(Item),
+    Gold { min: u32, max: u32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Drop {
+    kind: DropKind,
+    chance: u32,
+}
+
+impl Drop {
+    fn from_entry(entry: DropEntry) -> Result<Self, String> {
+        let kind = if entry.item == "gold" {
+            let min = entry.min.unwrap_or(1);
+            let max = entry.max.unwrap_or(min).max(min);
+            DropKind::Gold { min, max }
+        } else {
+            let item = Item::from_name(&entry.item)
+                .ok_or_else(|| format!("unknown item \"{}\"", entry.item))?;
+            DropKind::Item(item)
+        };
+        Ok(Self {
+            kind,
+            chance: entry.chance.min(100),
+        })
+    }
+}
+
+/// The drops for each monster, keyed by the monster's name
+pub struct LootTables {
+    tables: HashMap<String, Vec<Drop>>,
+}
+
+impl Default for LootTables {
+    fn default() -> Self {
+        Self::parse(LOOT_TABLES).expect("invalid loot tables")
+    }
+}
+
+impl LootTables {
+    fn parse(s: &str) -> Result<Self, String> {
+        let entries =
+            toml::from_str::<HashMap<String, Vec<DropEntry>>>(s).map_err(|e| e.to_string())?;
+        let tables = entries
+            .into_iter()
+            .map(|(monster, entries)| {
+                let drops = entries
+                    .into_iter()
+                    .map(Drop::from_entry)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("{}: {}", monster, e))?;
+                Ok((monster, drops))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { tables })
+    }
+
+    /// Rolls the loot dropped by a monster of the given kind
+    pub fn roll<R: Rng>(&self, monster: Tile, rng: &mut R) -> Vec<Loot> {
+        let drops = match self.tables.get(monster.name()) {
+            Some(drops) => drops,
+            None => return Vec::new(),
+        };
+        let mut loot = Vec::new();
+        for drop in drops {
+            if rng.gen_range(0..100) >= drop.chance {
+                continue;
+            }
+            loot.push(match drop.kind {
+                DropKind::Item(item) => Loot::Item(item),
+                DropKind::Gold { min, max } => Loot::Gold(rng.gen_range(min..=max)),
+            });
+        }
+        loot
+    }
+}
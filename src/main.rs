@@ -1,284 +1,4943 @@
 use gridbugs::{
-    chargrid::{control_flow::*, prelude::*},
-    direction::CardinalDirection,
+    chargrid::{
+        control_flow::*,
+        input::{keys, Input, MouseButton, MouseInput},
+        prelude::*,
+        text::StyledString,
+    },
+    direction::{CardinalDirection, Direction},
     entity_table::Entity,
     rgb_int::Rgb24,
-    shadowcast::Context as ShadowcastContext,
+};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_isaac::Isaac64Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
+mod ai;
+mod animation;
+mod arena;
+mod bench;
+mod bestiary;
+mod bug_report;
+mod cave;
+mod chat;
 mod components;
+mod conduct;
+mod console;
+mod controls;
+mod cutscene;
+mod darkness;
+mod discoveries;
+mod dungeon;
+mod effect;
+mod feeling;
+mod fixture;
+mod fov;
+mod frontend;
+mod hall_of_fame;
+mod hud;
+mod journal;
+mod level_map;
+mod line;
+mod loot;
+mod map_diff;
+mod menu;
+mod message_log;
+mod morgue;
+mod music;
+mod mutator;
+mod objective;
+mod overlay;
+mod presence;
+mod quick_menu;
+mod recording;
+mod ruleset;
+mod save_file;
+mod settings;
 mod spatial;
+mod speedrun;
+mod statistics;
+mod stream;
+mod terrain;
+mod travel;
 mod visibility;
 mod world;
 
-use components::Tile;
-use spatial::{Layer, Location};
-use visibility::{CellVisibility, EntityTile, VisibilityCell, VisibilityGrid};
-use world::World;
+use ai::{AiContext, NpcAction};
+use animation::{
+    AnimationCue, CharacterAnimations, Flashes, Pose, Projectile, PROJECTILE_FRAME_DURATION,
+};
+use arena::{Arena, ArenaArgs, ArenaConfig, ARENA_MAP, MIN_SPAWN_DISTANCE};
+use bestiary::{Bestiary, MonsterStats};
+use bug_report::BugReport;
+use cave::Cave;
+use chat::{Chat, ChatConfig};
+use components::{
+    Disposition, DoorState, Grip, Hand, HitPoints, Item, ItemCategory, Stamina, Tile, Trap,
+};
+use conduct::Conduct;
+use console::{Command, Tags};
+use controls::{AppInput, Controls};
+use cutscene::{Cutscenes, Sequence};
+use darkness::DIM_BRIGHTNESS;
+use discoveries::Discoveries;
+use dungeon::{Dungeon, Level};
+use effect::Effect;
+use fov::{Fov, FovAlgorithm};
+use frontend::{Adapter, Frontend, UiScale};
+use hall_of_fame::{HallOfFame, RunResult};
+use hud::Hud;
+use journal::{GameEvent, Journal};
+use loot::{Loot, LootTables};
+use map_diff::MapDiffArgs;
+use menu::Menu;
+use message_log::MessageLog;
+use music::Music;
+use mutator::{Mutator, Mutators};
+use overlay::{Highlight, Overlay};
+use presence::{Presence, PresenceStatus};
+use quick_menu::{QuickSlots, QuickUse};
+use recording::{Header, Recorder, RecordingArgs, Replay};
+use ruleset::{Ruleset, RunMode};
+use save_file::SaveFormat;
+use settings::{AnimationSpeed, AutoPickup, ItemRule, Settings, WhiteBalance};
+use spatial::{Layer, Location};
+use speedrun::{format_duration, RunTimer, SPLITS_PATH};
+use statistics::{Profile, StoredRun};
+use stream::{FrameStream, StreamTarget, Streamed};
+use terrain::{Maps, Terrain};
+use travel::{Destination, Travel, TravelStatus, TRAVEL_STEP_DURATION};
+use visibility::{CellVisibility, EntityTile, Light, VisibilityCell, VisibilityGrid};
+use world::{CharacterData, EquipOutcome, World, ACTION_COST, TICKS_PER_TURN};
+
+struct Args {
+    frontend: Frontend,
+    ui_scale: UiScale,
+    adapter: Adapter,
+    list_adapters: bool,
+    stream: Option<StreamTarget>,
+    chat: Option<ChatConfig>,
+    ruleset: Ruleset,
+    save_format: SaveFormat,
+    recording: RecordingArgs,
+    map: Option<PathBuf>,
+    arena: ArenaArgs,
+    profile: Option<String>,
+    bench_visibility: bool,
+    map_diff: MapDiffArgs,
+    check_fixtures: Option<PathBuf>,
+}
+
+impl Args {
+    fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                frontend = Frontend::parser();
+                ui_scale = UiScale::parser();
+                adapter = Adapter::parser();
+                list_adapters = flag("list-adapters")
+                    .desc("list the graphics adapters the wgpu frontend can use, then exit");
+                stream = StreamTarget::parser();
+                chat = ChatConfig::parser();
+                ruleset = Ruleset::parser();
+                save_format = SaveFormat::parser();
+                recording = RecordingArgs::parser();
+                map = opt_opt::<PathBuf, _>("PATH", "map")
+                    .desc("play a single level loaded from a map file instead of the built-in dungeon");
+                arena = ArenaArgs::parser();
+                profile = opt_opt::<String, _>("NAME", "profile")
+                    .desc("keep statistics for finished runs under this profile (default: default)");
+                bench_visibility = flag("bench-visibility")
+                    .desc("time visibility updates with and without caching, then exit");
+                map_diff = MapDiffArgs::parser();
+                check_fixtures = opt_opt::<PathBuf, _>("DIR", "check-fixtures")
+                    .desc("run the scenarios in each fixture file in a directory, then exit");
+            } in {
+                Self {
+                    frontend,
+                    ui_scale,
+                    adapter,
+                    list_adapters,
+                    stream,
+                    chat,
+                    ruleset,
+                    save_format,
+                    recording,
+                    map,
+                    arena,
+                    profile,
+                    bench_visibility,
+                    map_diff,
+                    check_fixtures,
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    use meap::Parser;
+    let Args {
+        frontend,
+        ui_scale,
+        adapter,
+        list_adapters,
+        stream,
+        chat,
+        ruleset,
+        save_format,
+        recording,
+        map,
+        arena,
+        profile,
+        bench_visibility,
+        map_diff,
+        check_fixtures,
+    } = Args::parser().with_help_default().parse_env_or_exit();
+    if list_adapters {
+        frontend::list_adapters();
+        return;
+    }
+    if bench_visibility {
+        bench::visibility();
+        return;
+    }
+    if let Some(dir) = check_fixtures {
+        match fixture::check_all(&dir) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("failed to check fixtures in {}: {}", dir.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(seed) = map_diff.seed {
+        match map_diff::run(&map_diff, seed) {
+            // caves from different seeds are expected to differ, so only a difference from a
+            // map generated from the same seed counts as a failure
+            Ok(identical) if identical || map_diff.against.is_none() => return,
+            Ok(_) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("failed to diff maps: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let frame_stream = stream.map(|stream| match stream.connect() {
+        Ok(frame_stream) => frame_stream,
+        Err(e) => {
+            eprintln!("failed to open frame stream: {}", e);
+            std::process::exit(1);
+        }
+    });
+    let maps = match map {
+        Some(path) => match Maps::load(&path) {
+            Ok(maps) => maps,
+            Err(e) => {
+                eprintln!("failed to load map {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => Maps::builtin(),
+    };
+    let startup = match recording.replay {
+        Some(path) => match Replay::load(&path) {
+            Ok(replay) => Startup::Replay(replay),
+            Err(e) => {
+                eprintln!("failed to load recording {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None if arena.start => Startup::NewGame(RunMode::Arena),
+        None => Startup::MainMenu,
+    };
+    let setup = Setup {
+        maps,
+        arena: arena.config,
+    };
+    let chat = chat.map(Chat::spawn);
+    let component = GameComponent::new(
+        chat,
+        save_format,
+        setup,
+        Profile::new(profile),
+        recording.record,
+    );
+    let app = app(frame_stream, ruleset, component, startup);
+    frontend.run(app, ui_scale, adapter);
+}
+
+/// Everything new games are created from other than their ruleset
+struct Setup {
+    maps: Maps,
+    arena: ArenaConfig,
+}
+
+/// What the game shows when it's first opened
+enum Startup {
+    MainMenu,
+    NewGame(RunMode),
+    Replay(Replay),
+}
+
+fn app(
+    frame_stream: Option<FrameStream>,
+    ruleset: Ruleset,
+    mut component: GameComponent,
+    startup: Startup,
+) -> App {
+    let mut game = Game::new(ruleset, &component.setup);
+    match startup {
+        Startup::MainMenu => (),
+        Startup::NewGame(mode) => component.new_game(&mut game, mode),
+        Startup::Replay(replay) => game = component.start_replay(replay),
+    }
+    cf(Streamed {
+        component,
+        frame_stream: RefCell::new(frame_stream),
+    })
+    .with_state(game)
+    .map_val(|| app::Exit)
+    .exit_on_close()
+}
+
+const SAVE_PATH: &str = "save.bin";
+const BUG_REPORT_JOURNAL_LENGTH: usize = 100;
+/// Number of the final messages of a run included in its morgue file
+const MORGUE_MESSAGES: usize = 20;
+/// Time without anything on screen changing before frames are slowed down
+const IDLE_DELAY: Duration = Duration::from_millis(500);
+/// How long each frame is held up for once the screen is idle, which caps the frame rate at
+/// under 20 frames a second while adding at most this much delay to the next key press
+const IDLE_FRAME_SLEEP: Duration = Duration::from_millis(50);
+/// Spawners stop creating monsters while a level has at least this many
+const LEVEL_POPULATION_CAP: usize = 12;
+/// Visible hostiles stop adding to the danger the player is in beyond this distance
+const DANGER_RANGE: u32 = 10;
+/// Danger at or above which the music is at its most intense
+const FULL_DANGER: u32 = 30;
+/// Maximum number of cells a thrown item travels
+const THROW_RANGE: usize = 8;
+/// Damage dealt to a character hit by a thrown item
+const THROW_DAMAGE: u32 = 1;
+/// Stamina spent to move two cells in one turn
+const SPRINT_COST: u32 = 3;
+/// Stamina spent on an attack which can deal double damage
+const POWER_ATTACK_COST: u32 = 4;
+/// Stamina recovered by waiting a turn
+const REST_STAMINA: u32 = 2;
+/// A shield blocks one in this many attacks
+const SHIELD_BLOCK_CHANCE: u32 = 4;
+/// Off hand attacks deal this fraction of the damage of a main hand attack
+const DUAL_WIELD_DAMAGE_DIVISOR: u32 = 2;
+/// Experience awarded for meeting a level's objective
+const PUZZLE_EXPERIENCE: u32 = 25;
+/// Number of turns the freeze chat effect stops an enemy for
+const FREEZE_TURNS: u64 = 3;
+const CONFUSION_TURNS: u64 = 5;
+const CHARM_TURNS: u64 = 10;
+const DARKVISION_BRIGHTNESS_PERCENT: u32 = 60;
+/// A confused player walks in a random direction one step in this many
+const CONFUSED_STUMBLE_CHANCE: u32 = 2;
+/// Levels further above the player than this are forgotten in endless runs
+const ENDLESS_LEVELS_KEPT_ABOVE: usize = 2;
+/// Weight the player can carry regardless of their stats
+const BASE_CARRY_CAPACITY: u32 = 10;
+/// Extra weight the player can carry for each point of maximum stamina
+const CARRY_CAPACITY_PER_STAMINA: u32 = 1;
+/// Actions take this much longer when the player carries more than their capacity
+const ENCUMBERED_ACTION_COST: u32 = ACTION_COST * 3 / 2;
+
+/// An item which has left the thrower's inventory but not yet landed. The world is updated
+/// when it lands, after its flight has been animated.
+struct Throw {
+    thrower: Entity,
+    item: Entity,
+    path: Vec<Coord>,
+    /// Given off by the item while it's in flight
+    light: Option<Light>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Game {
+    ruleset: Ruleset,
+    /// The seed the run was started with, so it can be reproduced
+    seed: u64,
+    /// All randomness in the game comes from here
+    rng: Isaac64Rng,
+    level: Level,
+    dungeon: Dungeon,
+    player_entity: Entity,
+    turn: u64,
+    /// Scheduler ticks since the start of the run
+    time: u64,
+    /// Earned by destroying spawners
+    experience: u32,
+    gold: u32,
+    /// Whether the player was carrying more than their capacity at the end of their last
+    /// turn, so they're told when that changes
+    encumbered: bool,
+    /// Set once the player clears the deepest level of the dungeon
+    won: bool,
+    journal: Journal,
+    message_log: MessageLog,
+    quick_slots: QuickSlots,
+    /// Ids and tags given to entities in the debug console
+    tags: Tags,
+    #[serde(skip)]
+    fov: Fov,
+    #[serde(skip)]
+    ai_context: AiContext,
+    #[serde(skip)]
+    loot_tables: LootTables,
+    /// Applied from the settings rather than saved, like the field of view algorithm
+    #[serde(skip)]
+    auto_pickup: AutoPickup,
+    /// Whether the run timer is shown, from the settings
+    #[serde(skip)]
+    show_timer: bool,
+    /// Applied to the colour of lit cells after their light, from the settings
+    #[serde(skip)]
+    white_balance: WhiteBalance,
+    /// Things which happened in view since the UI last picked them up to animate
+    #[serde(skip)]
+    animation_cues: Vec<AnimationCue>,
+    /// Lights which only exist while something is animated. They're kept track of so none
+    /// are left behind if the game is saved while one is showing.
+    transient_lights: Vec<Entity>,
+    run_timer: RunTimer,
+    /// Set in arena runs to send in waves of monsters
+    arena: Option<Arena>,
+}
+
+impl Game {
+    fn new(ruleset: Ruleset, setup: &Setup) -> Self {
+        let seed = ruleset
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen::<u64>());
+        let mut rng = Isaac64Rng::seed_from_u64(seed);
+        let mut player_entity = None;
+        let levels = match ruleset.mode {
+            RunMode::Dungeon => setup
+                .maps
+                .parse()
+                .into_iter()
+                .enumerate()
+                .map(|(i, terrain)| {
+                    if i == 0 {
+                        player_entity = terrain.player_entity;
+                    }
+                    let mut world = terrain.world;
+                    ruleset.mutators.apply_to_world(&mut world);
+                    let mut level = Level::new(world, i as u32 + 1);
+                    level.objective = terrain.objective;
+                    if let Some(hazard) = terrain.hazard {
+                        level.set_hazard(hazard);
+                    }
+                    level
+                })
+                .collect::<Vec<_>>(),
+            RunMode::Endless => {
+                let Cave { mut world, arrival } = cave::generate(1, &mut rng);
+                ruleset.mutators.apply_to_world(&mut world);
+                let location = Location {
+                    coord: arrival,
+                    layer: Some(Layer::Character),
+                };
+                player_entity = Some(world.insert_entity_data(location, World::make_player()));
+                vec![Level::new(world, 1)]
+            }
+            RunMode::Arena => {
+                let terrain = Terrain::parse(ARENA_MAP).expect("invalid arena map");
+                player_entity = terrain.player_entity;
+                let mut world = terrain.world;
+                ruleset.mutators.apply_to_world(&mut world);
+                let mut level = Level::new(world, 1);
+                level.objective = terrain.objective;
+                if let Some(hazard) = terrain.hazard {
+                    level.set_hazard(hazard);
+                }
+                vec![level]
+            }
+        };
+        let player_entity = player_entity.expect("didn't create player");
+        let (dungeon, mut level) = Dungeon::new(levels);
+        ruleset
+            .mutators
+            .apply_to_player(&mut level.world, player_entity);
+        let ai_context = AiContext::default();
+        let mut s = Self {
+            ruleset,
+            seed,
+            rng,
+            level,
+            dungeon,
+            player_entity,
+            turn: 0,
+            time: 0,
+            experience: 0,
+            gold: 0,
+            encumbered: false,
+            won: false,
+            journal: Journal::default(),
+            message_log: MessageLog::default(),
+            quick_slots: QuickSlots::default(),
+            tags: Tags::default(),
+            fov: Fov::default(),
+            ai_context,
+            loot_tables: LootTables::default(),
+            auto_pickup: AutoPickup::default(),
+            show_timer: false,
+            white_balance: WhiteBalance::default(),
+            animation_cues: Vec::new(),
+            transient_lights: Vec::new(),
+            run_timer: RunTimer::default(),
+            arena: None,
+        };
+        if ruleset.mode == RunMode::Arena {
+            s.arena = Some(Arena::new(&setup.arena));
+            s.player_take_arena_equipment(&setup.arena);
+        }
+        s.update_visibility();
+        s.report_level_feeling();
+        s
+    }
+
+    /// Gives the player the items they chose to fight the arena with, holding whatever they
+    /// can
+    fn player_take_arena_equipment(&mut self, config: &ArenaConfig) {
+        let world = &mut self.level.world;
+        for &item in &config.equipment.0 {
+            world.spawn_item_in_inventory(self.player_entity, item);
+        }
+        let count = world
+            .components
+            .inventory
+            .get(self.player_entity)
+            .map(|inventory| inventory.items().len())
+            .unwrap_or(0);
+        for index in 0..count {
+            world.toggle_equipped(self.player_entity, index);
+        }
+    }
+
+    /// Sends in the next wave of monsters once the arena has been cleared
+    fn arena_turn(&mut self) {
+        let remaining = self.level.world.all_npcs().count();
+        let monsters = match self.arena.as_mut().and_then(|arena| arena.turn(remaining)) {
+            Some(monsters) => monsters,
+            None => return,
+        };
+        let player_coord = match self.player_coord() {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        let world = &self.level.world;
+        let mut coords = world
+            .size()
+            .coord_iter_row_major()
+            .filter(|&coord| {
+                world.is_walkable_coord(coord)
+                    && !world.is_character_at_coord(coord)
+                    && coord.manhattan_distance(player_coord) >= MIN_SPAWN_DISTANCE
+            })
+            .collect::<Vec<_>>();
+        coords.shuffle(&mut self.rng);
+        for (monster, coord) in monsters.into_iter().zip(coords) {
+            self.level.world.spawn_monster(coord, monster);
+        }
+        if let Some(arena) = self.arena.as_ref() {
+            self.log(format!("Wave {} enters the arena!", arena.wave));
+        }
+    }
+
+    /// Describes the current level the first time the player arrives
+    fn report_level_feeling(&mut self) {
+        if self.level.visited {
+            return;
+        }
+        self.level.visited = true;
+        self.log(self.level.feeling.message());
+        if let Some(objective) = self.level.objective {
+            self.log(format!("Objective: {}", objective.description()));
+        }
+        if self.level.darkness.is_some() {
+            self.log("Darkness is creeping over this place. Keep the lights burning.");
+        }
+    }
+
+    /// Lets the darkness dim one of the level's lights, and sends out a shade from an unlit
+    /// cell the player can't see
+    fn darkness_turn(&mut self) {
+        let turn = match self.level.darkness.as_mut() {
+            Some(darkness) => darkness.turn(),
+            None => return,
+        };
+        if turn.dim {
+            let lights = self.level.world.fixed_lights().collect::<Vec<_>>();
+            if let Some(&light) = lights.choose(&mut self.rng) {
+                let coord = self.level.world.entity_coord(light);
+                if self.level.world.dim_light(light, DIM_BRIGHTNESS)
+                    && coord
+                        .is_some_and(|coord| self.level.visibility_grid.is_currently_visible(coord))
+                {
+                    self.log("The darkness swallows a light.");
+                }
+            }
+        }
+        if !turn.spawn || self.level.world.all_npcs().count() >= LEVEL_POPULATION_CAP {
+            return;
+        }
+        let world = &self.level.world;
+        let visibility_grid = &self.level.visibility_grid;
+        let coords = world
+            .size()
+            .coord_iter_row_major()
+            .filter(|&coord| {
+                world.is_walkable_coord(coord)
+                    && !world.is_character_at_coord(coord)
+                    && !visibility_grid.is_currently_visible(coord)
+                    && darkness::is_unlit(world, coord)
+            })
+            .collect::<Vec<_>>();
+        if let Some(&coord) = coords.choose(&mut self.rng) {
+            self.level.world.spawn_shade(coord);
+            self.log("Something stirs in the darkness.");
+        }
+    }
+
+    /// Rewards the player the first time the current level's objective is met
+    fn check_objective(&mut self) {
+        let objective = match self.level.objective {
+            Some(objective) if !self.level.solved => objective,
+            _ => return,
+        };
+        if objective.is_met(&self.level.world) {
+            self.level.solved = true;
+            self.experience += PUZZLE_EXPERIENCE;
+            self.log(format!(
+                "You have solved the puzzle! You gain {} experience.",
+                PUZZLE_EXPERIENCE
+            ));
+        }
+    }
+
+    fn update_visibility(&mut self) {
+        if let Some(player_coord) = self.level.world.entity_coord(self.player_entity) {
+            let opacity_changes = self.level.world.take_opacity_changes();
+            let darkvision = self.level.world.darkvision(self.player_entity);
+            self.level.visibility_grid.update(
+                player_coord,
+                &self.level.world,
+                &opacity_changes,
+                self.fov.strategy_mut(),
+                darkvision,
+                None,
+            );
+        }
+        self.record_encounters();
+    }
+
+    /// Adds a light which lasts only as long as the animation which adds it. The player's view
+    /// is updated whenever a transient light is added, moved or removed, so it's lit for
+    /// exactly the frames it exists.
+    pub fn add_transient_light(&mut self, coord: Coord, light: Light) -> Option<Entity> {
+        let entity = self.level.world.spawn_transient_light(coord, light)?;
+        self.transient_lights.push(entity);
+        self.update_visibility();
+        Some(entity)
+    }
+
+    pub fn move_transient_light(&mut self, entity: Entity, coord: Coord) {
+        if self.level.world.move_light(entity, coord) {
+            self.update_visibility();
+        }
+    }
+
+    pub fn remove_transient_light(&mut self, entity: Entity) {
+        self.transient_lights.retain(|&light| light != entity);
+        self.level.world.remove_entity(entity);
+        self.update_visibility();
+    }
+
+    /// Animates lights between turns, updating what the player can see if any changed within
+    /// their view. Lights elsewhere are picked up by the next update, so flickering out of
+    /// sight doesn't cause the screen to be drawn again.
+    pub fn tick_lights(&mut self, since_last_tick: Duration) {
+        let changed = self.level.world.update_flickering_lights(since_last_tick);
+        let world = &self.level.world;
+        let in_view = changed.into_iter().any(|entity| {
+            match (
+                world.entity_coord(entity),
+                world.components.light.get(entity),
+            ) {
+                (Some(coord), Some(light)) => self
+                    .level
+                    .visibility_grid
+                    .is_light_in_view(coord, light.vision_distance),
+                _ => false,
+            }
+        });
+        if in_view {
+            self.update_visibility();
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.set_fov_algorithm(settings.fov_algorithm);
+        self.auto_pickup = settings.auto_pickup;
+        self.show_timer = settings.speedrun_timer;
+        self.white_balance = settings.white_balance;
+    }
+
+    pub fn set_fov_algorithm(&mut self, algorithm: FovAlgorithm) {
+        if self.fov.algorithm() != algorithm {
+            self.fov = Fov::new(algorithm);
+            self.level.visibility_grid.clear_fov_cache();
+            self.update_visibility();
+        }
+    }
+
+    /// Adds a journal entry for each NPC that the player can see for the first time
+    fn record_encounters(&mut self) {
+        let level = &mut self.level;
+        let newly_encountered = level
+            .world
+            .all_npcs()
+            .filter(|&(entity, _)| !level.encountered_npcs.contains(entity))
+            .filter(|&(entity, _)| {
+                level
+                    .world
+                    .entity_coord(entity)
+                    .map(|coord| level.visibility_grid.is_currently_visible(coord))
+                    .unwrap_or(false)
+            })
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        for entity in newly_encountered {
+            level.encountered_npcs.insert(entity, ());
+            let components = &level.world.components;
+            if let Some(&monster) = components.tile.get(entity) {
+                let stats = MonsterStats {
+                    max_hit_points: components
+                        .hit_points
+                        .get(entity)
+                        .map(|h| h.max)
+                        .unwrap_or(0),
+                    damage: components.damage.get(entity).cloned().unwrap_or(0),
+                };
+                self.journal
+                    .push(self.turn, GameEvent::Encountered { monster, stats });
+            }
+        }
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        bincode::serialize(self).map_err(io::Error::other)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: SaveFormat) -> io::Result<()> {
+        save_file::write(path, &self.to_bytes()?, format)
+    }
+
+    /// Text rendering of the map as the player currently knows it
+    fn frame_snapshot(&self) -> String {
+        let vis_count = self.level.visibility_grid.count();
+        let width = self.level.world.size().width() as i32;
+        let mut frame = String::new();
+        for (coord, cell) in self.level.visibility_grid.enumerate() {
+            let ch = match cell.visibility(vis_count) {
+                CellVisibility::NeverVisible => ' ',
+                CellVisibility::PreviouslyVisible
+                | CellVisibility::CurrentlyVisibleWithLightColour(_)
+                | CellVisibility::CurrentlyVisibleInDarkness => cell
+                    .tile_layers()
+                    .top()
+                    .map(|EntityTile { tile, .. }| tile_char(tile))
+                    .unwrap_or(' '),
+            };
+            frame.push(ch);
+            if coord.x == width - 1 {
+                frame.push('\n');
+            }
+        }
+        frame
+    }
+
+    pub fn bug_report(&self) -> io::Result<BugReport> {
+        let journal = self
+            .journal
+            .recent(BUG_REPORT_JOURNAL_LENGTH)
+            .iter()
+            .map(|entry| format!("{}: {:?}\n", entry.turn, entry.event))
+            .collect();
+        let info = format!(
+            "version: {}\nseed: {}\nturn: {}\n",
+            env!("CARGO_PKG_VERSION"),
+            self.seed,
+            self.turn
+        );
+        Ok(BugReport {
+            save: self.to_bytes()?,
+            journal,
+            frame: self.frame_snapshot(),
+            info,
+        })
+    }
+
+    /// A summary of the run for its morgue file
+    pub fn morgue(&self) -> String {
+        let outcome = if self.won {
+            "Won by clearing the deepest level"
+        } else {
+            "Died"
+        };
+        let mut lines = vec![
+            format!(
+                "rl1 {} - {} run ({} difficulty)",
+                env!("CARGO_PKG_VERSION"),
+                self.ruleset.mode.name(),
+                self.ruleset.ai_difficulty.name()
+            ),
+            String::new(),
+            format!(
+                "{} on depth {} after {} turns.",
+                outcome,
+                self.dungeon.depth(),
+                self.turn
+            ),
+            format!("Time: {}", format_duration(self.run_timer.elapsed())),
+            format!("Experience: {}", self.experience),
+            format!("Gold: {}", self.gold),
+            format!("Seed: {}", self.seed),
+            format!("Mutators: {}", self.ruleset.mutators.name()),
+            String::new(),
+            "Conducts kept:".to_string(),
+        ];
+        let kept = self.conduct().kept();
+        if kept.is_empty() {
+            lines.push("  none".to_string());
+        }
+        lines.extend(kept.into_iter().map(|conduct| format!("  {}", conduct)));
+        lines.push(String::new());
+        lines.push("Last messages:".to_string());
+        lines.extend(
+            self.message_log
+                .recent(MORGUE_MESSAGES)
+                .iter()
+                .map(|message| format!("  {}", message)),
+        );
+        let mut morgue = lines.join("\n");
+        morgue.push('\n');
+        morgue
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let bytes = save_file::read(path)?;
+        let mut game: Self = bincode::deserialize(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // whatever they were lighting up stopped when the game was saved
+        for light in std::mem::take(&mut game.transient_lights) {
+            game.level.world.remove_entity(light);
+        }
+        Ok(game)
+    }
+
+    pub fn discoveries(&self) -> Discoveries {
+        Discoveries::from_journal(&self.journal)
+    }
+
+    pub fn conduct(&self) -> Conduct {
+        Conduct::from_journal(&self.journal)
+    }
+
+    pub fn message_log(&self) -> &MessageLog {
+        &self.message_log
+    }
+
+    pub fn log(&mut self, message: impl Into<String>) {
+        self.message_log.push(message);
+    }
+
+    pub fn player_coord(&self) -> Option<Coord> {
+        self.level.world.entity_coord(self.player_entity)
+    }
+
+    pub fn annotation(&self, coord: Coord) -> Option<&str> {
+        self.level.annotations.get(&coord).map(|s| s.as_str())
+    }
+
+    /// Labels a cell the player has seen. An empty label removes the annotation.
+    pub fn annotate(&mut self, coord: Coord, label: &str) {
+        if !self.level.visibility_grid.is_known(coord) {
+            return;
+        }
+        let label = label.trim();
+        if label.is_empty() {
+            self.level.annotations.remove(&coord);
+        } else {
+            self.level.annotations.insert(coord, label.to_string());
+        }
+    }
+
+    /// Marks the area around a cell the player has seen for travel to avoid, or clears the
+    /// exclusion centred there
+    pub fn toggle_travel_exclusion(&mut self, coord: Coord) {
+        if !self.level.visibility_grid.is_known(coord) {
+            return;
+        }
+        if self.level.travel_exclusions.toggle(coord) {
+            self.log("Travel will avoid this area.");
+        } else {
+            self.log("Travel will no longer avoid this area.");
+        }
+    }
+
+    /// Each cell the player has seen which travel avoids
+    fn travel_excluded_cells(&self) -> Vec<Coord> {
+        let visibility_grid = &self.level.visibility_grid;
+        self.level
+            .travel_exclusions
+            .iter()
+            .flat_map(|exclusion| {
+                let radius = exclusion.radius as i32;
+                (-radius..=radius)
+                    .flat_map(move |y| {
+                        (-radius..=radius).map(move |x| exclusion.centre + Coord::new(x, y))
+                    })
+                    .filter(move |&coord| exclusion.contains(coord))
+            })
+            .filter(|&coord| visibility_grid.is_known(coord))
+            .collect()
+    }
+
+    /// The closest stairs leading down which the player has seen
+    fn nearest_known_stairs_down(&self) -> Option<Coord> {
+        let player_coord = self.player_coord()?;
+        let world = &self.level.world;
+        world
+            .components
+            .tile
+            .iter()
+            .filter(|&(_, &tile)| tile == Tile::StairsDown)
+            .filter_map(|(entity, _)| world.entity_coord(entity))
+            .filter(|&coord| self.level.visibility_grid.is_known(coord))
+            .min_by_key(|&coord| (coord - player_coord).magnitude2())
+    }
+
+    /// The closest part of the level's unfinished objective which the player has seen
+    fn nearest_known_objective_target(&self) -> Option<Coord> {
+        let player_coord = self.player_coord()?;
+        let objective = self.level.objective.filter(|_| !self.level.solved)?;
+        objective
+            .targets(&self.level.world)
+            .into_iter()
+            .filter(|&coord| self.level.visibility_grid.is_known(coord))
+            .min_by_key(|&coord| (coord - player_coord).magnitude2())
+    }
+
+    fn player_hit_points(&self) -> Option<HitPoints> {
+        self.level
+            .world
+            .components
+            .hit_points
+            .get(self.player_entity)
+            .cloned()
+    }
+
+    fn visibility_grid(&self) -> &VisibilityGrid {
+        &self.level.visibility_grid
+    }
+
+    /// The path the player would take to travel to the destination
+    fn travel_path(&self, destination: Destination) -> Vec<Coord> {
+        self.player_coord()
+            .and_then(|player_coord| {
+                travel::path(
+                    &self.level.visibility_grid,
+                    &self.level.travel_exclusions,
+                    player_coord,
+                    destination,
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    /// What the travel interruption rules are checked against
+    fn travel_status(&self) -> TravelStatus {
+        let world = &self.level.world;
+        let visible_hostiles = world
+            .all_npcs()
+            .filter(|(_, npc)| npc.disposition == Disposition::Hostile)
+            .filter(|&(entity, _)| self.is_visible(entity))
+            .map(|(entity, _)| (entity, self.character_name(entity)))
+            .collect();
+        let item_underfoot = self
+            .player_coord()
+            .and_then(|coord| world.item_at_coord(coord))
+            .map(|item| self.item_name(item).to_string());
+        TravelStatus {
+            visible_hostiles,
+            hit_points: self.player_hit_points(),
+            item_underfoot,
+        }
+    }
+
+    /// The direction the player should step in to travel towards the destination, using
+    /// only what they remember of the level
+    fn travel_direction(&self, destination: Destination) -> Option<Direction> {
+        travel::first_step(
+            &self.level.visibility_grid,
+            &self.level.travel_exclusions,
+            self.player_coord()?,
+            destination,
+        )
+    }
+
+    /// The name of a hostile NPC the player can currently see, if there is one
+    fn visible_hostile_name(&self) -> Option<&'static str> {
+        self.level
+            .world
+            .all_npcs()
+            .filter(|(_, npc)| npc.disposition == Disposition::Hostile)
+            .find(|&(entity, _)| self.is_visible(entity))
+            .map(|(entity, _)| self.character_name(entity))
+    }
+
+    pub fn player_walk(&mut self, direction: Direction) {
+        let player_coord = match self.level.world.entity_coord(self.player_entity) {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        let stumbled = self.level.world.is_confused(self.player_entity)
+            && self.rng.gen_ratio(1, CONFUSED_STUMBLE_CHANCE);
+        let direction = if stumbled {
+            self.log("You stumble in your confusion.");
+            Direction::all()
+                .nth(self.rng.gen_range(0..8))
+                .unwrap_or(direction)
+        } else {
+            direction
+        };
+        // a stumble costs the turn even if it goes nowhere, so it can't be retried for free
+        if !self.player_step(player_coord, direction) && stumbled {
+            self.end_player_turn();
+        }
+    }
+
+    /// Moves, attacks or interacts with whatever is in the direction, returning whether it
+    /// took the player's turn
+    fn player_step(&mut self, player_coord: Coord, direction: Direction) -> bool {
+        let destination = player_coord + direction.coord();
+        if let Some(character) = self.level.world.character_at_coord(destination) {
+            match self.level.world.components.npc.get(character) {
+                Some(npc) if npc.disposition == Disposition::Friendly => {
+                    let name = self.character_name(character);
+                    self.log(format!("The charmed {} is in your way.", name));
+                    false
+                }
+                Some(_) => {
+                    self.character_attack(self.player_entity, character);
+                    self.end_player_turn();
+                    true
+                }
+                None => false,
+            }
+        } else if let Some(spawner) = self.level.world.spawner_at_coord(destination) {
+            self.player_attack_spawner(spawner);
+            self.end_player_turn();
+            true
+        } else if let Some(door) = self
+            .level
+            .world
+            .door_at_coord(destination, DoorState::Closed)
+        {
+            self.character_open_door(self.player_entity, door);
+            self.end_player_turn();
+            true
+        } else if let Some(boulder) = self.level.world.boulder_at_coord(destination) {
+            if self
+                .level
+                .world
+                .push_boulder(boulder, destination + direction.coord())
+            {
+                self.log("You push the boulder.");
+                self.character_move(self.player_entity, destination);
+                self.end_player_turn();
+                true
+            } else {
+                self.log("The boulder won't budge.");
+                false
+            }
+        } else if let Some(brazier) = self.level.world.unlit_brazier_at_coord(destination) {
+            self.level.world.light_brazier(brazier);
+            self.log("You light the brazier.");
+            self.end_player_turn();
+            true
+        } else if self.level.world.is_solid_feature_at_coord(destination) {
+            self.log("You bump into a wall.");
+            false
+        } else if self.level.world.is_walkable_coord(destination)
+            && self.character_move(self.player_entity, destination)
+        {
+            self.player_auto_pick_up();
+            self.end_player_turn();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn player_wait(&mut self) {
+        if let Some(stamina) = self
+            .level
+            .world
+            .components
+            .stamina
+            .get_mut(self.player_entity)
+        {
+            stamina.recover(REST_STAMINA);
+        }
+        self.end_player_turn();
+    }
+
+    fn player_is_confused(&self) -> bool {
+        self.level.world.is_confused(self.player_entity)
+    }
+
+    fn player_stamina(&self) -> Option<Stamina> {
+        self.level
+            .world
+            .components
+            .stamina
+            .get(self.player_entity)
+            .cloned()
+    }
+
+    /// Returns false, telling the player why, if they don't have enough stamina
+    fn player_spend_stamina(&mut self, cost: u32) -> bool {
+        let spent = self
+            .level
+            .world
+            .components
+            .stamina
+            .get_mut(self.player_entity)
+            .map(|stamina| stamina.spend(cost))
+            .unwrap_or(false);
+        if !spent {
+            self.log("You're too tired.");
+        }
+        spent
+    }
+
+    /// Moves up to two cells in one turn. Sprinting only goes through open floor, so it
+    /// never attacks or opens doors.
+    pub fn player_sprint(&mut self, direction: Direction) {
+        let player_coord = match self.player_coord() {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        let world = &self.level.world;
+        let is_clear = |coord: Coord| {
+            world.is_walkable_coord(coord)
+                && !world.is_character_at_coord(coord)
+                && world.spawner_at_coord(coord).is_none()
+        };
+        let first = player_coord + direction.coord();
+        if !is_clear(first) {
+            self.log("There's no room to sprint that way.");
+            return;
+        }
+        let second = first + direction.coord();
+        let second_clear = is_clear(second);
+        if self.player_is_encumbered() {
+            self.log("You're carrying too much to sprint.");
+            return;
+        }
+        if !self.player_spend_stamina(SPRINT_COST) {
+            return;
+        }
+        // springing a trap on the first step stops the sprint
+        let trapped = self.level.world.trap_at_coord(first).is_some();
+        self.character_move(self.player_entity, first);
+        if second_clear && !trapped && self.player_coord().is_some() {
+            self.character_move(self.player_entity, second);
+        }
+        self.player_auto_pick_up();
+        self.end_player_turn();
+    }
+
+    /// An attack on an adjacent NPC which can deal up to twice the player's normal damage
+    pub fn player_power_attack(&mut self, direction: Direction) {
+        let player_coord = match self.player_coord() {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        let victim = match self
+            .level
+            .world
+            .character_at_coord(player_coord + direction.coord())
+            .filter(|&entity| self.level.world.components.npc.contains(entity))
+        {
+            Some(victim) => victim,
+            None => {
+                self.log("There's nothing there to attack.");
+                return;
+            }
+        };
+        if !self.player_spend_stamina(POWER_ATTACK_COST) {
+            return;
+        }
+        let damage = self.main_hand_damage(self.player_entity) * 2;
+        let weapon = self.main_hand_weapon(self.player_entity);
+        self.character_strike(self.player_entity, victim, weapon, damage);
+        self.end_player_turn();
+    }
+
+    pub fn player_pick_up(&mut self) {
+        let player_coord = match self.level.world.entity_coord(self.player_entity) {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        if self.level.world.item_at_coord(player_coord).is_none() {
+            self.log("There is nothing here to pick up.");
+            return;
+        }
+        if let Some(amount) = self.level.world.take_gold(player_coord) {
+            self.gold += amount;
+            self.journal
+                .push(self.turn, GameEvent::PickedUp(Item::Gold));
+            self.log(format!("You pick up {} gold.", amount));
+            self.end_player_turn();
+            return;
+        }
+        if let Some(item) = self.level.world.pick_up_item(self.player_entity) {
+            self.record_item_event(item, GameEvent::PickedUp);
+            self.log(format!("You pick up the {}.", self.item_name(item)));
+            self.end_player_turn();
+        } else {
+            self.log("Your inventory is full.");
+        }
+    }
+
+    /// Picks up the item the player just stepped onto if the auto-pickup settings say to.
+    /// This is part of the move, so it doesn't take a turn of its own.
+    fn player_auto_pick_up(&mut self) {
+        let player_coord = match self.player_coord() {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        let world = &self.level.world;
+        let item = match world
+            .item_at_coord(player_coord)
+            .and_then(|item| world.components.item.get(item))
+        {
+            Some(&item) if self.auto_pickup.picks_up(item) => item,
+            _ => return,
+        };
+        if item == Item::Gold {
+            if let Some(amount) = self.level.world.take_gold(player_coord) {
+                self.gold += amount;
+                self.journal
+                    .push(self.turn, GameEvent::PickedUp(Item::Gold));
+                self.log(format!(
+                    "You pick up {} gold ({} in total).",
+                    amount, self.gold
+                ));
+            }
+        } else if let Some(item) = self.level.world.pick_up_item(self.player_entity) {
+            self.record_item_event(item, GameEvent::PickedUp);
+            self.log(format!("You pick up the {}.", self.item_name(item)));
+        }
+    }
+
+    pub fn player_drop(&mut self, index: usize) {
+        if index >= self.player_inventory().len() {
+            return;
+        }
+        if let Some(item) = self.level.world.drop_item(self.player_entity, index) {
+            self.record_item_event(item, GameEvent::Dropped);
+            self.log(format!("You drop the {}.", self.item_name(item)));
+            self.end_player_turn();
+        } else {
+            self.log("There is no room to drop that here.");
+        }
+    }
+
+    pub fn player_quaff(&mut self, index: usize) {
+        if self.character_drink_potion(self.player_entity, index) {
+            self.end_player_turn();
+        } else if index < self.player_inventory().len() {
+            self.log("You can't drink that.");
+        }
+    }
+
+    /// The cells on the line from the player to the target, each paired with whether a
+    /// thrown item would reach it. Items stop at the first character in their way, and
+    /// can't pass through solid or opaque cells or travel further than the throw range.
+    pub fn projectile_line(&self, target: Coord) -> Vec<(Coord, bool)> {
+        let player_coord = match self.player_coord() {
+            Some(player_coord) => player_coord,
+            None => return Vec::new(),
+        };
+        let world = &self.level.world;
+        let mut reachable = true;
+        line::cells(player_coord, target)
+            .into_iter()
+            .enumerate()
+            .map(|(i, coord)| {
+                if i >= THROW_RANGE
+                    || !coord.is_valid(world.size())
+                    || world.is_solid_feature_at_coord(coord)
+                    || world.get_opacity_at_coord(coord) == 255
+                {
+                    reachable = false;
+                }
+                let cell = (coord, reachable);
+                if world.is_character_at_coord(coord) {
+                    reachable = false;
+                }
+                cell
+            })
+            .collect()
+    }
+
+    /// The directions from the player to each hostile NPC next to them
+    pub fn adjacent_hostile_directions(&self) -> Vec<Direction> {
+        let player_coord = match self.player_coord() {
+            Some(player_coord) => player_coord,
+            None => return Vec::new(),
+        };
+        let world = &self.level.world;
+        Direction::all()
+            .filter(|direction| {
+                world
+                    .character_at_coord(player_coord + direction.coord())
+                    .and_then(|character| world.components.npc.get(character))
+                    .is_some_and(|npc| npc.disposition == Disposition::Hostile)
+            })
+            .collect()
+    }
+
+    /// The closest hostile NPC the player can see, for choosing a default target
+    fn nearest_visible_hostile(&self) -> Option<Coord> {
+        let player_coord = self.player_coord()?;
+        self.level
+            .world
+            .all_npcs()
+            .filter(|(_, npc)| npc.disposition == Disposition::Hostile)
+            .filter(|&(entity, _)| self.is_visible(entity))
+            .filter_map(|(entity, _)| self.level.world.entity_coord(entity))
+            .min_by_key(|&coord| (coord - player_coord).magnitude2())
+    }
+
+    /// Takes an item out of the player's inventory and works out where it will land when
+    /// thrown at the target. Call `land_throw` with the result once its flight has been
+    /// animated.
+    pub fn player_throw(&mut self, index: usize, target: Coord) -> Option<Throw> {
+        let path = self
+            .projectile_line(target)
+            .into_iter()
+            .take_while(|&(_, reachable)| reachable)
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
+        if path.is_empty() {
+            self.log("There's no room to throw anything that way.");
+            return None;
+        }
+        let item = self
+            .level
+            .world
+            .take_inventory_item(self.player_entity, index)?;
+        self.record_item_event(item, |item| GameEvent::Thrown {
+            thrower: Some(Tile::Player),
+            item,
+        });
+        self.log(format!("You throw the {}.", self.item_name(item)));
+        Some(Throw {
+            thrower: self.player_entity,
+            item,
+            path,
+            light: self.level.world.flight_light(item),
+        })
+    }
+
+    pub fn land_throw(&mut self, throw: Throw) {
+        let Throw {
+            thrower,
+            item,
+            path,
+            ..
+        } = throw;
+        let &target = path.last().expect("empty throw path");
+        if let Some(victim) = self.level.world.character_at_coord(target) {
+            if self.level.visibility_grid.is_currently_visible(target) {
+                self.animation_cues.push(AnimationCue::Flash(target));
+            }
+            self.character_damage(thrower, victim, THROW_DAMAGE);
+        }
+        // the item comes to rest in the closest free cell to where it landed
+        let landed = path
+            .iter()
+            .rev()
+            .any(|&coord| self.level.world.place_item(item, coord));
+        if !landed {
+            if let Some(inventory) = self.level.world.components.inventory.get_mut(thrower) {
+                inventory.insert(item);
+            }
+        }
+        self.end_player_turn();
+    }
+
+    fn item_name(&self, item: Entity) -> &'static str {
+        self.level
+            .world
+            .components
+            .item
+            .get(item)
+            .map(|item| item.name())
+            .unwrap_or("item")
+    }
+
+    fn record_item_event(&mut self, item: Entity, f: impl FnOnce(Item) -> GameEvent) {
+        if let Some(&item) = self.level.world.components.item.get(item) {
+            self.journal.push(self.turn, f(item));
+        }
+    }
+
+    /// The names of the items in the player's inventory, noting where equipped items are held
+    pub fn player_inventory(&self) -> Vec<String> {
+        let components = &self.level.world.components;
+        components
+            .inventory
+            .get(self.player_entity)
+            .map(|inventory| {
+                inventory
+                    .items()
+                    .iter()
+                    .filter_map(|&entity| {
+                        let item = components.item.get(entity)?;
+                        Some(match components.equipped.get(entity) {
+                            Some(hand) => format!("{} ({})", item.name(), hand.name()),
+                            None => item.name().to_string(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The kind of the item at an index in the player's inventory
+    fn player_item(&self, index: usize) -> Option<Item> {
+        let world = &self.level.world;
+        let entity = *world
+            .components
+            .inventory
+            .get(self.player_entity)?
+            .items()
+            .get(index)?;
+        world.components.item.get(entity).copied()
+    }
+
+    /// The index of the first item of a kind in the player's inventory
+    fn player_inventory_index(&self, item: Item) -> Option<usize> {
+        let world = &self.level.world;
+        world
+            .components
+            .inventory
+            .get(self.player_entity)?
+            .items()
+            .iter()
+            .position(|&entity| world.components.item.get(entity) == Some(&item))
+    }
+
+    /// Describes an item in the player's inventory. Equippable items are compared with what
+    /// the player holds in the hand it would go in.
+    fn player_item_details(&self, index: usize) -> Option<Vec<String>> {
+        let world = &self.level.world;
+        let entity = *world
+            .components
+            .inventory
+            .get(self.player_entity)?
+            .items()
+            .get(index)?;
+        let item = *world.components.item.get(entity)?;
+        let mut lines = vec![item.name().to_string(), item.description().to_string()];
+        let grip = match item.grip() {
+            Some(grip) => grip,
+            None => {
+                lines.push(format!("Weight: {}", item.weight()));
+                return Some(lines);
+            }
+        };
+        if let Some(hand) = world.components.equipped.get(entity) {
+            lines.push(format!("You hold it in your {}.", hand.name()));
+            return Some(lines);
+        }
+        let hands: &[Hand] = match grip {
+            Grip::OneHanded => &[Hand::Main, Hand::Both],
+            Grip::Shield => &[Hand::Off, Hand::Both],
+            Grip::TwoHanded => &[Hand::Both, Hand::Main, Hand::Off],
+        };
+        let mut held = hands
+            .iter()
+            .filter_map(|&hand| world.equipped_item(self.player_entity, hand))
+            .map(Some)
+            .collect::<Vec<_>>();
+        if held.is_empty() {
+            held.push(None);
+        }
+        lines.push(String::new());
+        lines.extend(item_comparison(item, &held));
+        Some(lines)
+    }
+
+    pub fn player_wield(&mut self, index: usize) {
+        let item = match self
+            .level
+            .world
+            .components
+            .inventory
+            .get(self.player_entity)
+        {
+            Some(inventory) => match inventory.items().get(index) {
+                Some(&item) => item,
+                None => return,
+            },
+            None => return,
+        };
+        let name = self.item_name(item);
+        match self.level.world.toggle_equipped(self.player_entity, index) {
+            Some(EquipOutcome::Equipped(hand)) => {
+                self.log(format!("You take the {} in your {}.", name, hand.name()));
+                self.end_player_turn();
+            }
+            Some(EquipOutcome::Unequipped) => {
+                self.log(format!("You put away the {}.", name));
+                self.end_player_turn();
+            }
+            None => self.log(format!("You can't wield the {}.", name)),
+        }
+    }
+
+    /// Closes an adjacent open door, as long as nothing is in the doorway
+    pub fn player_close_door(&mut self) {
+        let player_coord = match self.player_coord() {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        let world = &self.level.world;
+        let door = CardinalDirection::all().find_map(|direction| {
+            let coord = player_coord + direction.coord();
+            world
+                .door_at_coord(coord, DoorState::Open)
+                .filter(|_| !world.is_character_at_coord(coord))
+                .filter(|_| world.item_at_coord(coord).is_none())
+        });
+        if let Some(door) = door {
+            self.level.world.close_door(door);
+            self.log("You close the door.");
+            self.end_player_turn();
+        } else {
+            self.log("There is no open door here to close.");
+        }
+    }
+
+    fn player_is_on(&self, tile: Tile) -> bool {
+        self.player_coord()
+            .and_then(|coord| self.level.world.spatial_table.layers_at(coord))
+            .and_then(|layers| layers.feature)
+            .and_then(|feature| self.level.world.components.tile.get(feature))
+            .map(|&t| t == tile)
+            .unwrap_or(false)
+    }
+
+    pub fn player_descend(&mut self) {
+        let endless = self.ruleset.mode == RunMode::Endless;
+        if !self.player_is_on(Tile::StairsDown) || !(endless || self.dungeon.has_level_below()) {
+            self.log("There are no stairs leading down here.");
+            return;
+        }
+        if !self.dungeon.has_level_below() {
+            let depth = self.dungeon.depth() + 1;
+            let mut cave = cave::generate(depth, &mut self.rng);
+            self.ruleset.mutators.apply_to_world(&mut cave.world);
+            self.dungeon.push(Level::new(cave.world, depth));
+        }
+        let character_data = self.level.world.take_character(self.player_entity);
+        self.dungeon.descend(&mut self.level);
+        self.run_timer.reach_depth(self.dungeon.depth(), self.turn);
+        if endless {
+            self.dungeon.discard_levels_above(ENDLESS_LEVELS_KEPT_ABOVE);
+        }
+        self.enter_level(character_data, Tile::StairsUp);
+        self.log(format!("You descend to depth {}.", self.dungeon.depth()));
+        self.report_level_feeling();
+    }
+
+    pub fn player_ascend(&mut self) {
+        if !self.player_is_on(Tile::StairsUp) || !self.dungeon.has_level_above() {
+            self.log("There are no stairs leading up here.");
+            return;
+        }
+        let character_data = self.level.world.take_character(self.player_entity);
+        self.dungeon.ascend(&mut self.level);
+        self.enter_level(character_data, Tile::StairsDown);
+        self.log(format!("You ascend to depth {}.", self.dungeon.depth()));
+        self.report_level_feeling();
+    }
+
+    /// Places the player on the given stairs in the current level. If a character is
+    /// standing on the stairs the player arrives next to them instead.
+    fn enter_level(&mut self, character_data: CharacterData, arrival_stairs: Tile) {
+        let world = &mut self.level.world;
+        let stairs_coord = world
+            .find_feature(arrival_stairs)
+            .expect("level has no arrival stairs");
+        let coord = if world.is_character_at_coord(stairs_coord) {
+            world
+                .nearest_free_coord(stairs_coord, 1)
+                .expect("no room for player")
+        } else {
+            stairs_coord
+        };
+        self.player_entity = world.insert_character(coord, character_data);
+        // the cache may have been computed with a different FOV algorithm
+        self.level.visibility_grid.clear_fov_cache();
+        self.update_visibility();
+    }
+
+    fn end_player_turn(&mut self) {
+        let encumbered = self.player_is_encumbered();
+        if encumbered != self.encumbered {
+            self.encumbered = encumbered;
+            if encumbered {
+                self.log("You are overburdened and slow down.");
+            } else {
+                self.log("You are no longer overburdened.");
+            }
+        }
+        let cost = if encumbered {
+            ENCUMBERED_ACTION_COST
+        } else {
+            ACTION_COST
+        };
+        self.end_player_action(cost);
+    }
+
+    fn player_carried_weight(&self) -> u32 {
+        self.level.world.carried_weight(self.player_entity)
+    }
+
+    fn player_carry_capacity(&self) -> u32 {
+        let max_stamina = self
+            .player_stamina()
+            .map(|stamina| stamina.max)
+            .unwrap_or(0);
+        BASE_CARRY_CAPACITY + max_stamina * CARRY_CAPACITY_PER_STAMINA
+    }
+
+    fn player_is_encumbered(&self) -> bool {
+        self.player_carried_weight() > self.player_carry_capacity()
+    }
+
+    /// Runs the scheduler after the player takes an action of the given cost. Time passes,
+    /// with each character gaining energy at its own speed and NPCs acting whenever they
+    /// have enough, until the player has enough energy to act again.
+    fn end_player_action(&mut self, cost: u32) {
+        self.level.world.spend_energy(self.player_entity, cost);
+        self.turn += 1;
+        if let Some(player_coord) = self.player_coord() {
+            self.ai_context
+                .update_player_coord(player_coord, &self.level.world);
+        }
+        // a player without a speed would never get to act again
+        let player_has_speed = self
+            .level
+            .world
+            .components
+            .speed
+            .contains(self.player_entity);
+        while player_has_speed
+            && self.player_coord().is_some()
+            && !self.level.world.has_energy(self.player_entity, ACTION_COST)
+        {
+            self.time += 1;
+            self.level
+                .world
+                .tick_energy(self.ruleset.mutators.monster_speed_factor());
+            for entity in self.level.world.tick_statuses() {
+                if self.is_visible(entity) {
+                    let name = self.character_name(entity);
+                    self.log(format!("The {} is no longer charmed.", name));
+                }
+            }
+            let mut ready = self
+                .level
+                .world
+                .all_npcs()
+                .map(|(entity, _)| entity)
+                .filter(|&entity| self.level.world.has_energy(entity, ACTION_COST))
+                .collect::<Vec<_>>();
+            // whoever has the most energy saved up has been waiting longest, so goes first
+            ready.sort_by_key(|&entity| std::cmp::Reverse(self.level.world.energy(entity)));
+            for entity in ready {
+                // an earlier action this tick may have killed or frozen the character
+                if !self.level.world.has_energy(entity, ACTION_COST) {
+                    continue;
+                }
+                self.level.world.spend_energy(entity, ACTION_COST);
+                self.npc_act(entity);
+                if self.player_coord().is_none() {
+                    break;
+                }
+            }
+            if self.time.is_multiple_of(TICKS_PER_TURN) {
+                self.spawner_turn();
+                self.arena_turn();
+                self.darkness_turn();
+            }
+        }
+        self.check_objective();
+        self.check_victory();
+        self.update_visibility();
+    }
+
+    /// Runs through the dungeon are won by clearing its deepest level of monsters and
+    /// spawners. Endless and arena runs go on until the player dies.
+    fn check_victory(&mut self) {
+        if self.won
+            || self.ruleset.mode != RunMode::Dungeon
+            || self.dungeon.has_level_below()
+            || self.player_coord().is_none()
+        {
+            return;
+        }
+        let world = &self.level.world;
+        let cleared = world
+            .all_npcs()
+            .all(|(_, npc)| npc.disposition != Disposition::Hostile)
+            && world.all_spawners().next().is_none();
+        if cleared {
+            self.won = true;
+            self.log("You have cleared the deepest level of the dungeon. You win!");
+        }
+    }
+
+    /// What's kept of the run for the statistics screen once it's finished
+    pub fn stored_run(&self) -> StoredRun {
+        StoredRun {
+            won: self.won,
+            turns: self.turn,
+            journal: self.journal.clone(),
+        }
+    }
+
+    /// How the run went, for the hall of fame
+    pub fn run_result(&self) -> RunResult {
+        RunResult {
+            won: self.won,
+            depth: self.dungeon.depth(),
+            turns: self.turn,
+            conduct: self.conduct(),
+        }
+    }
+
+    /// How much danger the player is in from the hostiles they can currently see, from 0 to
+    /// 1. Each hostile adds more the closer and more threatening it is.
+    pub fn danger(&self) -> f32 {
+        let player_coord = match self.player_coord() {
+            Some(player_coord) => player_coord,
+            None => return 0.0,
+        };
+        let world = &self.level.world;
+        let total = world
+            .all_npcs()
+            .filter(|(_, npc)| npc.disposition == Disposition::Hostile)
+            .filter_map(|(entity, _)| {
+                Some((
+                    world.entity_coord(entity)?,
+                    *world.components.tile.get(entity)?,
+                ))
+            })
+            .filter(|&(coord, _)| self.level.visibility_grid.is_currently_visible(coord))
+            .map(|(coord, tile)| {
+                feeling::threat(tile)
+                    * DANGER_RANGE.saturating_sub(coord.manhattan_distance(player_coord))
+            })
+            .sum::<u32>();
+        (total as f32 / FULL_DANGER as f32).min(1.0)
+    }
+
+    pub fn presence_status(&self) -> PresenceStatus {
+        PresenceStatus {
+            depth: self.dungeon.depth(),
+            turn: self.turn,
+        }
+    }
+
+    /// The weapon a character holds in its main hand, or in both hands
+    fn main_hand_weapon(&self, entity: Entity) -> Option<Item> {
+        let world = &self.level.world;
+        world
+            .equipped_item(entity, Hand::Main)
+            .or_else(|| world.equipped_item(entity, Hand::Both))
+    }
+
+    /// The most damage a character's attacks deal, including the weapon in its main hand
+    fn main_hand_damage(&self, entity: Entity) -> u32 {
+        let base = self
+            .level
+            .world
+            .components
+            .damage
+            .get(entity)
+            .cloned()
+            .unwrap_or(0);
+        let weapon = self.main_hand_weapon(entity);
+        base + weapon.map(|item| item.damage_bonus()).unwrap_or(0)
+    }
+
+    /// Attacks with the main hand weapon, followed by the off hand weapon if the attacker is
+    /// dual wielding and the victim survived
+    fn character_attack(&mut self, attacker: Entity, victim: Entity) {
+        let damage = self.main_hand_damage(attacker);
+        let weapon = self.main_hand_weapon(attacker);
+        self.character_strike(attacker, victim, weapon, damage);
+        let off_hand = self
+            .level
+            .world
+            .equipped_item(attacker, Hand::Off)
+            .filter(|item| item.grip() == Some(Grip::OneHanded));
+        if let Some(off_hand) = off_hand {
+            if self.level.world.entity_coord(victim).is_some() {
+                let base = self
+                    .level
+                    .world
+                    .components
+                    .damage
+                    .get(attacker)
+                    .cloned()
+                    .unwrap_or(0);
+                let damage = (base + off_hand.damage_bonus()) / DUAL_WIELD_DAMAGE_DIVISOR;
+                self.character_strike(attacker, victim, Some(off_hand), damage);
+            }
+        }
+    }
+
+    /// A single blow with a weapon, or without one, which can deal up to `max_damage` unless
+    /// the victim blocks it
+    fn character_strike(
+        &mut self,
+        attacker: Entity,
+        victim: Entity,
+        weapon: Option<Item>,
+        max_damage: u32,
+    ) {
+        if self.character_blocks(victim) {
+            let attacker_name = self.character_name(attacker);
+            let victim_name = self.character_name(victim);
+            if victim == self.player_entity {
+                self.log(format!("You block the {}'s attack.", attacker_name));
+            } else if attacker == self.player_entity {
+                self.log(format!("The {} blocks your attack.", victim_name));
+            }
+            return;
+        }
+        let damage = self.roll_damage(max_damage);
+        self.character_damage(attacker, victim, damage);
+        let killed = self.level.world.entity_coord(victim).is_none();
+        if attacker == self.player_entity && killed {
+            self.journal.push(self.turn, GameEvent::KilledWith(weapon));
+        }
+    }
+
+    fn character_blocks(&mut self, entity: Entity) -> bool {
+        self.level.world.equipped_item(entity, Hand::Off) == Some(Item::Shield)
+            && self.rng.gen_ratio(1, SHIELD_BLOCK_CHANCE)
+    }
+
+    /// Rolls the damage of an attack which can deal up to `max` damage
+    fn roll_damage(&mut self, max: u32) -> u32 {
+        if max == 0 {
+            return 0;
+        }
+        self.rng.gen_range(max.div_ceil(2)..=max)
+    }
+
+    fn character_damage(&mut self, attacker: Entity, victim: Entity, damage: u32) {
+        let attacker_tile = self.level.world.components.tile.get(attacker).cloned();
+        let victim_tile = self.level.world.components.tile.get(victim).cloned();
+        self.journal.push(
+            self.turn,
+            GameEvent::Attack {
+                attacker: attacker_tile,
+                victim: victim_tile,
+                damage,
+            },
+        );
+        let name = |tile: Option<Tile>| tile.map(|t| t.name()).unwrap_or("something");
+        let victim_coord = self.level.world.entity_coord(victim);
+        if let Some(target) = victim_coord {
+            self.cue_animation(
+                attacker,
+                AnimationCue::Lunge {
+                    entity: attacker,
+                    target,
+                },
+            );
+        }
+        if self.level.world.damage_character(victim, damage) {
+            if let (Some(coord), Some(tile)) = (victim_coord, victim_tile) {
+                self.cue_animation(victim, AnimationCue::Death { coord, tile });
+            }
+            self.journal.push(
+                self.turn,
+                GameEvent::Died {
+                    victim: victim_tile,
+                    killer: attacker_tile,
+                },
+            );
+            self.character_drop_loot(victim);
+            self.level.world.remove_entity(victim);
+            if victim == self.player_entity {
+                self.log("You die...");
+            } else if attacker == self.player_entity {
+                self.log(format!("You kill the {}.", name(victim_tile)));
+            } else {
+                self.log(format!("The {} dies.", name(victim_tile)));
+            }
+        } else {
+            if damage > 0 {
+                self.cue_animation(victim, AnimationCue::Hurt(victim));
+            }
+            if victim == self.player_entity {
+                self.log(format!("The {} hits you.", name(attacker_tile)));
+            } else if attacker == self.player_entity {
+                self.log(format!("You hit the {}.", name(victim_tile)));
+            } else if self.is_visible(victim) {
+                self.log(format!(
+                    "The {} hits the {}.",
+                    name(attacker_tile),
+                    name(victim_tile)
+                ));
+            }
+        }
+    }
+
+    /// Queues an animation of something which happened to an entity, if the player can see it
+    fn cue_animation(&mut self, entity: Entity, cue: AnimationCue) {
+        if self.is_visible(entity) {
+            self.animation_cues.push(cue);
+        }
+    }
+
+    fn take_animation_cues(&mut self) -> Vec<AnimationCue> {
+        std::mem::take(&mut self.animation_cues)
+    }
+
+    /// Scatters what an NPC was carrying around where it died, along with any loot rolled
+    /// from its loot table
+    fn character_drop_loot(&mut self, entity: Entity) {
+        if !self.level.world.components.npc.contains(entity) {
+            return;
+        }
+        let coord = match self.level.world.entity_coord(entity) {
+            Some(coord) => coord,
+            None => return,
+        };
+        for item in self.level.world.take_all_inventory_items(entity) {
+            if !self.level.world.place_item_near(item, coord) {
+                self.level.world.remove_entity(item);
+            }
+        }
+        let monster = match self.level.world.components.tile.get(entity) {
+            Some(&monster) => monster,
+            None => return,
+        };
+        let loot = (0..self.ruleset.mutators.loot_rolls())
+            .flat_map(|_| self.loot_tables.roll(monster, &mut self.rng))
+            .collect::<Vec<_>>();
+        for loot in loot {
+            match loot {
+                Loot::Item(item) => {
+                    self.level.world.spawn_item_near(coord, item);
+                }
+                Loot::Gold(amount) => self.level.world.drop_gold(coord, amount),
+            }
+        }
+    }
+
+    fn player_attack_spawner(&mut self, entity: Entity) {
+        let world = &self.level.world;
+        let spawner = match world.components.spawner.get(entity) {
+            Some(&spawner) => spawner,
+            None => return,
+        };
+        let damage = self.main_hand_damage(self.player_entity);
+        let damage = self.roll_damage(damage);
+        let world = &mut self.level.world;
+        let name = spawner.tile().name();
+        if !world.damage_character(entity, damage) {
+            self.log(format!("You hit the {}.", name));
+            return;
+        }
+        world.remove_entity(entity);
+        self.experience += spawner.experience();
+        self.journal.push(self.turn, GameEvent::Destroyed(spawner));
+        self.log(format!(
+            "You destroy the {}. You gain {} experience.",
+            name,
+            spawner.experience()
+        ));
+    }
+
+    /// Counts down each spawner, creating a monster next to any which are ready as long as
+    /// the level isn't already at its population cap
+    fn spawner_turn(&mut self) {
+        let ready = self.level.world.all_spawners().collect::<Vec<_>>();
+        for (entity, spawner) in ready {
+            let world = &mut self.level.world;
+            let countdown = match world.components.spawn_countdown.get_mut(entity) {
+                Some(countdown) => countdown,
+                None => continue,
+            };
+            *countdown = countdown.saturating_sub(1);
+            if *countdown > 0 {
+                continue;
+            }
+            *countdown = spawner.interval();
+            if world.all_npcs().count() >= LEVEL_POPULATION_CAP {
+                continue;
+            }
+            let coord = match world
+                .entity_coord(entity)
+                .and_then(|coord| world.nearest_free_coord(coord, 1))
+            {
+                Some(coord) => coord,
+                None => continue,
+            };
+            if world.spawn_monster(coord, spawner.monster()).is_some()
+                && self.level.visibility_grid.is_currently_visible(coord)
+            {
+                self.log(format!(
+                    "A {} emerges from the {}.",
+                    spawner.monster().name(),
+                    spawner.tile().name()
+                ));
+            }
+        }
+    }
+
+    /// Moves a character to an adjacent coord, springing any trap there. The player and NPCs
+    /// all move through here so they're subject to the same rules. Returns false if the
+    /// character couldn't move.
+    fn character_move(&mut self, entity: Entity, destination: Coord) -> bool {
+        if self
+            .level
+            .world
+            .spatial_table
+            .update_coord(entity, destination)
+            .is_err()
+        {
+            return false;
+        }
+        if let Some(trap) = self.level.world.trap_at_coord(destination) {
+            if !self.level.world.floats(entity) {
+                self.character_spring_trap(entity, trap);
+            }
+        }
+        true
+    }
+
+    fn character_spring_trap(&mut self, entity: Entity, trap: Trap) {
+        let visible = self.is_visible(entity);
+        let victim_tile = self.level.world.components.tile.get(entity).cloned();
+        let victim_coord = self.level.world.entity_coord(entity);
+        let name = self.character_name(entity);
+        if entity == self.player_entity {
+            self.log("You step on the spikes!");
+        } else if visible {
+            self.log(format!("The {} steps on the spikes.", name));
+        }
+        self.journal.push(
+            self.turn,
+            GameEvent::Attack {
+                attacker: Some(Tile::Trap),
+                victim: victim_tile,
+                damage: trap.damage(),
+            },
+        );
+        if self.level.world.damage_character(entity, trap.damage()) {
+            if let (Some(coord), Some(tile)) = (victim_coord, victim_tile) {
+                self.cue_animation(entity, AnimationCue::Death { coord, tile });
+            }
+            self.journal.push(
+                self.turn,
+                GameEvent::Died {
+                    victim: victim_tile,
+                    killer: Some(Tile::Trap),
+                },
+            );
+            self.character_drop_loot(entity);
+            self.level.world.remove_entity(entity);
+            if entity == self.player_entity {
+                self.log("You die...");
+            } else if visible {
+                self.log(format!("The {} dies.", name));
+            }
+        } else {
+            self.cue_animation(entity, AnimationCue::Hurt(entity));
+        }
+    }
+
+    fn character_open_door(&mut self, entity: Entity, door: Entity) {
+        self.level.world.open_door(door);
+        if entity == self.player_entity {
+            self.log("You open the door.");
+        } else if self.is_visible(entity) {
+            let name = self.character_name(entity);
+            self.log(format!("The {} opens a door.", name));
+        }
+    }
+
+    /// Returns false if the item at the index isn't a potion
+    fn character_drink_potion(&mut self, entity: Entity, index: usize) -> bool {
+        if !self.level.world.drink_potion(entity, index) {
+            return false;
+        }
+        let drinker = self.level.world.components.tile.get(entity).cloned();
+        self.journal.push(self.turn, GameEvent::Drank { drinker });
+        if entity == self.player_entity {
+            self.log("You drink the potion. You feel better.");
+        } else if self.is_visible(entity) {
+            let name = self.character_name(entity);
+            self.log(format!("The {} drinks a potion.", name));
+        }
+        true
+    }
+
+    pub fn apply_effect(&mut self, effect: Effect) {
+        let player_coord = match self.level.world.entity_coord(self.player_entity) {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        self.journal.push(self.turn, GameEvent::Effect(effect));
+        match effect {
+            Effect::SpawnMonster => {
+                if let Some(coord) = self.level.world.nearest_free_coord(player_coord, 3) {
+                    self.level.world.spawn_goblin(coord);
+                    self.log("Chat summons a goblin!");
+                }
+            }
+            Effect::Heal => {
+                if let Some(hit_points) = self
+                    .level
+                    .world
+                    .components
+                    .hit_points
+                    .get_mut(self.player_entity)
+                {
+                    hit_points.current = hit_points.max;
+                }
+                self.log("Chat heals you.");
+            }
+            Effect::GiveItem => {
+                if self
+                    .level
+                    .world
+                    .spawn_item_in_inventory(self.player_entity, Item::Potion)
+                    .is_some()
+                {
+                    self.log("Chat gives you a potion.");
+                }
+            }
+            Effect::FreezeEnemy => {
+                let target = self
+                    .nearest_visible_hostile()
+                    .and_then(|coord| self.level.world.character_at_coord(coord));
+                if let Some(entity) = target {
+                    self.level.world.freeze(entity, FREEZE_TURNS);
+                    let name = self.character_name(entity);
+                    self.log(format!("Chat freezes the {} in place!", name));
+                }
+            }
+            Effect::ExtraTurn => {
+                self.level.world.grant_extra_action(self.player_entity);
+                self.log("Chat speeds you up. You can act twice!");
+            }
+            Effect::Confuse => {
+                self.level
+                    .world
+                    .confuse(self.player_entity, CONFUSION_TURNS);
+                self.log("Chat makes your head spin!");
+            }
+            Effect::CharmEnemy => {
+                let target = self
+                    .nearest_visible_hostile()
+                    .and_then(|coord| self.level.world.character_at_coord(coord));
+                if let Some(entity) = target {
+                    self.level.world.charm(entity, CHARM_TURNS);
+                    let name = self.character_name(entity);
+                    self.log(format!("Chat charms the {} to your side!", name));
+                }
+            }
+        }
+        self.update_visibility();
+    }
+
+    /// Runs a line typed into the debug console, logging its output
+    pub fn run_console_command(&mut self, line: &str) {
+        let command = match Command::parse(line) {
+            Ok(command) => command,
+            Err(e) => {
+                self.log(e);
+                return;
+            }
+        };
+        if let Err(e) = self.run_command(command) {
+            self.log(e);
+        }
+        self.update_visibility();
+    }
+
+    fn run_command(&mut self, command: Command) -> Result<(), String> {
+        match command {
+            Command::List => {
+                let characters = std::iter::once(self.player_entity)
+                    .chain(self.level.world.all_npcs().map(|(entity, _)| entity))
+                    .collect::<Vec<_>>();
+                for entity in characters {
+                    let coord = match self.level.world.entity_coord(entity) {
+                        Some(coord) => coord,
+                        None => continue,
+                    };
+                    let id = self.tags.id(self.dungeon.depth(), entity);
+                    let name = self.character_name(entity);
+                    self.log(format!("{}: {} at {},{}", id, name, coord.x, coord.y));
+                }
+            }
+            Command::Tags => {
+                let depth = self.dungeon.depth();
+                let lines = self
+                    .tags
+                    .iter()
+                    .map(|(name, level_entity)| {
+                        let coord = Some(level_entity.entity)
+                            .filter(|_| level_entity.depth == depth)
+                            .and_then(|entity| self.level.world.entity_coord(entity));
+                        match coord {
+                            Some(coord) => format!(
+                                "@{}: {} at {},{}",
+                                name,
+                                self.character_name(level_entity.entity),
+                                coord.x,
+                                coord.y
+                            ),
+                            None => format!("@{}: not on this level", name),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if lines.is_empty() {
+                    self.log("No entities are tagged.");
+                }
+                for line in lines {
+                    self.log(line);
+                }
+            }
+            Command::Tag { target, name } => {
+                let (entity, _) = self.console_target(&target)?;
+                self.log(format!(
+                    "Tagged the {} as @{}.",
+                    self.character_name(entity),
+                    name
+                ));
+                self.tags.tag(name, self.dungeon.depth(), entity);
+            }
+            Command::Untag(name) => {
+                if !self.tags.untag(&name) {
+                    return Err(format!("no tag @{}", name));
+                }
+            }
+            Command::Kill(target) => {
+                let (entity, _) = self.console_target(&target)?;
+                if entity == self.player_entity {
+                    return Err("the console won't kill the player".to_string());
+                }
+                self.log(format!(
+                    "The {} is struck down.",
+                    self.character_name(entity)
+                ));
+                self.character_drop_loot(entity);
+                self.level.world.remove_entity(entity);
+            }
+            Command::Goto(target) => {
+                let (_, coord) = self.console_target(&target)?;
+                let destination = self
+                    .level
+                    .world
+                    .nearest_free_coord(coord, 1)
+                    .ok_or("there's nowhere to stand near there")?;
+                self.character_move(self.player_entity, destination);
+            }
+        }
+        Ok(())
+    }
+
+    /// The character a console command refers to, as long as it's on the current level
+    fn console_target(&self, target: &console::Target) -> Result<(Entity, Coord), String> {
+        let entity = self.tags.resolve(target, self.dungeon.depth())?;
+        let coord = self
+            .level
+            .world
+            .entity_coord(entity)
+            .ok_or("that entity isn't on this level")?;
+        if self.level.world.character_at_coord(coord) != Some(entity) {
+            return Err("that entity isn't a character".to_string());
+        }
+        Ok((entity, coord))
+    }
+
+    fn npc_act(&mut self, entity: Entity) {
+        let player_coord = match self.level.world.entity_coord(self.player_entity) {
+            Some(player_coord) => player_coord,
+            None => return,
+        };
+        let behaviours = match self.level.world.components.npc.get(entity) {
+            Some(npc) if npc.disposition == Disposition::Friendly => {
+                self.friendly_npc_act(entity);
+                return;
+            }
+            Some(npc) => npc
+                .behaviours
+                .intersection(self.ruleset.ai_difficulty.behaviours()),
+            None => return,
+        };
+        let action =
+            self.ai_context
+                .choose_action(entity, &self.level.world, behaviours, player_coord);
+        match action {
+            NpcAction::Wait => (),
+            NpcAction::Move(direction) => {
+                if let Some(coord) = self.level.world.entity_coord(entity) {
+                    self.character_move(entity, coord + direction.coord());
+                }
+            }
+            NpcAction::Attack => self.character_attack(entity, self.player_entity),
+            NpcAction::OpenDoor(door) => self.character_open_door(entity, door),
+            NpcAction::DrinkPotion(index) => {
+                self.character_drink_potion(entity, index);
+            }
+        }
+    }
+
+    /// Charmed NPCs stand their ground, attacking any hostile NPC which comes next to them
+    fn friendly_npc_act(&mut self, entity: Entity) {
+        let target = self
+            .level
+            .world
+            .entity_coord(entity)
+            .and_then(|coord| self.level.world.adjacent_hostile(coord));
+        if let Some(target) = target {
+            self.character_attack(entity, target);
+        }
+    }
+
+    fn is_visible(&self, entity: Entity) -> bool {
+        self.level
+            .world
+            .entity_coord(entity)
+            .map(|coord| self.level.visibility_grid.is_currently_visible(coord))
+            .unwrap_or(false)
+    }
+
+    fn character_name(&self, entity: Entity) -> &'static str {
+        self.level
+            .world
+            .components
+            .tile
+            .get(entity)
+            .map(|tile| tile.name())
+            .unwrap_or("something")
+    }
+}
+
+/// Presses of the same key closer together than this are taken to be the OS repeating a
+/// held key rather than separate presses
+const AUTO_REPEAT_GAP: Duration = Duration::from_millis(60);
+
+/// Slows the repeats of a held key to the configured repeat interval. Frontends don't report
+/// keys being released, so a key counts as held while its repeats keep arriving in quick
+/// succession, and deliberate double taps always get through.
+#[derive(Default)]
+struct KeyRepeat {
+    last: Option<KeyboardInput>,
+    /// Time since the last key press, whether or not it was accepted
+    since_last: Duration,
+    since_accepted: Duration,
+}
+
+impl KeyRepeat {
+    fn tick(&mut self, since_last_tick: Duration) {
+        self.since_last += since_last_tick;
+        self.since_accepted += since_last_tick;
+    }
+
+    fn accept(&mut self, keyboard_input: KeyboardInput, interval: Duration) -> bool {
+        let held = self.last == Some(keyboard_input) && self.since_last < AUTO_REPEAT_GAP;
+        self.last = Some(keyboard_input);
+        self.since_last = Duration::ZERO;
+        if held && self.since_accepted < interval {
+            return false;
+        }
+        self.since_accepted = Duration::ZERO;
+        true
+    }
+}
+
+/// Number of closed prompts and menus which are remembered to be reopened
+const UI_HISTORY_LEN: usize = 8;
+
+/// What the player has recently looked at and which prompts they've closed, so they can be
+/// brought back without navigating to them again
+#[derive(Default)]
+struct UiHistory {
+    /// Most recently closed last
+    prompts: Vec<UiState>,
+    last_examined: Option<Entity>,
+}
+
+impl UiHistory {
+    fn push_prompt(&mut self, ui_state: UiState) {
+        if self.prompts.len() == UI_HISTORY_LEN {
+            self.prompts.remove(0);
+        }
+        self.prompts.push(ui_state);
+    }
+
+    fn pop_prompt(&mut self) -> Option<UiState> {
+        self.prompts.pop()
+    }
+
+    /// Forgets everything, since none of it refers to a game which has just been replaced
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Time since the player last took a turn, for passing turns automatically when the turn
+/// timer is on
+#[derive(Default)]
+struct TurnClock {
+    turn: u64,
+    elapsed: Duration,
+}
+
+impl TurnClock {
+    /// Returns true if the interval has passed without the player taking a turn
+    fn tick(&mut self, turn: u64, since_last_tick: Duration, interval: Duration) -> bool {
+        if turn != self.turn {
+            self.turn = turn;
+            self.elapsed = Duration::ZERO;
+        }
+        self.elapsed += since_last_tick;
+        if self.elapsed < interval {
+            return false;
+        }
+        self.elapsed = Duration::ZERO;
+        true
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InventoryMode {
+    View,
+    Drop,
+    Quaff,
+    Throw,
+    Wield,
+}
+
+/// Actions which cost stamina, waiting for the player to choose a direction
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Exertion {
+    Sprint,
+    PowerAttack,
+}
+
+const MAX_ANNOTATION_LENGTH: usize = 24;
+
+#[derive(Clone, Copy)]
+enum MainMenuEntry {
+    NewGame,
+    NewEndlessGame,
+    NewArenaGame,
+    Continue,
+    HallOfFame,
+    Statistics,
+    Bestiary,
+    Quit,
+}
+
+#[derive(Clone, Copy)]
+enum PauseMenuEntry {
+    Resume,
+    Save,
+    MainMenu,
+    Quit,
+}
+
+#[derive(Clone, Copy)]
+enum GameOverEntry {
+    NewGame,
+    MainMenu,
+    Quit,
+}
+
+fn main_menu() -> Menu<MainMenuEntry> {
+    let mut entries = vec![
+        (MainMenuEntry::NewGame, "New Game"),
+        (MainMenuEntry::NewEndlessGame, "Endless Descent"),
+        (MainMenuEntry::NewArenaGame, "Arena"),
+    ];
+    if Path::new(SAVE_PATH).exists() {
+        entries.push((MainMenuEntry::Continue, "Continue"));
+    }
+    entries.push((MainMenuEntry::HallOfFame, "Hall of Fame"));
+    entries.push((MainMenuEntry::Statistics, "Statistics"));
+    entries.push((MainMenuEntry::Bestiary, "Bestiary"));
+    entries.push((MainMenuEntry::Quit, "Quit"));
+    Menu::new(entries)
+}
+
+/// Actions offered when the player right-clicks a cell of the map
+#[derive(Clone, Copy)]
+enum CellAction {
+    WalkHere,
+    ThrowAt,
+    Examine,
+    Annotate,
+}
+
+fn pause_menu() -> Menu<PauseMenuEntry> {
+    Menu::new(vec![
+        (PauseMenuEntry::Resume, "Resume"),
+        (PauseMenuEntry::Save, "Save"),
+        (PauseMenuEntry::MainMenu, "Main Menu"),
+        (PauseMenuEntry::Quit, "Quit"),
+    ])
+}
+
+fn game_over_menu() -> Menu<GameOverEntry> {
+    Menu::new(vec![
+        (GameOverEntry::NewGame, "New Game"),
+        (GameOverEntry::MainMenu, "Main Menu"),
+        (GameOverEntry::Quit, "Quit"),
+    ])
+}
+
+enum UiState {
+    MainMenu {
+        menu: Menu<MainMenuEntry>,
+        error: Option<String>,
+    },
+    /// Reached from the main menu, so there's no game in progress behind it
+    HallOfFame,
+    /// Also reached from the main menu
+    Statistics,
+    /// The bestiary reached from the main menu, which goes back to it when closed
+    MainMenuBestiary,
+    /// Choosing the mutators for a new run, also reached from the main menu
+    NewGame {
+        mode: RunMode,
+        mutators: Mutators,
+    },
+    Pause(Menu<PauseMenuEntry>),
+    GameOver(Menu<GameOverEntry>),
+    Game,
+    Options,
+    /// Choosing exceptions to the auto-pickup categories, reached from the options
+    AutoPickupRules {
+        selected: usize,
+    },
+    Inventory(InventoryMode),
+    /// Examining one item in the inventory
+    ItemDetails {
+        index: usize,
+    },
+    Discoveries {
+        query: String,
+    },
+    Bestiary,
+    /// The whole level as the player knows it, centred on `focus`
+    LevelMap {
+        focus: Coord,
+    },
+    Look {
+        cursor: Coord,
+    },
+    Annotate {
+        cursor: Coord,
+    },
+    AnnotateText {
+        coord: Coord,
+        text: String,
+    },
+    /// Typing a debug console command
+    Console {
+        text: String,
+    },
+    Aim {
+        index: usize,
+        cursor: Coord,
+    },
+    Travel {
+        cursor: Coord,
+    },
+    Exert(Exertion),
+    /// Waiting for the player to choose which of several adjacent hostiles to attack
+    AutoFight,
+    /// Pages of story, after which the player is taken to `then`
+    Cutscene {
+        sequence: Sequence,
+        then: AfterCutscene,
+    },
+    /// The quick menu, either to use the item in a slot or, when opened from the inventory,
+    /// to assign the item at `assign` to one
+    QuickMenu {
+        assign: Option<usize>,
+    },
+    /// Opened by right-clicking a cell, listing what can be done there
+    ContextMenu {
+        coord: Coord,
+        menu: Menu<CellAction>,
+    },
+}
+
+impl UiState {
+    /// A fresh copy of this state for the UI history, if it's a prompt or menu which can be
+    /// reopened later. States which refer to an inventory slot are left out, as the item in
+    /// the slot may have changed by the time they'd be reopened.
+    fn recallable(&self) -> Option<Self> {
+        let ui_state = match self {
+            Self::Options => Self::Options,
+            &Self::AutoPickupRules { selected } => Self::AutoPickupRules { selected },
+            &Self::Inventory(mode) => Self::Inventory(mode),
+            Self::Discoveries { query } => Self::Discoveries {
+                query: query.clone(),
+            },
+            Self::Bestiary => Self::Bestiary,
+            &Self::LevelMap { focus } => Self::LevelMap { focus },
+            &Self::Look { cursor } => Self::Look { cursor },
+            &Self::Annotate { cursor } => Self::Annotate { cursor },
+            &Self::Travel { cursor } => Self::Travel { cursor },
+            &Self::Exert(exertion) => Self::Exert(exertion),
+            Self::QuickMenu { assign: None } => Self::QuickMenu { assign: None },
+            _ => return None,
+        };
+        Some(ui_state)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum AfterCutscene {
+    Game,
+    GameOver,
+}
+
+impl AfterCutscene {
+    fn ui_state(self) -> UiState {
+        match self {
+            Self::Game => UiState::Game,
+            Self::GameOver => UiState::GameOver(game_over_menu()),
+        }
+    }
+}
+
+fn cursor_direction(keyboard_input: KeyboardInput) -> Option<CardinalDirection> {
+    match keyboard_input {
+        KeyboardInput::Left => Some(CardinalDirection::West),
+        KeyboardInput::Right => Some(CardinalDirection::East),
+        KeyboardInput::Up => Some(CardinalDirection::North),
+        KeyboardInput::Down => Some(CardinalDirection::South),
+        _ => None,
+    }
+}
+
+struct GameComponent {
+    setup: Setup,
+    settings: Settings,
+    save_format: SaveFormat,
+    controls: Controls,
+    key_repeat: KeyRepeat,
+    turn_clock: TurnClock,
+    ui_state: UiState,
+    chat: Option<Chat>,
+    presence: Presence,
+    music: Music,
+    cutscenes: Cutscenes,
+    bestiary: Bestiary,
+    hall_of_fame: HallOfFame,
+    /// Where finished runs are stored for the statistics screen
+    profile: Profile,
+    /// Worked out from the profile's stored runs when the statistics screen is opened
+    statistics: Vec<String>,
+    /// Number of journal entries which have been recorded in the bestiary
+    journal_cursor: usize,
+    /// A thrown item in flight, which lands when its animation completes
+    animation: Option<(Projectile, Throw)>,
+    character_animations: CharacterAnimations,
+    /// Set when something may have changed what's on screen. Frames are only drawn when
+    /// something has, so while the game waits for the player the last frame is left on
+    /// screen rather than being drawn again.
+    dirty: Cell<bool>,
+    /// The visibility count and screen size when the last frame was drawn. The visibility
+    /// grid is updated whenever the map or its lighting changes, so a new count means the
+    /// map needs drawing again.
+    drawn: Cell<Option<(u64, Size)>>,
+    /// Time since the screen last needed drawing
+    idle: Duration,
+    flashes: Flashes,
+    /// Set while the player is exploring or travelling, which takes a step each tick
+    travel: Option<Travel>,
+    hud: Hud,
+    ui_history: UiHistory,
+    /// Where to record new games, if they're being recorded
+    record_path: Option<PathBuf>,
+    recorder: Option<Recorder>,
+    /// Set while a recording is being played back, during which the player's keys are ignored
+    replay: Option<Replay>,
+}
+
+impl GameComponent {
+    fn new(
+        chat: Option<Chat>,
+        save_format: SaveFormat,
+        setup: Setup,
+        profile: Profile,
+        record_path: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            setup,
+            profile,
+            settings: Settings::load(),
+            save_format,
+            controls: Controls::load(),
+            key_repeat: KeyRepeat::default(),
+            turn_clock: TurnClock::default(),
+            ui_state: UiState::MainMenu {
+                menu: main_menu(),
+                error: None,
+            },
+            chat,
+            presence: Presence::connect(),
+            music: Music::start(),
+            cutscenes: Cutscenes::default(),
+            bestiary: Bestiary::load(),
+            hall_of_fame: HallOfFame::load(),
+            statistics: Vec::new(),
+            journal_cursor: 0,
+            animation: None,
+            character_animations: CharacterAnimations::default(),
+            dirty: Cell::new(true),
+            drawn: Cell::new(None),
+            idle: Duration::ZERO,
+            flashes: Flashes::default(),
+            travel: None,
+            hud: Hud,
+            ui_history: UiHistory::default(),
+            record_path,
+            recorder: None,
+            replay: None,
+        }
+    }
+
+    /// Starts playing back a recording, returning the game in the state it was recorded from
+    fn start_replay(&mut self, replay: Replay) -> Game {
+        let header = &replay.header;
+        self.settings = header.settings;
+        let mut game = Game::new(
+            Ruleset {
+                seed: Some(header.seed),
+                ..header.ruleset
+            },
+            &self.setup,
+        );
+        game.apply_settings(&self.settings);
+        game.log("Replaying a recording. Press escape to take over.");
+        self.ui_state = UiState::Game;
+        self.replay = Some(replay);
+        game
+    }
+
+    fn new_game(&mut self, state: &mut Game, mode: RunMode) {
+        let ruleset = Ruleset {
+            mode,
+            ..state.ruleset
+        };
+        *state = Game::new(ruleset, &self.setup);
+        state.apply_settings(&self.settings);
+        self.journal_cursor = 0;
+        self.animation = None;
+        self.travel = None;
+        self.ui_history.clear();
+        self.ui_state = self.cutscene(self.cutscenes.intro(), AfterCutscene::Game);
+        self.start_recording(state);
+    }
+
+    /// Opens the screen for choosing mutators before starting a new run, starting with the
+    /// ones chosen last time
+    fn choose_mutators(&mut self, state: &Game, mode: RunMode) {
+        self.ui_state = UiState::NewGame {
+            mode,
+            mutators: state.ruleset.mutators,
+        };
+    }
+
+    fn update_new_game(&mut self, state: &mut Game, keyboard_input: KeyboardInput) {
+        let (mode, mutators) = match &mut self.ui_state {
+            UiState::NewGame { mode, mutators } => (*mode, mutators),
+            _ => return,
+        };
+        match keyboard_input {
+            KeyboardInput::Char(ch @ '1'..='9') => {
+                let index = ch as usize - '1' as usize;
+                if let Some(&mutator) = Mutator::ALL.get(index) {
+                    mutators.toggle(mutator);
+                }
+            }
+            keys::RETURN => {
+                // the new game takes the rest of its ruleset from the current one
+                state.ruleset.mutators = *mutators;
+                self.new_game(state, mode);
+            }
+            _ => (),
+        }
+    }
+
+    /// Shows a sequence before moving on to `then`. Cutscenes are skipped during replays, as
+    /// the keys pressed during them aren't recorded.
+    fn cutscene(&self, sequence: Option<Sequence>, then: AfterCutscene) -> UiState {
+        match sequence {
+            Some(sequence) if self.replay.is_none() => UiState::Cutscene { sequence, then },
+            _ => then.ui_state(),
+        }
+    }
+
+    fn update_cutscene(&mut self, keyboard_input: KeyboardInput) {
+        let (sequence, then) = match &mut self.ui_state {
+            UiState::Cutscene { sequence, then } => (sequence, *then),
+            _ => return,
+        };
+        if let keys::RETURN | KeyboardInput::Char(' ') = keyboard_input {
+            if !sequence.advance() {
+                self.ui_state = then.ui_state();
+            }
+        }
+    }
+
+    fn start_recording(&mut self, state: &mut Game) {
+        self.recorder = None;
+        let path = match self.record_path.as_ref() {
+            Some(path) if self.replay.is_none() => path,
+            _ => return,
+        };
+        let header = Header {
+            seed: state.seed,
+            ruleset: state.ruleset,
+            settings: self.settings,
+        };
+        match Recorder::create(path, &header) {
+            Ok(recorder) => self.recorder = Some(recorder),
+            Err(e) => state.log(format!("Failed to start recording: {}", e)),
+        }
+    }
+
+    fn record_input(&mut self, state: &mut Game, keyboard_input: KeyboardInput) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.record(state.turn, keyboard_input) {
+                state.log(format!("Failed to record input: {}", e));
+                self.recorder = None;
+            }
+        }
+    }
+
+    /// Feeds recorded key presses back into the game as they come due. Returns `Some(())` if
+    /// a replayed key quit the game.
+    fn tick_replay(&mut self, state: &mut Game, since_last_tick: Duration) -> Option<()> {
+        self.replay.as_mut()?.tick(since_last_tick);
+        // keys pressed during an animation were ignored when recording
+        while self.animation.is_none() {
+            let replay = self.replay.as_mut()?;
+            let recorded_input = match replay.next_due() {
+                Some(recorded_input) => recorded_input,
+                None => break,
+            };
+            if replay.check_diverged(&recorded_input, state.turn) {
+                state.log("The replay no longer matches the recording.");
+            }
+            if self
+                .handle_keyboard_input(state, recorded_input.keyboard_input)
+                .is_some()
+            {
+                return Some(());
+            }
+        }
+        if self.replay.as_ref().is_some_and(Replay::is_finished) {
+            self.replay = None;
+            state.log("Replay finished.");
+        }
+        None
+    }
+
+    /// Returns `Some(())` if the player chose to quit
+    fn update_main_menu(&mut self, state: &mut Game, keyboard_input: KeyboardInput) -> Option<()> {
+        let entry = match &mut self.ui_state {
+            UiState::MainMenu { menu, .. } => menu.update(keyboard_input)?,
+            _ => return None,
+        };
+        match entry {
+            MainMenuEntry::NewGame => self.choose_mutators(state, RunMode::Dungeon),
+            MainMenuEntry::NewEndlessGame => self.choose_mutators(state, RunMode::Endless),
+            MainMenuEntry::NewArenaGame => self.choose_mutators(state, RunMode::Arena),
+            MainMenuEntry::Continue => match Game::load(SAVE_PATH) {
+                Ok(game) => {
+                    *state = game;
+                    state.apply_settings(&self.settings);
+                    self.journal_cursor = state.journal().len();
+                    self.ui_history.clear();
+                    self.recorder = None;
+                    self.ui_state = UiState::Game;
+                }
+                Err(e) => {
+                    self.ui_state = UiState::MainMenu {
+                        menu: main_menu(),
+                        error: Some(format!("Failed to load game: {}", e)),
+                    }
+                }
+            },
+            MainMenuEntry::HallOfFame => self.ui_state = UiState::HallOfFame,
+            MainMenuEntry::Statistics => {
+                self.statistics = self.profile.statistics().lines();
+                self.ui_state = UiState::Statistics;
+            }
+            MainMenuEntry::Bestiary => self.ui_state = UiState::MainMenuBestiary,
+            MainMenuEntry::Quit => return Some(()),
+        }
+        None
+    }
+
+    /// Returns `Some(())` if the player chose to quit
+    fn update_pause(&mut self, state: &mut Game, keyboard_input: KeyboardInput) -> Option<()> {
+        let entry = match &mut self.ui_state {
+            UiState::Pause(menu) => menu.update(keyboard_input)?,
+            _ => return None,
+        };
+        match entry {
+            PauseMenuEntry::Resume => self.ui_state = UiState::Game,
+            PauseMenuEntry::Save => {
+                match state.save(SAVE_PATH, self.save_format) {
+                    Ok(()) => state.log("Game saved."),
+                    Err(e) => state.log(format!("Failed to save game: {}", e)),
+                }
+                self.ui_state = UiState::Game;
+            }
+            PauseMenuEntry::MainMenu => {
+                self.ui_state = UiState::MainMenu {
+                    menu: main_menu(),
+                    error: None,
+                }
+            }
+            PauseMenuEntry::Quit => return Some(()),
+        }
+        None
+    }
+
+    /// Returns `Some(())` if the player chose to quit
+    fn update_game_over(&mut self, state: &mut Game, keyboard_input: KeyboardInput) -> Option<()> {
+        let entry = match &mut self.ui_state {
+            UiState::GameOver(menu) => menu.update(keyboard_input)?,
+            _ => return None,
+        };
+        match entry {
+            GameOverEntry::NewGame => self.new_game(state, state.ruleset.mode),
+            GameOverEntry::MainMenu => {
+                self.ui_state = UiState::MainMenu {
+                    menu: main_menu(),
+                    error: None,
+                }
+            }
+            GameOverEntry::Quit => return Some(()),
+        }
+        None
+    }
+
+    /// Escape backs out of whatever screen is open, pausing the game if nothing is
+    fn escape(&mut self) -> Option<()> {
+        match self.ui_state {
+            UiState::MainMenu { .. } => return Some(()),
+            UiState::HallOfFame
+            | UiState::Statistics
+            | UiState::MainMenuBestiary
+            | UiState::NewGame { .. } => {
+                self.ui_state = UiState::MainMenu {
+                    menu: main_menu(),
+                    error: None,
+                }
+            }
+            UiState::GameOver(_) => (),
+            UiState::Game => self.ui_state = UiState::Pause(pause_menu()),
+            UiState::Cutscene { then, .. } => self.ui_state = then.ui_state(),
+            _ => self.ui_state = UiState::Game,
+        }
+        None
+    }
+
+    fn set_animation_speed(&mut self, animation_speed: AnimationSpeed) {
+        self.settings.animation_speed = animation_speed;
+    }
+
+    /// Writes the settings to disk if they differ from `previous`, so changes to them are
+    /// kept between runs
+    fn save_changed_settings(&self, previous: Settings) {
+        if self.settings != previous {
+            if let Err(e) = self.settings.save() {
+                eprintln!("failed to save settings: {}", e);
+            }
+        }
+    }
+
+    fn update_game(&mut self, state: &mut Game, keyboard_input: KeyboardInput) {
+        let app_input = match self.controls.get(keyboard_input) {
+            Some(app_input) => app_input,
+            None => return,
+        };
+        match app_input {
+            AppInput::Move(direction) => state.player_walk(direction),
+            AppInput::Wait => state.player_wait(),
+            AppInput::Sprint => self.ui_state = UiState::Exert(Exertion::Sprint),
+            AppInput::PowerAttack => self.ui_state = UiState::Exert(Exertion::PowerAttack),
+            AppInput::Options => self.ui_state = UiState::Options,
+            AppInput::PickUp => state.player_pick_up(),
+            AppInput::CloseDoor => state.player_close_door(),
+            AppInput::Descend => state.player_descend(),
+            AppInput::Ascend => state.player_ascend(),
+            AppInput::BugReport => match state.bug_report().and_then(|r| r.write()) {
+                Ok(path) => state.log(format!("Wrote bug report to {}.", path.display())),
+                Err(e) => state.log(format!("Failed to write bug report: {}", e)),
+            },
+            AppInput::Inventory => self.ui_state = UiState::Inventory(InventoryMode::View),
+            AppInput::Drop => self.ui_state = UiState::Inventory(InventoryMode::Drop),
+            AppInput::Quaff => self.ui_state = UiState::Inventory(InventoryMode::Quaff),
+            AppInput::Throw => self.ui_state = UiState::Inventory(InventoryMode::Throw),
+            AppInput::Wield => self.ui_state = UiState::Inventory(InventoryMode::Wield),
+            AppInput::AutoFight => {
+                let targets = state.adjacent_hostile_directions();
+                match targets.as_slice() {
+                    [] if state.visible_hostile_name().is_some() => {
+                        state.log("No enemy is within reach.")
+                    }
+                    [] => state.log("There are no enemies in sight."),
+                    &[direction] => state.player_walk(direction),
+                    _ => self.ui_state = UiState::AutoFight,
+                }
+            }
+            AppInput::QuickMenu => self.ui_state = UiState::QuickMenu { assign: None },
+            AppInput::Console => {
+                self.ui_state = UiState::Console {
+                    text: String::new(),
+                }
+            }
+            AppInput::Fire => {
+                if state.player_inventory().is_empty() {
+                    state.log("You have nothing to throw.");
+                } else {
+                    self.start_aim(state, 0);
+                }
+            }
+            AppInput::Discoveries => {
+                self.ui_state = UiState::Discoveries {
+                    query: String::new(),
+                }
+            }
+            AppInput::Bestiary => self.ui_state = UiState::Bestiary,
+            AppInput::LevelMap => {
+                if let Some(focus) = state.player_coord() {
+                    self.ui_state = UiState::LevelMap { focus };
+                }
+            }
+            AppInput::Explore => self.start_travel(state, Destination::Unexplored),
+            AppInput::Travel => {
+                if let Some(cursor) = state.player_coord() {
+                    self.ui_state = UiState::Travel { cursor };
+                }
+            }
+            AppInput::Look => {
+                if let Some(cursor) = state.player_coord() {
+                    self.ui_state = UiState::Look { cursor };
+                }
+            }
+            AppInput::Annotate => {
+                if let Some(cursor) = state.player_coord() {
+                    self.ui_state = UiState::Annotate { cursor };
+                }
+            }
+            AppInput::ToggleChat => {
+                if let Some(chat) = self.chat.as_mut() {
+                    chat.toggle();
+                }
+            }
+            AppInput::Save => match state.save(SAVE_PATH, self.save_format) {
+                Ok(()) => state.log("Game saved."),
+                Err(e) => state.log(format!("Failed to save game: {}", e)),
+            },
+            AppInput::Load => match Game::load(SAVE_PATH) {
+                Ok(game) => {
+                    *state = game;
+                    state.apply_settings(&self.settings);
+                    self.journal_cursor = state.journal().len();
+                    self.ui_history.clear();
+                    if self.recorder.take().is_some() {
+                        state.log("Recording stopped, as loaded games can't be replayed.");
+                    }
+                    state.log("Game loaded.");
+                }
+                Err(e) => state.log(format!("Failed to load game: {}", e)),
+            },
+            AppInput::RecallExamine => {
+                let coord = self
+                    .ui_history
+                    .last_examined
+                    .and_then(|entity| state.level.world.entity_coord(entity))
+                    .filter(|&coord| state.visibility_grid().is_known(coord));
+                match coord {
+                    Some(cursor) => self.ui_state = UiState::Look { cursor },
+                    None if self.ui_history.last_examined.is_some() => {
+                        state.log("What you last examined is nowhere to be found.")
+                    }
+                    None => state.log("You haven't examined anything yet."),
+                }
+            }
+            AppInput::RecallPrompt => match self.ui_history.pop_prompt() {
+                Some(ui_state) => self.ui_state = ui_state,
+                None => state.log("There's no prompt to go back to."),
+            },
+            AppInput::AnimationFaster => {
+                self.set_animation_speed(self.settings.animation_speed.faster())
+            }
+            AppInput::AnimationSlower => {
+                self.set_animation_speed(self.settings.animation_speed.slower())
+            }
+        }
+    }
+
+    fn update_options(&mut self, state: &mut Game, keyboard_input: KeyboardInput) {
+        match keyboard_input {
+            KeyboardInput::Char('f') => {
+                self.settings.fov_algorithm = self.settings.fov_algorithm.next();
+                state.set_fov_algorithm(self.settings.fov_algorithm);
+            }
+            KeyboardInput::Char('t') => {
+                self.settings.turn_timer = self.settings.turn_timer.next();
+            }
+            KeyboardInput::Char('s') => {
+                self.settings.speedrun_timer = !self.settings.speedrun_timer;
+                state.apply_settings(&self.settings);
+            }
+            KeyboardInput::Char('n') => {
+                let interrupts = &mut self.settings.travel_interrupts;
+                interrupts.new_monster = !interrupts.new_monster;
+            }
+            KeyboardInput::Char('h') => {
+                let interrupts = &mut self.settings.travel_interrupts;
+                interrupts.low_health = interrupts.low_health.next();
+            }
+            KeyboardInput::Char('i') => {
+                let interrupts = &mut self.settings.travel_interrupts;
+                interrupts.item = !interrupts.item;
+            }
+            KeyboardInput::Char(ch @ '1'..='9') => {
+                let index = ch as usize - '1' as usize;
+                if let Some(&category) = ItemCategory::ALL.get(index) {
+                    self.settings.auto_pickup.toggle(category);
+                    state.apply_settings(&self.settings);
+                }
+            }
+            KeyboardInput::Left => self.set_animation_speed(self.settings.animation_speed.slower()),
+            KeyboardInput::Right => {
+                self.set_animation_speed(self.settings.animation_speed.faster())
+            }
+            KeyboardInput::Char('[') => {
+                self.settings.white_balance = self.settings.white_balance.cooler();
+                state.apply_settings(&self.settings);
+            }
+            KeyboardInput::Char(']') => {
+                self.settings.white_balance = self.settings.white_balance.warmer();
+                state.apply_settings(&self.settings);
+            }
+            KeyboardInput::Char('a') => self.ui_state = UiState::AutoPickupRules { selected: 0 },
+            KeyboardInput::Char('o') | keys::RETURN => self.ui_state = UiState::Game,
+            _ => (),
+        }
+    }
+
+    fn update_auto_pickup_rules(
+        &mut self,
+        state: &mut Game,
+        selected: usize,
+        keyboard_input: KeyboardInput,
+    ) {
+        let count = Item::ALL.len();
+        match keyboard_input {
+            KeyboardInput::Up => {
+                self.ui_state = UiState::AutoPickupRules {
+                    selected: (selected + count - 1) % count,
+                }
+            }
+            KeyboardInput::Down => {
+                self.ui_state = UiState::AutoPickupRules {
+                    selected: (selected + 1) % count,
+                }
+            }
+            KeyboardInput::Char(' ') | keys::RETURN => {
+                self.settings
+                    .auto_pickup
+                    .cycle_item_rule(Item::ALL[selected]);
+                state.apply_settings(&self.settings);
+            }
+            KeyboardInput::Char('a') => self.ui_state = UiState::Options,
+            _ => (),
+        }
+    }
+
+    fn update_look(&mut self, state: &Game, cursor: Coord, keyboard_input: KeyboardInput) {
+        if let Some(direction) = cursor_direction(keyboard_input) {
+            let cursor = cursor + direction.coord();
+            if state.visibility_grid().is_known(cursor) {
+                self.ui_state = UiState::Look { cursor };
+            }
+            return;
+        }
+        if let KeyboardInput::Char('x') | keys::RETURN = keyboard_input {
+            self.ui_state = UiState::Game;
+        }
+    }
+
+    fn update_level_map(&mut self, state: &Game, focus: Coord, keyboard_input: KeyboardInput) {
+        if let Some(direction) = cursor_direction(keyboard_input) {
+            self.ui_state = UiState::LevelMap {
+                focus: level_map::pan(state, focus, direction),
+            };
+            return;
+        }
+        match keyboard_input {
+            KeyboardInput::Char('.') => {
+                if let Some(focus) = state.player_coord() {
+                    self.ui_state = UiState::LevelMap { focus };
+                }
+            }
+            KeyboardInput::Char('M') | keys::RETURN => self.ui_state = UiState::Game,
+            _ => (),
+        }
+    }
+
+    fn update_annotate(&mut self, state: &Game, cursor: Coord, keyboard_input: KeyboardInput) {
+        if let Some(direction) = cursor_direction(keyboard_input) {
+            let cursor = cursor + direction.coord();
+            if cursor.is_valid(state.level.world.size()) {
+                self.ui_state = UiState::Annotate { cursor };
+            }
+            return;
+        }
+        match keyboard_input {
+            keys::RETURN if state.visibility_grid().is_known(cursor) => {
+                let text = state.annotation(cursor).unwrap_or("").to_string();
+                self.ui_state = UiState::AnnotateText {
+                    coord: cursor,
+                    text,
+                };
+            }
+            KeyboardInput::Char('N') => self.ui_state = UiState::Game,
+            _ => (),
+        }
+    }
+
+    fn update_travel(&mut self, state: &mut Game, cursor: Coord, keyboard_input: KeyboardInput) {
+        if let Some(direction) = cursor_direction(keyboard_input) {
+            let cursor = cursor + direction.coord();
+            if cursor.is_valid(state.level.world.size()) {
+                self.ui_state = UiState::Travel { cursor };
+            }
+            return;
+        }
+        match keyboard_input {
+            keys::RETURN if state.visibility_grid().is_known(cursor) => {
+                self.ui_state = UiState::Game;
+                self.start_travel(state, Destination::Coord(cursor));
+            }
+            KeyboardInput::Char('>') => {
+                if let Some(cursor) = state.nearest_known_stairs_down() {
+                    self.ui_state = UiState::Travel { cursor };
+                }
+            }
+            KeyboardInput::Char('e') => state.toggle_travel_exclusion(cursor),
+            KeyboardInput::Char('T') => self.ui_state = UiState::Game,
+            _ => (),
+        }
+    }
+
+    fn update_exert(
+        &mut self,
+        state: &mut Game,
+        exertion: Exertion,
+        keyboard_input: KeyboardInput,
+    ) {
+        if let Some(AppInput::Move(direction)) = self.controls.get(keyboard_input) {
+            match exertion {
+                Exertion::Sprint => state.player_sprint(direction),
+                Exertion::PowerAttack => state.player_power_attack(direction),
+            }
+            self.ui_state = UiState::Game;
+        } else if keyboard_input == keys::RETURN {
+            self.ui_state = UiState::Game;
+        }
+    }
+
+    fn update_auto_fight(&mut self, state: &mut Game, keyboard_input: KeyboardInput) {
+        if let Some(AppInput::Move(direction)) = self.controls.get(keyboard_input) {
+            if state.adjacent_hostile_directions().contains(&direction) {
+                state.player_walk(direction);
+                self.ui_state = UiState::Game;
+            }
+        } else if keyboard_input == keys::RETURN {
+            self.ui_state = UiState::Game;
+        }
+    }
+
+    /// Choosing a direction in the quick menu uses the item in that slot, or assigns the item
+    /// the menu was opened for to it. Terminals don't report keys being released, so rather
+    /// than being held open the menu stays open until a direction is chosen.
+    fn update_quick_menu(
+        &mut self,
+        state: &mut Game,
+        assign: Option<usize>,
+        keyboard_input: KeyboardInput,
+    ) {
+        let direction = match self.controls.get(keyboard_input) {
+            Some(AppInput::Move(direction)) => direction,
+            _ => {
+                if let keys::RETURN | KeyboardInput::Char('Q') = keyboard_input {
+                    self.ui_state = UiState::Game;
+                }
+                return;
+            }
+        };
+        if let Some(index) = assign {
+            if let Some(item) = state.player_item(index) {
+                state.quick_slots.assign(direction, item);
+            }
+            self.ui_state = UiState::Inventory(InventoryMode::View);
+            return;
+        }
+        let item = match state.quick_slots.get(direction) {
+            Some(item) => item,
+            None => return,
+        };
+        self.ui_state = UiState::Game;
+        let index = match state.player_inventory_index(item) {
+            Some(index) => index,
+            None => {
+                state.log(format!("You don't have a {}.", item.name()));
+                return;
+            }
+        };
+        match QuickUse::for_item(item) {
+            QuickUse::Quaff => state.player_quaff(index),
+            QuickUse::Wield => state.player_wield(index),
+            QuickUse::Throw => self.start_aim(state, index),
+        }
+    }
+
+    fn start_travel(&mut self, state: &mut Game, destination: Destination) {
+        let interrupts = self.settings.travel_interrupts;
+        match Travel::start(destination, interrupts, &state.travel_status()) {
+            Ok(travel) => self.travel = Some(travel),
+            Err(message) => state.log(message),
+        }
+    }
+
+    /// Takes the next step towards the travel destination when it's due. Travel stops when
+    /// the player arrives, can't find a way, or one of the interruption rules applies.
+    fn tick_travel(&mut self, state: &mut Game, since_last_tick: Duration) {
+        let step_duration = self.settings.animation_speed.scale(TRAVEL_STEP_DURATION);
+        if !matches!(self.ui_state, UiState::Game) || self.animation.is_some() {
+            return;
+        }
+        let travel = match self.travel.as_mut() {
+            Some(travel) => travel,
+            None => return,
+        };
+        if !travel.tick(since_last_tick, step_duration) {
+            return;
+        }
+        if let Some(message) = travel.interruption(&state.travel_status()) {
+            state.log(message);
+            self.travel = None;
+            return;
+        }
+        let destination = travel.destination;
+        if let Destination::Coord(coord) = destination {
+            if state.player_coord() == Some(coord) {
+                self.travel = None;
+                return;
+            }
+        }
+        let direction = match state.travel_direction(destination) {
+            Some(direction) => direction,
+            None => {
+                match destination {
+                    Destination::Unexplored => state.log("There's nowhere left to explore."),
+                    Destination::Coord(_) => state.log("You don't know a way there."),
+                }
+                self.travel = None;
+                return;
+            }
+        };
+        let turn = state.turn;
+        state.player_walk(direction);
+        if state.turn == turn {
+            // the step was blocked, so trying again would get nowhere
+            self.travel = None;
+        }
+    }
+
+    /// Whether what's on screen changes over time without any input, so every frame must be
+    /// drawn
+    fn is_animating(&self, state: &Game) -> bool {
+        self.animation.is_some()
+            || !self.character_animations.is_empty()
+            || self.travel.is_some()
+            || self.replay.is_some()
+            || self.chat.is_some()
+            || state.show_timer
+            || matches!(self.ui_state, UiState::Cutscene { .. })
+    }
+
+    fn needs_drawing(&self, state: &Game, size: Size) -> bool {
+        self.dirty.get() || self.drawn.get() != Some((state.visibility_grid().count(), size))
+    }
+
+    /// Holds up each frame once nothing on screen has changed for a while. chargrid_wgpu
+    /// presents a frame on every tick whether or not anything was drawn, so not drawing
+    /// alone saves little GPU time.
+    fn throttle_when_idle(&mut self, state: &Game, size: Size, since_last_tick: Duration) {
+        if self.needs_drawing(state, size) {
+            self.idle = Duration::ZERO;
+            return;
+        }
+        self.idle += since_last_tick;
+        if self.idle >= IDLE_DELAY {
+            std::thread::sleep(IDLE_FRAME_SLEEP);
+        }
+    }
+
+    /// Passes the player's turn if the turn timer runs out while they're deciding what to do
+    fn tick_turn_timer(&mut self, state: &mut Game, since_last_tick: Duration) {
+        let interval = match self.settings.turn_timer.interval() {
+            Some(interval) => interval,
+            None => return,
+        };
+        let waiting_for_player = matches!(self.ui_state, UiState::Game)
+            && self.animation.is_none()
+            && self.travel.is_none()
+            && state.player_coord().is_some();
+        if waiting_for_player && self.turn_clock.tick(state.turn, since_last_tick, interval) {
+            state.player_wait();
+        }
+    }
+
+    /// Handles a key pressed by the player or replayed from a recording. Returns `Some(())`
+    /// if the player chose to quit.
+    fn handle_keyboard_input(
+        &mut self,
+        state: &mut Game,
+        keyboard_input: KeyboardInput,
+    ) -> Option<()> {
+        let previous = self.ui_state.recallable();
+        let exit = self.dispatch_keyboard_input(state, keyboard_input);
+        self.update_ui_history(state, previous);
+        exit
+    }
+
+    /// Remembers the prompt which was just closed, if one was, and what's being examined
+    fn update_ui_history(&mut self, state: &Game, previous: Option<UiState>) {
+        for ui_state in [previous.as_ref(), Some(&self.ui_state)]
+            .into_iter()
+            .flatten()
+        {
+            if let &UiState::Look { cursor } = ui_state {
+                if let Some(entity) = examined_entity(state, cursor) {
+                    self.ui_history.last_examined = Some(entity);
+                }
+            }
+        }
+        if let (UiState::Game, Some(previous)) = (&self.ui_state, previous) {
+            self.ui_history.push_prompt(previous);
+        }
+    }
+
+    fn dispatch_keyboard_input(
+        &mut self,
+        state: &mut Game,
+        keyboard_input: KeyboardInput,
+    ) -> Option<()> {
+        if self.travel.take().is_some() {
+            // any key interrupts travel
+            return None;
+        }
+        if keyboard_input == keys::ESCAPE {
+            return self.escape();
+        }
+        let exit = match self.ui_state {
+            UiState::MainMenu { .. } => self.update_main_menu(state, keyboard_input),
+            UiState::Pause(_) => self.update_pause(state, keyboard_input),
+            UiState::GameOver(_) => self.update_game_over(state, keyboard_input),
+            _ => None,
+        };
+        if exit.is_some() {
+            return exit;
+        }
+        match self.ui_state {
+            UiState::MainMenu { .. } | UiState::Pause(_) | UiState::GameOver(_) => (),
+            UiState::HallOfFame | UiState::Statistics | UiState::MainMenuBestiary => {
+                if keyboard_input == keys::RETURN {
+                    self.escape();
+                }
+            }
+            UiState::NewGame { .. } => self.update_new_game(state, keyboard_input),
+            UiState::Game => {
+                let settings = self.settings;
+                self.update_game(state, keyboard_input);
+                self.save_changed_settings(settings);
+            }
+            UiState::Options => {
+                let settings = self.settings;
+                self.update_options(state, keyboard_input);
+                self.save_changed_settings(settings);
+            }
+            UiState::AutoPickupRules { selected } => {
+                let settings = self.settings;
+                self.update_auto_pickup_rules(state, selected, keyboard_input);
+                self.save_changed_settings(settings);
+            }
+            UiState::Inventory(mode) => self.update_inventory(state, mode, keyboard_input),
+            UiState::Annotate { cursor } => self.update_annotate(state, cursor, keyboard_input),
+            UiState::Look { cursor } => self.update_look(state, cursor, keyboard_input),
+            UiState::AnnotateText { .. } => self.update_annotate_text(state, keyboard_input),
+            UiState::Console { .. } => self.update_console(state, keyboard_input),
+            UiState::Aim { index, cursor } => self.update_aim(state, index, cursor, keyboard_input),
+            UiState::Travel { cursor } => self.update_travel(state, cursor, keyboard_input),
+            UiState::Exert(exertion) => self.update_exert(state, exertion, keyboard_input),
+            UiState::AutoFight => self.update_auto_fight(state, keyboard_input),
+            UiState::Cutscene { .. } => self.update_cutscene(keyboard_input),
+            UiState::ContextMenu { coord, .. } => {
+                let action = match &mut self.ui_state {
+                    UiState::ContextMenu { menu, .. } => menu.update(keyboard_input),
+                    _ => None,
+                };
+                if let Some(action) = action {
+                    self.apply_cell_action(state, coord, action);
+                }
+            }
+            UiState::Discoveries { .. } => self.update_discoveries(keyboard_input),
+            UiState::ItemDetails { index } => match keyboard_input {
+                KeyboardInput::Char('i') | keys::RETURN => {
+                    self.ui_state = UiState::Inventory(InventoryMode::View)
+                }
+                KeyboardInput::Char('Q') => {
+                    self.ui_state = UiState::QuickMenu {
+                        assign: Some(index),
+                    }
+                }
+                _ => (),
+            },
+            UiState::QuickMenu { assign } => self.update_quick_menu(state, assign, keyboard_input),
+            UiState::Bestiary => {
+                if let KeyboardInput::Char('B') | keys::RETURN = keyboard_input {
+                    self.ui_state = UiState::Game;
+                }
+            }
+            UiState::LevelMap { focus } => self.update_level_map(state, focus, keyboard_input),
+        }
+        None
+    }
+
+    /// Highlights for the cursors and previews of the current UI state
+    fn overlay(&self, state: &Game) -> Overlay {
+        let mut overlay = Overlay::default();
+        overlay.highlight_all(state.travel_excluded_cells(), Highlight::Excluded);
+        if let (UiState::Game, Some(travel)) = (&self.ui_state, self.travel.as_ref()) {
+            overlay.highlight_all(state.travel_path(travel.destination), Highlight::Path);
+        }
+        match &self.ui_state {
+            UiState::Look { cursor }
+            | UiState::Annotate { cursor }
+            | UiState::AnnotateText { coord: cursor, .. }
+            | UiState::ContextMenu { coord: cursor, .. } => {
+                overlay.highlight(*cursor, Highlight::Cursor)
+            }
+            UiState::Travel { cursor } => {
+                overlay.highlight_all(
+                    state.travel_path(Destination::Coord(*cursor)),
+                    Highlight::Path,
+                );
+                overlay.highlight(*cursor, Highlight::Cursor);
+            }
+            UiState::Aim { cursor, .. } => {
+                for (coord, reachable) in state.projectile_line(*cursor) {
+                    let highlight = if reachable {
+                        Highlight::Aim
+                    } else {
+                        Highlight::OutOfReach
+                    };
+                    overlay.highlight(coord, highlight);
+                }
+                overlay.highlight(*cursor, Highlight::Cursor);
+            }
+            UiState::AutoFight => {
+                if let Some(player_coord) = state.player_coord() {
+                    overlay.highlight_all(
+                        state
+                            .adjacent_hostile_directions()
+                            .into_iter()
+                            .map(|direction| player_coord + direction.coord()),
+                        Highlight::Cursor,
+                    );
+                }
+            }
+            _ => (),
+        }
+        overlay
+    }
+
+    /// The parts of the screen showing the map and the message log, beside the HUD
+    fn map_and_log_ctx<'a>(&self, state: &Game, ctx: Ctx<'a>) -> (Ctx<'a>, Ctx<'a>) {
+        let hud_width = self.hud.size(state, ctx).width();
+        let main_width = ctx.bounding_box.size().width().saturating_sub(hud_width);
+        let main_ctx = ctx.set_width(main_width);
+        let map_height = main_ctx
+            .bounding_box
+            .size()
+            .height()
+            .saturating_sub(LOG_HEIGHT);
+        let map_ctx = main_ctx.set_height(map_height);
+        let log_ctx = main_ctx.add_y(map_height as i32).set_height(LOG_HEIGHT);
+        (map_ctx, log_ctx)
+    }
+
+    /// Right-clicking a cell of the map opens a menu of what can be done there, whose entries
+    /// can then be clicked or chosen with the keyboard. Mouse input isn't recorded, so it's
+    /// ignored while a game is being recorded or replayed.
+    fn handle_mouse_input(&mut self, state: &mut Game, ctx: Ctx, mouse_input: MouseInput) {
+        if self.recorder.is_some() || self.replay.is_some() || self.animation.is_some() {
+            return;
+        }
+        let (button, screen_coord) = match mouse_input {
+            MouseInput::MousePress { button, coord } => (button, coord),
+            _ => return,
+        };
+        let (map_ctx, _) = self.map_and_log_ctx(state, ctx);
+        let camera = Camera::for_game(state, map_ctx.bounding_box.size());
+        if let UiState::ContextMenu { coord, menu } = &mut self.ui_state {
+            let coord = *coord;
+            if button == MouseButton::Left {
+                let action = context_menu_ctx(coord, menu, camera, map_ctx)
+                    .and_then(|menu_ctx| {
+                        menu_ctx
+                            .bounding_box
+                            .coord_absolute_to_relative(screen_coord)
+                    })
+                    .and_then(|menu_coord| usize::try_from(menu_coord.y - 1).ok())
+                    .and_then(|index| menu.choose(index));
+                match action {
+                    Some(action) => self.apply_cell_action(state, coord, action),
+                    None => self.ui_state = UiState::Game,
+                }
+                return;
+            }
+        } else if !matches!(self.ui_state, UiState::Game) {
+            return;
+        }
+        if button != MouseButton::Right {
+            return;
+        }
+        let coord = match map_ctx
+            .bounding_box
+            .coord_absolute_to_relative(screen_coord)
+            .map(|coord| camera.screen_to_world(coord))
+        {
+            Some(coord) if coord.is_valid(state.level.world.size()) => coord,
+            _ => return,
+        };
+        // clicking interrupts travel like any key
+        self.travel = None;
+        self.ui_state = match cell_actions(state, coord) {
+            Some(menu) => UiState::ContextMenu { coord, menu },
+            None => {
+                state.log("You haven't seen this location.");
+                UiState::Game
+            }
+        };
+    }
+
+    /// Carries out an action chosen from a cell's context menu in the same way as its
+    /// keyboard command
+    fn apply_cell_action(&mut self, state: &mut Game, coord: Coord, action: CellAction) {
+        self.ui_state = UiState::Game;
+        match action {
+            CellAction::WalkHere => self.start_travel(state, Destination::Coord(coord)),
+            CellAction::ThrowAt => {
+                self.ui_state = UiState::Aim {
+                    index: 0,
+                    cursor: coord,
+                }
+            }
+            CellAction::Examine => self.ui_state = UiState::Look { cursor: coord },
+            CellAction::Annotate => {
+                let text = state.annotation(coord).unwrap_or("").to_string();
+                self.ui_state = UiState::AnnotateText { coord, text };
+            }
+        }
+    }
+
+    /// Records the result of a run which just ended in the hall of fame, and exports the
+    /// speedrun splits if the timer is on
+    fn end_run(&mut self, state: &mut Game) {
+        if self.settings.speedrun_timer {
+            match state.run_timer.export(state.turn) {
+                Ok(()) => state.log(format!("Splits saved to {}.", SPLITS_PATH)),
+                Err(e) => state.log(format!("Failed to save splits: {}", e)),
+            }
+        }
+        if self.replay.is_some() {
+            // the run was already recorded when it was played
+            return;
+        }
+        match morgue::write(&state.morgue()) {
+            Ok(path) => state.log(format!("Morgue file written to {}.", path.display())),
+            Err(e) => state.log(format!("Failed to write morgue file: {}", e)),
+        }
+        let change = self.hall_of_fame.record(&state.ruleset, state.run_result());
+        state.log(format!(
+            "Score: {}. Rating: {:.0} ({:+.0}).",
+            change.score, change.rating, change.change
+        ));
+        if let Err(e) = self.hall_of_fame.save() {
+            state.log(format!("Failed to save hall of fame: {}", e));
+        }
+        if let Err(e) = self.profile.store(&state.stored_run()) {
+            state.log(format!("Failed to store run statistics: {}", e));
+        }
+    }
+
+    /// Records new journal entries in the bestiary, saving it if anything changed
+    fn update_bestiary(&mut self, state: &Game) {
+        let entries = &state.journal().entries()[self.journal_cursor..];
+        self.journal_cursor = state.journal().len();
+        if self.bestiary.record(entries) {
+            if let Err(e) = self.bestiary.save() {
+                eprintln!("failed to save bestiary: {}", e);
+            }
+        }
+    }
+
+    fn update_discoveries(&mut self, keyboard_input: KeyboardInput) {
+        let query = match &mut self.ui_state {
+            UiState::Discoveries { query } => query,
+            _ => return,
+        };
+        match keyboard_input {
+            keys::RETURN => self.ui_state = UiState::Game,
+            keys::BACKSPACE => {
+                query.pop();
+            }
+            KeyboardInput::Char(ch) if !ch.is_control() => query.push(ch),
+            _ => (),
+        }
+    }
+
+    fn update_annotate_text(&mut self, state: &mut Game, keyboard_input: KeyboardInput) {
+        let (coord, text) = match &mut self.ui_state {
+            UiState::AnnotateText { coord, text } => (*coord, text),
+            _ => return,
+        };
+        match keyboard_input {
+            keys::RETURN => {
+                state.annotate(coord, text);
+                self.ui_state = UiState::Game;
+            }
+            keys::BACKSPACE => {
+                text.pop();
+            }
+            KeyboardInput::Char(ch) if !ch.is_control() && text.len() < MAX_ANNOTATION_LENGTH => {
+                text.push(ch);
+            }
+            _ => (),
+        }
+    }
+
+    fn update_console(&mut self, state: &mut Game, keyboard_input: KeyboardInput) {
+        let text = match &mut self.ui_state {
+            UiState::Console { text } => text,
+            _ => return,
+        };
+        match keyboard_input {
+            keys::RETURN => {
+                state.run_console_command(text);
+                self.ui_state = UiState::Game;
+            }
+            keys::BACKSPACE => {
+                text.pop();
+            }
+            KeyboardInput::Char(ch) if !ch.is_control() => text.push(ch),
+            _ => (),
+        }
+    }
+
+    /// Starts aiming the item at the given inventory index, initially at the closest enemy
+    fn start_aim(&mut self, state: &Game, index: usize) {
+        if let Some(cursor) = state.nearest_visible_hostile().or(state.player_coord()) {
+            self.ui_state = UiState::Aim { index, cursor };
+        }
+    }
+
+    fn update_aim(
+        &mut self,
+        state: &mut Game,
+        index: usize,
+        cursor: Coord,
+        keyboard_input: KeyboardInput,
+    ) {
+        if let Some(AppInput::Move(direction)) = self.controls.get(keyboard_input) {
+            let cursor = cursor + direction.coord();
+            if cursor.is_valid(state.level.world.size()) {
+                self.ui_state = UiState::Aim { index, cursor };
+            }
+            return;
+        }
+        match keyboard_input {
+            keys::RETURN | KeyboardInput::Char('f') | KeyboardInput::Char('t') => {
+                self.ui_state = UiState::Game;
+                if state.player_coord() == Some(cursor) {
+                    return;
+                }
+                if let Some(throw) = state.player_throw(index, cursor) {
+                    let tile = state
+                        .level
+                        .world
+                        .components
+                        .tile
+                        .get(throw.item)
+                        .cloned()
+                        .unwrap_or(Tile::Potion);
+                    let mut projectile = Projectile::new(throw.path.clone(), tile);
+                    let light = throw
+                        .light
+                        .and_then(|light| state.add_transient_light(throw.path[0], light));
+                    if let Some(light) = light {
+                        projectile = projectile.with_light(light);
+                    }
+                    self.animation = Some((projectile, throw));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Advances the animation in progress, landing the thrown item once it completes
+    fn tick_animation(&mut self, state: &mut Game, since_last_tick: Duration) {
+        let frame_duration = self
+            .settings
+            .animation_speed
+            .scale(PROJECTILE_FRAME_DURATION);
+        let (projectile, _) = match self.animation.as_mut() {
+            Some(animation) => animation,
+            None => return,
+        };
+        let previous = projectile.current();
+        let finished = projectile.tick(since_last_tick, frame_duration);
+        // the projectile's light follows it from frame to frame, going out when it lands
+        if let Some(light) = projectile.light() {
+            match projectile.current() {
+                Some((coord, _)) if !finished => {
+                    if projectile.current() != previous {
+                        state.move_transient_light(light, coord);
+                    }
+                }
+                _ => state.remove_transient_light(light),
+            }
+        }
+        if finished {
+            if let Some((_, throw)) = self.animation.take() {
+                state.land_throw(throw);
+            }
+        }
+    }
+
+    /// Starts animating whatever the game has queued up since the last tick
+    fn start_animations(&mut self, state: &mut Game) {
+        let animation_speed = self.settings.animation_speed;
+        for cue in state.take_animation_cues() {
+            match cue {
+                AnimationCue::Flash(coord) => {
+                    if let Some(duration) = Flashes::duration(animation_speed) {
+                        let light = state.add_transient_light(coord, World::impact_flash());
+                        if let Some(light) = light {
+                            self.flashes.start(light, duration);
+                        }
+                    }
+                }
+                cue => self.character_animations.start(cue, animation_speed),
+            }
+        }
+    }
+
+    fn update_inventory(
+        &mut self,
+        state: &mut Game,
+        mode: InventoryMode,
+        keyboard_input: KeyboardInput,
+    ) {
+        match keyboard_input {
+            // 'i' and 'd' are also item letters, so they only close the inventory once it holds
+            // too few items to have an item under them
+            KeyboardInput::Char(ch @ 'a'..='z')
+                if mode == InventoryMode::View
+                    && ((ch as u8 - b'a') as usize) < state.player_inventory().len() =>
+            {
+                self.ui_state = UiState::ItemDetails {
+                    index: (ch as u8 - b'a') as usize,
+                };
+            }
+            KeyboardInput::Char(ch @ 'a'..='z') if mode != InventoryMode::View => {
+                let index = (ch as u8 - b'a') as usize;
+                match mode {
+                    InventoryMode::Drop => state.player_drop(index),
+                    InventoryMode::Quaff => state.player_quaff(index),
+                    InventoryMode::Wield => state.player_wield(index),
+                    InventoryMode::Throw if index < state.player_inventory().len() => {
+                        self.start_aim(state, index);
+                        return;
+                    }
+                    InventoryMode::Throw | InventoryMode::View => (),
+                }
+                self.ui_state = UiState::Game;
+            }
+            KeyboardInput::Char('i') | KeyboardInput::Char('d') | keys::RETURN => {
+                self.ui_state = UiState::Game
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Component for GameComponent {
+    type Output = Option<()>;
+    type State = Game;
+
+    fn render(&self, state: &Self::State, ctx: Ctx, fb: &mut FrameBuffer) {
+        let size = ctx.bounding_box.size();
+        if !self.needs_drawing(state, size) {
+            return;
+        }
+        self.dirty.set(false);
+        self.drawn
+            .set(Some((state.visibility_grid().count(), size)));
+        fb.clear();
+        if let UiState::MainMenu { menu, error } = &self.ui_state {
+            let mut lines = vec!["rl1".to_string(), String::new()];
+            lines.extend(menu.lines());
+            if let Some(error) = error {
+                lines.push(String::new());
+                lines.push(error.clone());
+            }
+            render_panel(&lines, ctx, fb);
+            return;
+        }
+        if let UiState::HallOfFame = self.ui_state {
+            let mut lines = vec!["Hall of Fame".to_string(), String::new()];
+            let hall_of_fame = self.hall_of_fame.lines();
+            if hall_of_fame.is_empty() {
+                lines.push("(no runs finished yet)".to_string());
+            }
+            lines.extend(hall_of_fame);
+            lines.push(String::new());
+            lines.push("enter: back".to_string());
+            render_panel(&lines, ctx, fb);
+            return;
+        }
+        if let UiState::MainMenuBestiary = self.ui_state {
+            render_bestiary(&self.bestiary, "enter: back", ctx, fb);
+            return;
+        }
+        if let UiState::Statistics = self.ui_state {
+            let mut lines = vec![
+                format!("Statistics for {}", self.profile.name()),
+                String::new(),
+            ];
+            if self.statistics.is_empty() {
+                lines.push("(no runs finished yet)".to_string());
+            }
+            lines.extend(self.statistics.iter().cloned());
+            lines.push(String::new());
+            lines.push("enter: back".to_string());
+            render_panel(&lines, ctx, fb);
+            return;
+        }
+        if let UiState::NewGame { mode, mutators } = &self.ui_state {
+            let mut lines = vec![format!("New {} run", mode.name()), String::new()];
+            lines.push("Mutators:".to_string());
+            for (i, &mutator) in Mutator::ALL.iter().enumerate() {
+                let mark = if mutators.contains(mutator) { 'x' } else { ' ' };
+                lines.push(format!("{}: [{}] {}", i + 1, mark, mutator.name()));
+                lines.push(format!("         {}", mutator.description()));
+            }
+            lines.push(String::new());
+            lines.push("1-9: toggle, enter: start, escape: back".to_string());
+            render_panel(&lines, ctx, fb);
+            return;
+        }
+        if let UiState::Cutscene { sequence, .. } = &self.ui_state {
+            render_panel(&sequence.lines(), ctx, fb);
+            return;
+        }
+        if let UiState::LevelMap { focus } = self.ui_state {
+            level_map::render(state, focus, ctx, fb);
+            return;
+        }
+        let hud_width = self.hud.size(state, ctx).width();
+        let main_width = ctx.bounding_box.size().width().saturating_sub(hud_width);
+        self.hud
+            .render(state, ctx.add_x(main_width as i32).set_width(hud_width), fb);
+        let (map_ctx, log_ctx) = self.map_and_log_ctx(state, ctx);
+        let camera = Camera::for_game(state, map_ctx.bounding_box.size());
+        render_game_with_visibility(state, camera, map_ctx, fb);
+        render_character_animations(
+            state,
+            &self.character_animations,
+            camera,
+            map_ctx.add_depth(1),
+            fb,
+        );
+        if let Some(stairs) = state.nearest_known_stairs_down() {
+            render_compass(
+                stairs,
+                COMPASS_STAIRS_COLOUR,
+                camera,
+                map_ctx.add_depth(1),
+                fb,
+            );
+        }
+        if let Some(target) = state.nearest_known_objective_target() {
+            render_compass(
+                target,
+                COMPASS_OBJECTIVE_COLOUR,
+                camera,
+                map_ctx.add_depth(1),
+                fb,
+            );
+        }
+        if let Some((projectile, _)) = self.animation.as_ref() {
+            if let Some((coord, tile)) = projectile.current() {
+                render_projectile(state, coord, tile, camera, map_ctx.add_depth(1), fb);
+            }
+        }
+        render_overlay(&self.overlay(state), camera, map_ctx.add_depth(1), fb);
+        match &self.ui_state {
+            UiState::Look { cursor } => {
+                for (i, line) in look_lines(state, &self.bestiary, *cursor)
+                    .iter()
+                    .take(LOG_HEIGHT as usize)
+                    .enumerate()
+                {
+                    render_status_line(line, log_ctx.add_y(i as i32), fb);
+                }
+            }
+            UiState::Annotate { cursor } => {
+                let status = match state.annotation(*cursor) {
+                    Some(annotation) => format!("Note: {}", annotation),
+                    None if state.visibility_grid().is_known(*cursor) => {
+                        "Press enter to add a note here.".to_string()
+                    }
+                    None => "You haven't seen this location.".to_string(),
+                };
+                render_status_line(&status, log_ctx, fb);
+            }
+            UiState::AnnotateText { text, .. } => {
+                render_status_line(&format!("Note: {}_", text), log_ctx, fb);
+            }
+            UiState::Console { text } => {
+                render_status_line(&format!("> {}_", text), log_ctx, fb);
+            }
+            UiState::Aim { cursor, .. } => {
+                let status = if state
+                    .projectile_line(*cursor)
+                    .last()
+                    .map(|&(_, reachable)| reachable)
+                    .unwrap_or(false)
+                {
+                    "Throw where? (enter: throw, escape: cancel)"
+                } else {
+                    "Your throw won't reach there. (escape: cancel)"
+                };
+                render_status_line(status, log_ctx, fb);
+            }
+            UiState::Exert(exertion) => {
+                let prompt = match exertion {
+                    Exertion::Sprint => "Sprint in which direction? (enter: cancel)",
+                    Exertion::PowerAttack => "Power attack in which direction? (enter: cancel)",
+                };
+                render_status_line(prompt, log_ctx, fb);
+            }
+            UiState::AutoFight => render_status_line(
+                "Attack which enemy? (direction: attack, enter: cancel)",
+                log_ctx,
+                fb,
+            ),
+            UiState::Travel { cursor } => {
+                let status = if state.visibility_grid().is_known(*cursor) {
+                    "Travel where? (enter: go, >: stairs down, e: avoid area)"
+                } else {
+                    "You haven't seen this location."
+                };
+                render_status_line(status, log_ctx, fb);
+            }
+            UiState::ContextMenu { coord, menu } => {
+                if let Some(menu_ctx) = context_menu_ctx(*coord, menu, camera, map_ctx) {
+                    render_panel(&menu.lines(), menu_ctx.add_depth(2), fb);
+                }
+                let status = look_lines(state, &self.bestiary, *coord)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default();
+                render_status_line(&status, log_ctx, fb);
+            }
+            _ => render_message_log(state.message_log(), log_ctx, fb),
+        }
+        match &self.ui_state {
+            UiState::Discoveries { query } => {
+                render_discoveries(state, query, ctx.add_depth(1), fb)
+            }
+            UiState::Options => render_options_menu(&self.settings, ctx.add_depth(1), fb),
+            UiState::AutoPickupRules { selected } => {
+                render_auto_pickup_rules(&self.settings, *selected, ctx.add_depth(1), fb)
+            }
+            UiState::Bestiary => render_bestiary(&self.bestiary, "B: close", ctx.add_depth(1), fb),
+            UiState::Inventory(mode) => render_inventory(state, *mode, ctx.add_depth(1), fb),
+            UiState::ItemDetails { index } => {
+                render_item_details(state, *index, ctx.add_depth(1), fb)
+            }
+            UiState::QuickMenu { assign } => {
+                render_quick_menu(state, *assign, ctx.add_depth(1), fb)
+            }
+            UiState::Pause(menu) => {
+                let mut lines = vec![format!("Paused (seed {})", state.seed), String::new()];
+                lines.extend(menu.lines());
+                render_panel(&lines, ctx.add_depth(1), fb);
+            }
+            UiState::GameOver(menu) => {
+                let status = state.presence_status();
+                let mut lines = vec![
+                    if state.won { "You won" } else { "You died" }.to_string(),
+                    String::new(),
+                    format!("Depth {}, turn {}", status.depth, status.turn),
+                    format!("Time: {}", format_duration(state.run_timer.elapsed())),
+                    format!("Experience: {}", state.experience),
+                    format!("Seed: {}", state.seed),
+                    String::new(),
+                ];
+                let conducts = state.conduct().kept();
+                if !conducts.is_empty() {
+                    lines.push("Conducts kept:".to_string());
+                    lines.extend(conducts.into_iter().map(|conduct| format!(" {}", conduct)));
+                    lines.push(String::new());
+                }
+                lines.extend(menu.lines());
+                render_panel(&lines, ctx.add_depth(1), fb);
+            }
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, state: &mut Self::State, ctx: Ctx, event: Event) -> Self::Output {
+        if self.is_animating(state) || !matches!(event, Event::Tick(_)) {
+            self.dirty.set(true);
+        }
+        if let Event::Tick(since_last_tick) = event {
+            self.key_repeat.tick(since_last_tick);
+            self.tick_animation(state, since_last_tick);
+            self.start_animations(state);
+            self.character_animations.tick(since_last_tick);
+            for light in self.flashes.tick(since_last_tick) {
+                state.remove_transient_light(light);
+            }
+            self.tick_travel(state, since_last_tick);
+            self.tick_turn_timer(state, since_last_tick);
+            state.tick_lights(since_last_tick);
+            match &mut self.ui_state {
+                UiState::MainMenu { .. }
+                | UiState::HallOfFame
+                | UiState::Statistics
+                | UiState::MainMenuBestiary
+                | UiState::NewGame { .. }
+                | UiState::Pause(_)
+                | UiState::GameOver(_) => (),
+                UiState::Cutscene { sequence, .. } => {
+                    sequence.tick(since_last_tick, self.settings.animation_speed)
+                }
+                _ => state.run_timer.tick(since_last_tick),
+            }
+            // the game behind the main menu is a placeholder, so it shouldn't set the mood
+            let danger = match self.ui_state {
+                UiState::MainMenu { .. }
+                | UiState::HallOfFame
+                | UiState::Statistics
+                | UiState::MainMenuBestiary
+                | UiState::NewGame { .. } => 0.0,
+                _ => state.danger(),
+            };
+            self.music.update(danger, since_last_tick);
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.tick(since_last_tick);
+            }
+            if self.tick_replay(state, since_last_tick).is_some() {
+                return Some(());
+            }
+            if let Some(effect) = self
+                .chat
+                .as_mut()
+                .and_then(|chat| chat.tick(since_last_tick))
+            {
+                state.apply_effect(effect);
+            }
+            self.throttle_when_idle(state, ctx.bounding_box.size(), since_last_tick);
+        }
+        if let Some(keyboard_input) = event.keyboard_input() {
+            if self.replay.is_some() {
+                if keyboard_input == keys::ESCAPE {
+                    self.replay = None;
+                    state.log("Replay stopped.");
+                }
+                return None;
+            }
+            if self.animation.is_some() {
+                return None;
+            }
+            if self.travel.is_none()
+                && !self
+                    .key_repeat
+                    .accept(keyboard_input, self.settings.key_repeat_interval())
+            {
+                return None;
+            }
+            // cutscenes don't affect the game, and aren't shown during replays
+            if !matches!(self.ui_state, UiState::Cutscene { .. }) {
+                self.record_input(state, keyboard_input);
+            }
+            if self.handle_keyboard_input(state, keyboard_input).is_some() {
+                return Some(());
+            }
+        }
+        if let Event::Input(Input::Mouse(mouse_input)) = event {
+            self.handle_mouse_input(state, ctx, mouse_input);
+        }
+        if let UiState::MainMenu { .. }
+        | UiState::HallOfFame
+        | UiState::Statistics
+        | UiState::MainMenuBestiary
+        | UiState::NewGame { .. } = self.ui_state
+        {
+            // the game behind the main menu is a placeholder until one is started or loaded
+            return None;
+        }
+        if let UiState::Game = self.ui_state {
+            if state.player_coord().is_none() || state.won {
+                self.ui_state =
+                    self.cutscene(self.cutscenes.ending(state.won), AfterCutscene::GameOver);
+                self.end_run(state);
+            }
+        }
+        self.update_bestiary(state);
+        self.presence.update(state.presence_status());
+        None
+    }
 
-enum Frontend {
-    Wgpu,
-    AnsiTerminal,
+    fn size(&self, _state: &Self::State, ctx: Ctx) -> Size {
+        ctx.bounding_box.size()
+    }
 }
 
-impl Frontend {
-    fn parser() -> impl meap::Parser<Item = Self> {
-        meap::choose_at_most_one! {
-            flag("wgpu").some_if(Self::Wgpu),
-            flag("ansi-terminal").some_if(Self::AnsiTerminal),
+/// Number of rows below the map reserved for the message log
+const LOG_HEIGHT: u32 = 3;
+
+fn render_message_log(message_log: &MessageLog, ctx: Ctx, fb: &mut FrameBuffer) {
+    let messages = message_log.recent(LOG_HEIGHT as usize);
+    // the most recent message is at the bottom and older messages fade out
+    let offset = LOG_HEIGHT as usize - messages.len();
+    for (i, message) in messages.iter().enumerate() {
+        let age = messages.len() - 1 - i;
+        let style = Style::plain_text().with_foreground(Rgba32::new_grey(255 - 64 * age as u8));
+        StyledString {
+            string: message.clone(),
+            style,
         }
-        .with_default_general(Self::Wgpu)
+        .render(&(), ctx.add_y((offset + i) as i32), fb);
     }
 }
 
-struct Args {
-    frontend: Frontend,
+/// The thing examined by looking at a cell, if the player can see something there other
+/// than the floor
+fn examined_entity(game: &Game, coord: Coord) -> Option<Entity> {
+    if !game.visibility_grid().is_currently_visible(coord) {
+        return None;
+    }
+    let layers = game.level.world.spatial_table.layers_at(coord)?;
+    layers.character.or(layers.item).or(layers.feature)
 }
 
-impl Args {
-    fn parser() -> impl meap::Parser<Item = Self> {
-        meap::let_map! {
-            let {
-                frontend = Frontend::parser();
-            } in {
-                Self { frontend }
+/// Lines describing what the player knows about a cell, shown while examining the map
+fn look_lines(game: &Game, bestiary: &Bestiary, coord: Coord) -> Vec<String> {
+    let visibility_grid = game.visibility_grid();
+    let cell = match visibility_grid.cell(coord) {
+        Some(cell) if visibility_grid.is_known(coord) => cell,
+        _ => return vec!["You haven't seen this location.".to_string()],
+    };
+    let world = &game.level.world;
+    let currently_visible = visibility_grid.is_currently_visible(coord);
+    let top = if currently_visible {
+        world.spatial_table.layers_at(coord).and_then(|layers| {
+            let entity = layers
+                .character
+                .or(layers.item)
+                .or(layers.feature)
+                .or(layers.floor)?;
+            let &tile = world.components.tile.get(entity)?;
+            Some(EntityTile { entity, tile })
+        })
+    } else {
+        cell.tile_layers().top()
+    };
+    let mut lines = Vec::new();
+    if let Some(EntityTile { entity, tile }) = top {
+        let description = match world.components.description.get(entity) {
+            Some(description) if currently_visible => {
+                format!("{}: {}", description.name, description.text)
+            }
+            _ => format!("{} (remembered)", tile.name()),
+        };
+        lines.push(description);
+        if let Some(amount) = world.components.gold.get(entity) {
+            if currently_visible {
+                lines.push(format!("Worth {} gold.", amount));
+            }
+        }
+        if currently_visible && world.is_frozen(entity) {
+            lines.push("It's frozen in place.".to_string());
+        }
+        if currently_visible && world.is_charmed(entity) {
+            lines.push("It's charmed and fights for you.".to_string());
+        }
+        if world.components.npc.contains(entity) {
+            if let Some(entry) = bestiary.get(tile.name()) {
+                if entry.is_unlocked() {
+                    lines.push(entry.description(tile.name()));
+                }
             }
         }
     }
+    if let Some(annotation) = game.annotation(coord) {
+        lines.push(format!("Note: {}", annotation));
+    }
+    lines
 }
 
-fn main() {
-    use meap::Parser;
-    let Args { frontend } = Args::parser().with_help_default().parse_env_or_exit();
-    match frontend {
-        Frontend::Wgpu => main_wgpu(),
-        Frontend::AnsiTerminal => main_ansi_terminal(),
+/// Renders a single line of text in place of the message log
+fn render_status_line(text: &str, ctx: Ctx, fb: &mut FrameBuffer) {
+    StyledString {
+        string: text.to_string(),
+        style: Style::plain_text().with_foreground(Rgba32::new_grey(255)),
     }
+    .render(&(), ctx, fb);
 }
 
-fn main_ansi_terminal() {
-    use gridbugs::chargrid_ansi_terminal::*;
-
-    let context = Context::new().unwrap();
-    context.run(app(), col_encode::XtermTrueColour);
+/// Draws highlights over the map. Their colours are translucent, so the frame buffer blends
+/// them with the map beneath.
+fn render_overlay(overlay: &Overlay, camera: Camera, ctx: Ctx, fb: &mut FrameBuffer) {
+    for (coord, colour) in overlay.cells() {
+        if let Some(screen_coord) = camera.world_to_screen(coord) {
+            fb.set_cell_relative_to_ctx(
+                ctx,
+                screen_coord,
+                0,
+                RenderCell::default().with_background(colour),
+            );
+        }
+    }
 }
 
-fn main_wgpu() {
-    use gridbugs::chargrid_wgpu::*;
-
-    const CELL_SCALE: f64 = 4.;
-    const CELL_HEIGHT: f64 = 6. * CELL_SCALE;
-    const CELL_WIDTH: f64 = 6. * CELL_SCALE;
-
-    let context = Context::new(Config {
-        font_bytes: FontBytes {
-            normal: include_bytes!("./fonts/PxPlus_IBM_CGAthin-custom.ttf").to_vec(),
-            bold: include_bytes!("./fonts/PxPlus_IBM_CGA-custom.ttf").to_vec(),
-        },
-        title: "rl1".to_string(),
-        window_dimensions_px: Dimensions {
-            width: 960.,
-            height: 720.,
-        },
-        cell_dimensions_px: Dimensions {
-            width: CELL_WIDTH,
-            height: CELL_HEIGHT,
-        },
-        font_scale: Dimensions {
-            width: CELL_WIDTH,
-            height: CELL_HEIGHT,
-        },
-        underline_width_cell_ratio: 0.1,
-        underline_top_offset_cell_ratio: 0.8,
-        resizable: false,
-        force_secondary_adapter: false,
-    });
-    context.run(app());
+/// The actions which apply to a cell, or `None` if the player hasn't seen it
+fn cell_actions(game: &Game, coord: Coord) -> Option<Menu<CellAction>> {
+    if !game.visibility_grid().is_known(coord) {
+        return None;
+    }
+    let elsewhere = game.player_coord() != Some(coord);
+    let mut entries = Vec::new();
+    if elsewhere {
+        entries.push((CellAction::WalkHere, "Walk here"));
+        if !game.player_inventory().is_empty() {
+            entries.push((CellAction::ThrowAt, "Throw at"));
+        }
+    }
+    entries.push((CellAction::Examine, "Examine"));
+    entries.push((CellAction::Annotate, "Annotate"));
+    Some(Menu::new(entries))
 }
 
-fn app() -> App {
-    cf(GameComponent {})
-        .with_state(Game::new())
-        .catch_escape()
-        .map_val(|| app::Exit)
-        .clear_each_frame()
-        .exit_on_close()
+/// Where a cell's context menu is drawn, beside the cell and kept within the map. Entries
+/// start on the second row of the panel.
+fn context_menu_ctx<'a>(
+    coord: Coord,
+    menu: &Menu<CellAction>,
+    camera: Camera,
+    map_ctx: Ctx<'a>,
+) -> Option<Ctx<'a>> {
+    let screen_coord = camera.world_to_screen(coord)?;
+    let lines = menu.lines();
+    let width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as i32 + 2;
+    let height = lines.len() as i32 + 2;
+    let map_size = map_ctx.bounding_box.size();
+    let x = (screen_coord.x + 1)
+        .min(map_size.width() as i32 - width)
+        .max(0);
+    let y = screen_coord.y.min(map_size.height() as i32 - height).max(0);
+    Some(
+        map_ctx
+            .add_offset(Coord::new(x, y))
+            .set_width(width as u32)
+            .set_height(height as u32),
+    )
 }
 
-struct Terrain {
-    world: World,
-    player_entity: Entity,
+/// The colour of the light on a cell, if the player can currently see it
+fn light_colour_at(game: &Game, coord: Coord) -> Option<Rgb24> {
+    let visibility_grid = game.visibility_grid();
+    match visibility_grid
+        .cell(coord)?
+        .visibility(visibility_grid.count())
+    {
+        CellVisibility::CurrentlyVisibleWithLightColour(light_colour) => light_colour,
+        _ => None,
+    }
 }
 
-impl Terrain {
-    fn new() -> Self {
-        let s = include_str!("./terrain.txt");
-        let player_data = World::make_player();
-        let rows = s.split('\n').filter(|s| !s.is_empty()).collect::<Vec<_>>();
-        let size = Size::new_u16(rows[0].len() as u16, rows.len() as u16);
-        let mut world = World::new(size);
-        let mut player_data = Some(player_data);
-        let mut player_entity = None;
-        for (y, row) in rows.iter().enumerate() {
-            for (x, ch) in row.chars().enumerate() {
-                if ch.is_control() {
-                    continue;
-                }
-                let coord = Coord::new(x as i32, y as i32);
-                match ch {
-                    '.' => {
-                        world.spawn_floor(coord);
-                    }
-                    'R' => {
-                        world.spawn_floor(coord);
-                        world.spawn_light(coord, Rgb24::new(255, 0, 0));
-                    }
-                    'G' => {
-                        world.spawn_floor(coord);
-                        world.spawn_light(coord, Rgb24::new(0, 255, 0));
-                    }
-                    '#' => {
-                        world.spawn_wall(coord);
-                    }
-                    '@' => {
-                        world.spawn_floor(coord);
-                        let location = Location {
-                            coord,
-                            layer: Some(Layer::Character),
-                        };
-                        player_entity =
-                            Some(world.insert_entity_data(location, player_data.take().unwrap()));
+/// Draws characters which are lunging, hurt or dying over how they'd usually be drawn
+fn render_character_animations(
+    game: &Game,
+    animations: &CharacterAnimations,
+    camera: Camera,
+    ctx: Ctx,
+    fb: &mut FrameBuffer,
+) {
+    let world = &game.level.world;
+    let mut render_tile = |coord: Coord, tile: Tile, foreground: Rgb24, lit: bool| {
+        let light_colour = match light_colour_at(game, coord) {
+            Some(light_colour) => light_colour,
+            None => return,
+        };
+        let screen_coord = match camera.world_to_screen(coord) {
+            Some(screen_coord) => screen_coord,
+            None => return,
+        };
+        let render_cell = RenderCell::default()
+            .with_character(tile_char(tile))
+            .with_foreground(foreground.to_rgba32(255));
+        if lit {
+            fb.set_cell_relative_to_ctx(
+                ctx_tint!(
+                    ctx,
+                    LightBlend {
+                        light_colour,
+                        white_balance: game.white_balance,
                     }
-
-                    other => panic!("unexpected char {}", other),
+                ),
+                screen_coord,
+                0,
+                render_cell,
+            );
+        } else {
+            fb.set_cell_relative_to_ctx(ctx, screen_coord, 0, render_cell);
+        }
+    };
+    for pose in animations.poses() {
+        match pose {
+            Pose::Displaced { entity, target } => {
+                let (coord, &tile) = match (
+                    world.entity_coord(entity),
+                    world.components.tile.get(entity),
+                ) {
+                    (Some(coord), Some(tile)) => (coord, tile),
+                    _ => continue,
+                };
+                let step = Coord::new((target.x - coord.x).signum(), (target.y - coord.y).signum());
+                let foreground = world
+                    .components
+                    .colour_hint
+                    .get(entity)
+                    .map(|colour_hint| colour_hint.foreground)
+                    .unwrap_or_else(|| tile.colour_hint().foreground);
+                render_tile(coord + step, tile, foreground, true);
+            }
+            Pose::Flash(entity) => {
+                if let (Some(coord), Some(&tile)) = (
+                    world.entity_coord(entity),
+                    world.components.tile.get(entity),
+                ) {
+                    render_tile(coord, tile, Rgb24::new(255, 0, 0), false);
                 }
             }
-        }
-        let player_entity = player_entity.expect("didn't create player");
-        Terrain {
-            world,
-            player_entity,
+            Pose::Fading {
+                coord,
+                tile,
+                brightness,
+            } => {
+                let foreground = tile
+                    .colour_hint()
+                    .foreground
+                    .saturating_scalar_mul_div(brightness as u32, 255);
+                render_tile(coord, tile, foreground, true);
+            }
         }
     }
 }
 
-struct Game {
-    world: World,
-    player_entity: Entity,
-    visibility_grid: VisibilityGrid,
-    shadowcast_context: ShadowcastContext<u8>,
+/// Renders a projectile in flight, as long as the player can see the cell it's passing through
+fn render_projectile(
+    game: &Game,
+    coord: Coord,
+    tile: Tile,
+    camera: Camera,
+    ctx: Ctx,
+    fb: &mut FrameBuffer,
+) {
+    if !game.visibility_grid().is_currently_visible(coord) {
+        return;
+    }
+    if let Some(screen_coord) = camera.world_to_screen(coord) {
+        fb.set_cell_relative_to_ctx(
+            ctx,
+            screen_coord,
+            0,
+            RenderCell::default()
+                .with_character(tile_char(tile))
+                .with_foreground(Rgba32::new_grey(255)),
+        );
+    }
 }
 
-impl Game {
-    fn new() -> Self {
-        let Terrain {
-            world,
-            player_entity,
-        } = Terrain::new();
-        let visibility_grid = VisibilityGrid::new(world.size());
-        let shadowcast_context = ShadowcastContext::default();
-        let mut s = Self {
-            world,
-            player_entity,
-            visibility_grid,
-            shadowcast_context,
-        };
-        s.update_visibility();
-        s
+const COMPASS_STAIRS_COLOUR: Rgba32 = Rgba32::new_rgb(255, 255, 0);
+const COMPASS_OBJECTIVE_COLOUR: Rgba32 = Rgba32::new_rgb(0, 255, 255);
+
+/// Renders an arrow at the edge of the view pointing towards a target which is off screen.
+/// The font only has the four orthogonal arrows, so a target off a corner gets the arrow for
+/// whichever way it's further off.
+fn render_compass(target: Coord, colour: Rgba32, camera: Camera, ctx: Ctx, fb: &mut FrameBuffer) {
+    if camera.world_to_screen(target).is_some() {
+        return;
     }
+    let screen_coord = target - camera.offset;
+    let edge = Coord::new(
+        screen_coord.x.clamp(0, camera.view_size.width() as i32 - 1),
+        screen_coord
+            .y
+            .clamp(0, camera.view_size.height() as i32 - 1),
+    );
+    let offset = screen_coord - edge;
+    let arrow = if offset.x.abs() >= offset.y.abs() {
+        if offset.x > 0 {
+            '→'
+        } else {
+            '←'
+        }
+    } else if offset.y > 0 {
+        '↓'
+    } else {
+        '↑'
+    };
+    fb.set_cell_relative_to_ctx(
+        ctx,
+        edge,
+        0,
+        RenderCell::default()
+            .with_character(arrow)
+            .with_foreground(colour)
+            .with_background(Rgba32::new_grey(0)),
+    );
+}
 
-    fn update_visibility(&mut self) {
-        if let Some(player_coord) = self.world.entity_coord(self.player_entity) {
-            self.visibility_grid.update(
-                player_coord,
-                &self.world,
-                &mut self.shadowcast_context,
-                None,
+/// Renders lines of text over a blank panel at the top of the context
+fn render_panel(lines: &[String], ctx: Ctx, fb: &mut FrameBuffer) {
+    let size = ctx.bounding_box.size();
+    for y in 0..(lines.len() as i32 + 2) {
+        for x in 0..size.width() as i32 {
+            fb.set_cell_relative_to_ctx(
+                ctx,
+                Coord::new(x, y),
+                0,
+                RenderCell::default()
+                    .with_character(' ')
+                    .with_background(Rgba32::new_grey(0)),
             );
         }
     }
+    let style = Style::plain_text().with_foreground(Rgba32::new_grey(255));
+    for (i, line) in lines.iter().enumerate() {
+        StyledString {
+            string: line.clone(),
+            style,
+        }
+        .render(&(), ctx.add_offset(Coord::new(1, 1 + i as i32)), fb);
+    }
+}
 
-    fn visibility_grid(&self) -> &VisibilityGrid {
-        &self.visibility_grid
+fn render_inventory(game: &Game, mode: InventoryMode, ctx: Ctx, fb: &mut FrameBuffer) {
+    let mut lines = vec![match mode {
+        InventoryMode::View => format!(
+            "Inventory (weight {}/{})",
+            game.player_carried_weight(),
+            game.player_carry_capacity()
+        ),
+        InventoryMode::Drop => "Drop which item?".to_string(),
+        InventoryMode::Quaff => "Drink which potion?".to_string(),
+        InventoryMode::Throw => "Throw which item?".to_string(),
+        InventoryMode::Wield => "Wield which item?".to_string(),
+    }];
+    lines.push(String::new());
+    let items = game.player_inventory();
+    if items.is_empty() {
+        lines.push("(empty)".to_string());
+    }
+    for (i, name) in items.into_iter().enumerate() {
+        lines.push(format!("{}) {}", (b'a' + i as u8) as char, name));
     }
+    lines.push(String::new());
+    lines.push(match mode {
+        InventoryMode::View => "a-z: examine, i: close".to_string(),
+        InventoryMode::Drop => "a-z: drop, enter: cancel".to_string(),
+        InventoryMode::Quaff => "a-z: drink, enter: cancel".to_string(),
+        InventoryMode::Throw => "a-z: throw, enter: cancel".to_string(),
+        InventoryMode::Wield => "a-z: wield or put away, enter: cancel".to_string(),
+    });
+    render_panel(&lines, ctx, fb);
+}
 
-    pub fn player_walk(&mut self, direction: CardinalDirection) {
-        let player_coord = self
-            .world
-            .spatial_table
-            .coord_of(self.player_entity)
-            .unwrap();
-        let destination = player_coord + direction.coord();
-        if let Some(layers) = self.world.spatial_table.layers_at(destination) {
-            if let Some(feature) = layers.feature {
-                if self.world.components.solid.contains(feature) {
-                    return;
-                }
-            }
-            if layers.floor.is_some() {
-                let _ = self
-                    .world
-                    .spatial_table
-                    .update_coord(self.player_entity, destination);
-            }
+/// A table comparing an item's stats with the items it would replace, where `None` stands
+/// for an empty hand
+fn item_comparison(item: Item, held: &[Option<Item>]) -> Vec<String> {
+    const COLUMN_WIDTH: usize = 12;
+    let stats = |item: Option<Item>| {
+        let damage = item.map(Item::damage_bonus).unwrap_or(0);
+        let grip = item.and_then(Item::grip);
+        let block = match grip {
+            Some(Grip::Shield) => format!("1 in {}", SHIELD_BLOCK_CHANCE),
+            _ => "-".to_string(),
+        };
+        let hands = match grip {
+            Some(Grip::OneHanded) => "one",
+            Some(Grip::TwoHanded) => "two",
+            Some(Grip::Shield) => "off",
+            None => "-",
+        };
+        let weight = item.map(Item::weight).unwrap_or(0);
+        [
+            format!("+{}", damage),
+            block,
+            hands.to_string(),
+            weight.to_string(),
+        ]
+    };
+    let columns = std::iter::once(Some(item))
+        .chain(held.iter().cloned())
+        .collect::<Vec<_>>();
+    let mut header = format!("{:w$}", "", w = COLUMN_WIDTH);
+    for (i, column) in columns.iter().enumerate() {
+        let name = match column {
+            Some(column) if i == 0 => column.name().to_string(),
+            Some(column) => format!("{} (held)", column.name()),
+            None => "(empty hand)".to_string(),
+        };
+        header.push_str(&format!("{:w$}", name, w = COLUMN_WIDTH + 2));
+    }
+    let mut lines = vec![header];
+    let column_stats = columns
+        .iter()
+        .map(|&column| stats(column))
+        .collect::<Vec<_>>();
+    for (row, label) in ["Damage", "Block", "Hands", "Weight"].iter().enumerate() {
+        let mut line = format!("{:w$}", label, w = COLUMN_WIDTH);
+        for stats in &column_stats {
+            line.push_str(&format!("{:w$}", stats[row], w = COLUMN_WIDTH + 2));
         }
-        self.update_visibility();
+        lines.push(line);
     }
+    lines
 }
 
-struct GameComponent {}
+fn render_item_details(game: &Game, index: usize, ctx: Ctx, fb: &mut FrameBuffer) {
+    let mut lines = game.player_item_details(index).unwrap_or_default();
+    lines.push(String::new());
+    lines.push("Q: add to quick menu, enter: back".to_string());
+    render_panel(&lines, ctx, fb);
+}
 
-impl Component for GameComponent {
-    type Output = Option<()>;
-    type State = Game;
+fn render_quick_menu(game: &Game, assign: Option<usize>, ctx: Ctx, fb: &mut FrameBuffer) {
+    const CELL_WIDTH: usize = 16;
+    let assigned = assign.and_then(|index| game.player_item(index));
+    let mut lines = vec![
+        match assigned {
+            Some(item) => format!("Put the {} in which slot?", item.name()),
+            None => "Quick menu".to_string(),
+        },
+        String::new(),
+    ];
+    lines.extend(game.quick_slots.lines(CELL_WIDTH));
+    lines.push(String::new());
+    lines.push(
+        match assigned {
+            Some(_) => "direction: assign (again to clear), escape: cancel",
+            None => "direction: use, escape: close",
+        }
+        .to_string(),
+    );
+    render_panel(&lines, ctx, fb);
+}
 
-    fn render(&self, state: &Self::State, ctx: Ctx, fb: &mut FrameBuffer) {
-        render_game_with_visibility(state, ctx, fb);
+fn render_discoveries(game: &Game, query: &str, ctx: Ctx, fb: &mut FrameBuffer) {
+    let mut lines = vec![
+        "Discoveries".to_string(),
+        format!("Search: {}_", query),
+        String::new(),
+    ];
+    lines.extend(game.discoveries().lines(query));
+    lines.push(String::new());
+    lines.push("type to search, enter: close".to_string());
+    render_panel(&lines, ctx, fb);
+}
+
+fn render_bestiary(bestiary: &Bestiary, close_hint: &str, ctx: Ctx, fb: &mut FrameBuffer) {
+    let mut lines = vec!["Bestiary".to_string(), String::new()];
+    lines.extend(
+        bestiary
+            .entries()
+            .map(|(name, entry)| entry.description(name)),
+    );
+    if lines.len() == 2 {
+        lines.push("(no monsters encountered yet)".to_string());
     }
+    lines.push(String::new());
+    lines.push(close_hint.to_string());
+    render_panel(&lines, ctx, fb);
+}
 
-    fn update(&mut self, state: &mut Self::State, _ctx: Ctx, event: Event) -> Self::Output {
-        if let Some(keyboard_input) = event.keyboard_input() {
-            match keyboard_input {
-                KeyboardInput::Left => state.player_walk(CardinalDirection::West),
-                KeyboardInput::Right => state.player_walk(CardinalDirection::East),
-                KeyboardInput::Up => state.player_walk(CardinalDirection::North),
-                KeyboardInput::Down => state.player_walk(CardinalDirection::South),
-                _ => (),
-            }
-        }
-        None
+fn render_options_menu(settings: &Settings, ctx: Ctx, fb: &mut FrameBuffer) {
+    let mut lines = vec![
+        "Options".to_string(),
+        String::new(),
+        format!("Animation speed: < {} >", settings.animation_speed.name()),
+        format!("Field of view: {}", settings.fov_algorithm.name()),
+        format!("Turn timer: {}", settings.turn_timer.name()),
+        format!(
+            "Speedrun timer: {}",
+            if settings.speedrun_timer { "on" } else { "off" }
+        ),
+        format!("White balance: < {} >", settings.white_balance.name()),
+        format!("Auto-pickup: {}", settings.auto_pickup.name()),
+    ];
+    for (i, &category) in ItemCategory::ALL.iter().enumerate() {
+        let check = if settings.auto_pickup.contains(category) {
+            'x'
+        } else {
+            ' '
+        };
+        lines.push(format!("  {}) [{}] {}", i + 1, check, category.name()));
     }
+    let interrupts = settings.travel_interrupts;
+    let check = |enabled: bool| if enabled { 'x' } else { ' ' };
+    lines.push("Stop travelling when:".to_string());
+    lines.push(format!(
+        "  n) [{}] a new monster comes into view",
+        check(interrupts.new_monster)
+    ));
+    lines.push(format!("  h) health: {}", interrupts.low_health.name()));
+    lines.push(format!(
+        "  i) [{}] stepping onto an item",
+        check(interrupts.item)
+    ));
+    lines.push(String::new());
+    lines.push(
+        "left/right: animation speed, f: field of view, t: turn timer, s: speedrun timer"
+            .to_string(),
+    );
+    lines.push("[/]: white balance, 1-4: auto-pickup, n/h/i: travel interruptions".to_string());
+    lines.push("a: auto-pickup exceptions, o: close".to_string());
+    render_panel(&lines, ctx, fb);
+}
 
-    fn size(&self, _state: &Self::State, ctx: Ctx) -> Size {
-        ctx.bounding_box.size()
+fn render_auto_pickup_rules(settings: &Settings, selected: usize, ctx: Ctx, fb: &mut FrameBuffer) {
+    let auto_pickup = settings.auto_pickup;
+    let mut lines = vec!["Auto-pickup exceptions".to_string(), String::new()];
+    for (i, &item) in Item::ALL.iter().enumerate() {
+        let cursor = if i == selected { '>' } else { ' ' };
+        let rule = match auto_pickup.item_rule(item) {
+            ItemRule::Category => {
+                let on = if auto_pickup.picks_up(item) {
+                    "on"
+                } else {
+                    "off"
+                };
+                format!("{} ({})", ItemRule::Category.name(), on)
+            }
+            rule => rule.name().to_string(),
+        };
+        lines.push(format!("{} {:10} {}", cursor, item.name(), rule));
     }
+    lines.push(String::new());
+    lines.push("up/down: select, space: change rule, a: back".to_string());
+    render_panel(&lines, ctx, fb);
 }
 
+/// Lights a cell, then adjusts the result by the white balance
 #[derive(Clone, Copy)]
 struct LightBlend {
     light_colour: Rgb24,
+    white_balance: WhiteBalance,
 }
 
 impl Tint for LightBlend {
     fn tint(&self, rgba32: Rgba32) -> Rgba32 {
-        rgba32
+        let lit = rgba32
             .to_rgb24()
             .normalised_mul(self.light_colour)
-            .saturating_add(self.light_colour.saturating_scalar_mul_div(1, 10))
-            .to_rgba32(255)
+            .saturating_add(self.light_colour.saturating_scalar_mul_div(1, 10));
+        self.white_balance.apply(lit).to_rgba32(255)
+    }
+}
+
+/// How cells seen with darkvision are drawn: without colour, and dimmer than lit cells
+struct Greyscale;
+
+impl Tint for Greyscale {
+    fn tint(&self, rgba32: Rgba32) -> Rgba32 {
+        let luminance = (rgba32.r as u32 * 77 + rgba32.g as u32 * 150 + rgba32.b as u32 * 29) >> 8;
+        Rgb24::new_grey((luminance * DARKVISION_BRIGHTNESS_PERCENT / 100) as u8).to_rgba32(255)
+    }
+}
+
+/// Translates between world coords and coords relative to the map's render context. The
+/// camera centres on its focus, but stops at the edges of the map so no space is wasted
+/// showing cells outside it. Maps that fit entirely in the view are not scrolled.
+#[derive(Clone, Copy)]
+struct Camera {
+    offset: Coord,
+    view_size: Size,
+}
+
+impl Camera {
+    fn new(focus: Coord, world_size: Size, view_size: Size) -> Self {
+        fn axis(focus: i32, world: i32, view: i32) -> i32 {
+            if world <= view {
+                0
+            } else {
+                (focus - view / 2).clamp(0, world - view)
+            }
+        }
+        let offset = Coord::new(
+            axis(focus.x, world_size.width() as i32, view_size.width() as i32),
+            axis(
+                focus.y,
+                world_size.height() as i32,
+                view_size.height() as i32,
+            ),
+        );
+        Self { offset, view_size }
+    }
+
+    fn for_game(game: &Game, view_size: Size) -> Self {
+        let world_size = game.level.world.size();
+        let focus = game.player_coord().unwrap_or_else(|| {
+            Coord::new(
+                world_size.width() as i32 / 2,
+                world_size.height() as i32 / 2,
+            )
+        });
+        Self::new(focus, world_size, view_size)
+    }
+
+    fn screen_to_world(&self, coord: Coord) -> Coord {
+        coord + self.offset
+    }
+
+    /// Returns `None` if the coord is outside the view
+    fn world_to_screen(&self, coord: Coord) -> Option<Coord> {
+        let screen_coord = coord - self.offset;
+        if screen_coord.is_valid(self.view_size) {
+            Some(screen_coord)
+        } else {
+            None
+        }
     }
 }
 
-fn render_game_with_visibility(game: &Game, ctx: Ctx, fb: &mut FrameBuffer) {
+fn render_game_with_visibility(game: &Game, camera: Camera, ctx: Ctx, fb: &mut FrameBuffer) {
     let visibility_grid = game.visibility_grid();
     let vis_count = visibility_grid.count();
     for (coord, visibility_cell) in game.visibility_grid().enumerate() {
+        let coord = match camera.world_to_screen(coord) {
+            Some(screen_coord) => screen_coord,
+            None => continue,
+        };
         match visibility_cell.visibility(vis_count) {
             CellVisibility::CurrentlyVisibleWithLightColour(Some(light_colour)) => {
                 render_visibile(
+                    &game.level.world,
+                    coord,
+                    visibility_cell,
+                    ctx_tint!(
+                        ctx,
+                        LightBlend {
+                            light_colour,
+                            white_balance: game.white_balance,
+                        }
+                    ),
+                    fb,
+                );
+            }
+            CellVisibility::CurrentlyVisibleInDarkness => {
+                render_visibile(
+                    &game.level.world,
                     coord,
                     visibility_cell,
-                    ctx_tint!(ctx, LightBlend { light_colour }),
+                    ctx_tint!(ctx, Greyscale),
                     fb,
                 );
             }
@@ -291,21 +4950,56 @@ fn render_game_with_visibility(game: &Game, ctx: Ctx, fb: &mut FrameBuffer) {
     }
 }
 
-fn render_visibile(coord: Coord, cell: &VisibilityCell, ctx: Ctx, fb: &mut FrameBuffer) {
-    let mut render_tile = |_entity, tile| {
-        let ch = match tile {
-            Tile::Floor => '.',
-            Tile::Wall => '█',
-            Tile::Player => '@',
-        };
-        fb.set_cell_relative_to_ctx(
-            ctx,
-            coord,
-            0,
-            RenderCell::default()
-                .with_character(ch)
-                .with_foreground(Rgba32::new_grey(255)),
-        );
+fn tile_char(tile: Tile) -> char {
+    match tile {
+        Tile::Floor => '.',
+        Tile::Wall => '█',
+        Tile::Player => '@',
+        Tile::Goblin => 'g',
+        Tile::Orc => 'o',
+        Tile::Shade => 's',
+        Tile::Potion => '!',
+        Tile::Key => '-',
+        Tile::Dagger => ')',
+        Tile::Sword => '|',
+        Tile::Greataxe => '/',
+        Tile::Shield => ']',
+        Tile::Corpse => '%',
+        Tile::Gold => '$',
+        Tile::StairsDown => '>',
+        Tile::StairsUp => '<',
+        Tile::DoorClosed => '+',
+        Tile::DoorOpen => '\'',
+        Tile::Trap => '^',
+        Tile::Nest => '&',
+        Tile::Portal => '0',
+        Tile::Boulder => 'O',
+        Tile::Plate => '_',
+        Tile::Brazier | Tile::BrazierLit => 'Y',
+    }
+}
+
+fn render_visibile(
+    world: &World,
+    coord: Coord,
+    cell: &VisibilityCell,
+    ctx: Ctx,
+    fb: &mut FrameBuffer,
+) {
+    let mut render_tile = |entity, tile: Tile| {
+        let colour_hint = world
+            .components
+            .colour_hint
+            .get(entity)
+            .cloned()
+            .unwrap_or_else(|| tile.colour_hint());
+        let mut render_cell = RenderCell::default()
+            .with_character(tile_char(tile))
+            .with_foreground(colour_hint.foreground.to_rgba32(255));
+        if let Some(background) = colour_hint.background {
+            render_cell = render_cell.with_background(background.to_rgba32(255));
+        }
+        fb.set_cell_relative_to_ctx(ctx, coord, 0, render_cell);
     };
     let tile_layers = cell.tile_layers();
     if let Some(EntityTile { entity, tile }) = tile_layers.floor {
@@ -322,21 +5016,98 @@ fn render_visibile(coord: Coord, cell: &VisibilityCell, ctx: Ctx, fb: &mut Frame
     }
 }
 
+/// A dim grey with the same brightness as the colour, for drawing remembered cells
+fn remembered_colour(colour: Rgb24) -> Rgba32 {
+    let brightness = (colour.r as u32 + colour.g as u32 + colour.b as u32) / 3;
+    Rgba32::new_grey((brightness / 2) as u8)
+}
+
+/// Draws the floor, features and items the player remembers seeing in a cell. Characters
+/// are left out as they have probably moved since.
 fn render_remembered(coord: Coord, cell: &VisibilityCell, ctx: Ctx, fb: &mut FrameBuffer) {
+    let mut render_tile = |tile: Tile| {
+        let colour_hint = tile.colour_hint();
+        let ch = match tile {
+            Tile::Wall => '▒',
+            _ => tile_char(tile),
+        };
+        let mut render_cell = RenderCell::default()
+            .with_character(ch)
+            .with_foreground(remembered_colour(colour_hint.foreground));
+        if let Some(background) = colour_hint.background {
+            render_cell = render_cell.with_background(remembered_colour(background));
+        }
+        fb.set_cell_relative_to_ctx(ctx, coord, 0, render_cell);
+    };
     let tile_layers = cell.tile_layers();
+    if let Some(EntityTile { tile, .. }) = tile_layers.floor {
+        render_tile(tile);
+    }
     if let Some(EntityTile { tile, .. }) = tile_layers.feature {
-        match tile {
-            Tile::Wall => {
-                fb.set_cell_relative_to_ctx(
-                    ctx,
-                    coord,
-                    0,
-                    RenderCell::default()
-                        .with_character('▒')
-                        .with_foreground(Rgba32::new_grey(127)),
-                );
-            }
-            _ => (),
+        render_tile(tile);
+    }
+    if let Some(EntityTile { tile, .. }) = tile_layers.item {
+        render_tile(tile);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(160);
+
+    fn press_after(key_repeat: &mut KeyRepeat, millis: u64) -> bool {
+        key_repeat.tick(Duration::from_millis(millis));
+        key_repeat.accept(KeyboardInput::Char('h'), INTERVAL)
+    }
+
+    #[test]
+    fn double_tap_is_not_throttled() {
+        let mut key_repeat = KeyRepeat::default();
+        assert!(press_after(&mut key_repeat, 0));
+        assert!(press_after(&mut key_repeat, 100));
+    }
+
+    #[test]
+    fn held_key_is_throttled() {
+        let mut key_repeat = KeyRepeat::default();
+        assert!(press_after(&mut key_repeat, 0));
+        let accepted = (0..12).filter(|_| press_after(&mut key_repeat, 30)).count();
+        // 360ms of repeats every 30ms, at most one per 160ms
+        assert_eq!(accepted, 2);
+    }
+
+    #[test]
+    fn confused_stumbles_into_walls_take_turns() {
+        let mut game = fixture::new_game("###\n#@#\n###\n").unwrap();
+        game.level.world.confuse(game.player_entity, 100);
+        for _ in 0..20 {
+            game.player_walk(Direction::North);
+        }
+        // every walk bumps into a wall, so only the stumbles take turns
+        assert!(game.turn > 0);
+    }
+
+    #[test]
+    fn walking_off_the_map_stays_put() {
+        let mut game = fixture::new_game("@.\n..\n").unwrap();
+        let outwards = [
+            Direction::North,
+            Direction::West,
+            Direction::NorthWest,
+            Direction::NorthEast,
+            Direction::SouthWest,
+        ];
+        for direction in outwards {
+            game.player_walk(direction);
+            assert_eq!(game.player_coord(), Some(Coord::new(0, 0)));
+        }
+        game.player_walk(Direction::SouthEast);
+        assert_eq!(game.player_coord(), Some(Coord::new(1, 1)));
+        for direction in outwards {
+            game.player_walk(direction.opposite());
+            assert_eq!(game.player_coord(), Some(Coord::new(1, 1)));
         }
     }
 }
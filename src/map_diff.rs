@@ -0,0 +1,126 @@
+//! Compares generated caves, to catch changes to level generation which break determinism.
+//! A cave is generated from a seed and compared cell by cell with the cave from the next
+//! seed, or with one written to a file by an earlier build. The two are printed side by side
+//! with the cells which differ highlighted.
+use crate::{cave, tile_char, world::World};
+use gridbugs::coord_2d::Coord;
+use rand::SeedableRng;
+use rand_isaac::Isaac64Rng;
+use std::{fs, io, path::PathBuf};
+
+/// ANSI escape codes to highlight a changed cell, and to go back to normal
+const HIGHLIGHT: &str = "\x1b[1;37;41m";
+const RESET: &str = "\x1b[0m";
+const GAP: &str = "    ";
+
+pub struct MapDiffArgs {
+    pub seed: Option<u64>,
+    pub depth: u32,
+    /// A map written by an earlier run of the tool to compare with instead of the next seed
+    pub against: Option<PathBuf>,
+    /// Where to write the map generated from the seed
+    pub write: Option<PathBuf>,
+}
+
+impl MapDiffArgs {
+    pub fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                seed = opt_opt::<u64, _>("SEED", "diff-maps")
+                    .desc("generate caves from this seed and the next, print a diff of them, then exit");
+                depth = opt_opt::<u32, _>("DEPTH", "diff-maps-depth")
+                    .desc("depth of the caves to compare (default 1)");
+                against = opt_opt::<PathBuf, _>("FILE", "diff-maps-against")
+                    .desc("compare with a map written by --diff-maps-write instead of the next seed");
+                write = opt_opt::<PathBuf, _>("FILE", "diff-maps-write")
+                    .desc("write the cave generated from the seed to a file to compare with later");
+            } in {
+                Self {
+                    seed,
+                    depth: depth.unwrap_or(1),
+                    against,
+                    write,
+                }
+            }
+        }
+    }
+}
+
+/// The character of the topmost thing in each cell, a row per line
+fn render(world: &World) -> String {
+    let size = world.size();
+    let mut text = String::new();
+    for y in 0..size.height() as i32 {
+        for x in 0..size.width() as i32 {
+            let ch = world
+                .tile_at_coord(Coord::new(x, y))
+                .map(tile_char)
+                .unwrap_or(' ');
+            text.push(ch);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn generate(seed: u64, depth: u32) -> String {
+    let mut rng = Isaac64Rng::seed_from_u64(seed);
+    render(&cave::generate(depth, &mut rng).world)
+}
+
+/// Prints the maps side by side with the cells which differ highlighted in both, and
+/// returns how many differ. Lines present in only one of the maps count as differing.
+fn print_diff(left: &str, right: &str) -> usize {
+    let left = left.lines().collect::<Vec<_>>();
+    let right = right.lines().collect::<Vec<_>>();
+    let width = left
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut differing = 0;
+    for i in 0..left.len().max(right.len()) {
+        let l = left.get(i).map(|line| line.chars().collect::<Vec<_>>());
+        let r = right.get(i).map(|line| line.chars().collect::<Vec<_>>());
+        let (l, r) = (l.unwrap_or_default(), r.unwrap_or_default());
+        let mut line = String::new();
+        for (this, other) in [(&l, &r), (&r, &l)] {
+            for x in 0..width.max(this.len()) {
+                let ch = this.get(x).cloned().unwrap_or(' ');
+                if this.get(x) == other.get(x) {
+                    line.push(ch);
+                } else {
+                    line.push_str(&format!("{}{}{}", HIGHLIGHT, ch, RESET));
+                }
+            }
+            line.push_str(GAP);
+        }
+        differing += (0..l.len().max(r.len()))
+            .filter(|&x| l.get(x) != r.get(x))
+            .count();
+        println!("{}", line.trim_end());
+    }
+    differing
+}
+
+/// Runs the comparison, returning whether the maps were identical
+pub fn run(args: &MapDiffArgs, seed: u64) -> io::Result<bool> {
+    let map = generate(seed, args.depth);
+    if let Some(path) = args.write.as_ref() {
+        fs::write(path, &map)?;
+    }
+    let (other, other_name) = match args.against.as_ref() {
+        Some(path) => (fs::read_to_string(path)?, path.display().to_string()),
+        None => (
+            generate(seed.wrapping_add(1), args.depth),
+            format!("seed {}", seed.wrapping_add(1)),
+        ),
+    };
+    println!(
+        "seed {} (left) vs {} (right), depth {}",
+        seed, other_name, args.depth
+    );
+    let differing = print_diff(&map, &other);
+    println!("{} cells differ", differing);
+    Ok(differing == 0)
+}
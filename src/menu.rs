@@ -0,0 +1,52 @@
+use gridbugs::chargrid::input::{keys, KeyboardInput};
+
+/// A vertical list of entries navigated with the arrow keys, one of which is selected
+pub struct Menu<T> {
+    entries: Vec<(T, &'static str)>,
+    selected: usize,
+}
+
+impl<T: Copy> Menu<T> {
+    pub fn new(entries: Vec<(T, &'static str)>) -> Self {
+        assert!(!entries.is_empty(), "menu has no entries");
+        Self {
+            entries,
+            selected: 0,
+        }
+    }
+
+    /// Moves the selection, returning the selected entry when it's chosen
+    pub fn update(&mut self, keyboard_input: KeyboardInput) -> Option<T> {
+        match keyboard_input {
+            KeyboardInput::Up => {
+                self.selected = self
+                    .selected
+                    .checked_sub(1)
+                    .unwrap_or(self.entries.len() - 1);
+            }
+            KeyboardInput::Down => self.selected = (self.selected + 1) % self.entries.len(),
+            keys::RETURN => return Some(self.entries[self.selected].0),
+            _ => (),
+        }
+        None
+    }
+
+    /// Selects and chooses the entry at an index, as when it's clicked
+    pub fn choose(&mut self, index: usize) -> Option<T> {
+        let &(entry, _) = self.entries.get(index)?;
+        self.selected = index;
+        Some(entry)
+    }
+
+    /// A line for each entry, with the selected entry marked
+    pub fn lines(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, (_, name))| {
+                let marker = if i == self.selected { '>' } else { ' ' };
+                format!("{} {}", marker, name)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct MessageLog {
+    messages: Vec<String>,
+}
+
+impl MessageLog {
+    pub fn push(&mut self, message: impl Into<String>) {
+        self.messages.push(message.into());
+    }
+
+    /// The most recent `count` messages, oldest first
+    pub fn recent(&self, count: usize) -> &[String] {
+        &self.messages[self.messages.len().saturating_sub(count)..]
+    }
+}
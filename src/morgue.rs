@@ -0,0 +1,21 @@
+//! A text summary of each finished run, written to the morgue directory so players can look
+//! back on or share how a run went
+use std::{
+    fs, io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MORGUE_DIR: &str = "morgue";
+
+/// Writes a run's summary to a new file in the morgue directory, returning its path
+pub fn write(contents: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(MORGUE_DIR)?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = PathBuf::from(MORGUE_DIR).join(format!("morgue-{}.txt", timestamp));
+    fs::write(&path, contents)?;
+    Ok(path)
+}
@@ -0,0 +1,109 @@
+//! Layered music which follows how much danger the player is in. A calm layer always plays,
+//! a tense layer fades in as hostiles come into view, and a combat layer joins it as they
+//! close in. The layers are looped from the stems in the directory named by the
+//! `RL1_MUSIC_DIR` environment variable. The audio player is only compiled in with the
+//! "audio" feature, otherwise updates are ignored.
+use std::time::Duration;
+
+/// Files in the music directory for each layer, from calmest to most intense
+const STEMS: [&str; 3] = ["calm.ogg", "tense.ogg", "combat.ogg"];
+/// How much of its full volume a layer can fade in or out by in a second
+const FADE_PER_SECOND: f32 = 0.5;
+
+#[cfg(feature = "audio")]
+mod player {
+    use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+    use std::{fs::File, io::BufReader, path::Path};
+
+    const MUSIC_DIR_ENV_VAR: &str = "RL1_MUSIC_DIR";
+
+    pub struct Player {
+        // audio stops when the stream is dropped
+        _stream: OutputStream,
+        sinks: Vec<Sink>,
+    }
+
+    fn play_looped(handle: &OutputStreamHandle, path: &Path) -> Option<Sink> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("failed to open {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let source = match Decoder::new(BufReader::new(file)) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("failed to decode {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        let sink = Sink::try_new(handle).ok()?;
+        sink.set_volume(0.0);
+        sink.append(source.repeat_infinite());
+        Some(sink)
+    }
+
+    impl Player {
+        /// Returns `None` if no music directory is configured or there's no audio device
+        pub fn start(stems: &[&str]) -> Option<Self> {
+            let dir = std::env::var(MUSIC_DIR_ENV_VAR).ok()?;
+            let (stream, handle) = OutputStream::try_default().ok()?;
+            let sinks = stems
+                .iter()
+                .map(|stem| play_looped(&handle, &Path::new(&dir).join(stem)))
+                .collect::<Option<Vec<_>>>()?;
+            Some(Self {
+                _stream: stream,
+                sinks,
+            })
+        }
+
+        pub fn set_volumes(&mut self, volumes: &[f32]) {
+            for (sink, &volume) in self.sinks.iter().zip(volumes) {
+                sink.set_volume(volume);
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+mod player {
+    pub struct Player;
+
+    impl Player {
+        pub fn start(_stems: &[&str]) -> Option<Self> {
+            None
+        }
+
+        pub fn set_volumes(&mut self, _volumes: &[f32]) {}
+    }
+}
+
+pub struct Music {
+    player: Option<player::Player>,
+    volumes: [f32; STEMS.len()],
+}
+
+impl Music {
+    pub fn start() -> Self {
+        Self {
+            player: player::Player::start(&STEMS),
+            volumes: [0.0; STEMS.len()],
+        }
+    }
+
+    /// Fades each layer towards its volume for the given danger, from 0 to 1
+    pub fn update(&mut self, danger: f32, since_last_tick: Duration) {
+        let player = match self.player.as_mut() {
+            Some(player) => player,
+            None => return,
+        };
+        let targets = [1.0, (danger * 2.0).min(1.0), (danger * 2.0 - 1.0).max(0.0)];
+        let max_change = FADE_PER_SECOND * since_last_tick.as_secs_f32();
+        for (volume, target) in self.volumes.iter_mut().zip(targets) {
+            *volume += (target - *volume).clamp(-max_change, max_change);
+        }
+        player.set_volumes(&self.volumes);
+    }
+}
@@ -0,0 +1,126 @@
+//! Mutators are optional rules chosen at the start of a run which change how it plays. Each
+//! is applied through a hook: some change levels as they're generated, and others change how
+//! the scheduler or combat works.
+use crate::world::World;
+use gridbugs::entity_table::Entity;
+use serde::{Deserialize, Serialize};
+
+/// Number of cells the player can see without light with the darkvision mutator
+const PLAYER_DARKVISION: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutator {
+    FastMonsters,
+    Darkness,
+    DoubleItems,
+    Darkvision,
+}
+
+impl Mutator {
+    pub const ALL: &'static [Self] = &[
+        Self::FastMonsters,
+        Self::Darkness,
+        Self::DoubleItems,
+        Self::Darkvision,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::FastMonsters => "fast monsters",
+            Self::Darkness => "darkness",
+            Self::DoubleItems => "double items",
+            Self::Darkvision => "darkvision",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::FastMonsters => "Every monster acts twice as often.",
+            Self::Darkness => "The only light is the one you carry.",
+            Self::DoubleItems => "Levels hold twice the items and monsters drop twice the loot.",
+            Self::Darkvision => "You can see a few cells away without light, in shades of grey.",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mutators {
+    /// One bit per mutator, indexed by position in `Mutator::ALL`
+    mutators: u8,
+}
+
+impl Mutators {
+    fn bit(mutator: Mutator) -> u8 {
+        1 << Mutator::ALL.iter().position(|&m| m == mutator).unwrap()
+    }
+
+    pub fn contains(self, mutator: Mutator) -> bool {
+        self.mutators & Self::bit(mutator) != 0
+    }
+
+    pub fn toggle(&mut self, mutator: Mutator) {
+        self.mutators ^= Self::bit(mutator);
+    }
+
+    /// Comma-separated names of the enabled mutators
+    pub fn name(self) -> String {
+        let names = Mutator::ALL
+            .iter()
+            .filter(|&&mutator| self.contains(mutator))
+            .map(|mutator| mutator.name())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    }
+
+    /// How many times faster than usual monsters gain energy
+    pub fn monster_speed_factor(self) -> u32 {
+        if self.contains(Mutator::FastMonsters) {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// How many times the loot table is rolled when a monster dies
+    pub fn loot_rolls(self) -> usize {
+        if self.contains(Mutator::DoubleItems) {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Changes the player at the start of a run
+    pub fn apply_to_player(self, world: &mut World, player: Entity) {
+        if self.contains(Mutator::Darkvision) {
+            world
+                .components
+                .darkvision
+                .insert(player, PLAYER_DARKVISION);
+        }
+    }
+
+    /// Changes a newly generated level
+    pub fn apply_to_world(self, world: &mut World) {
+        if self.contains(Mutator::Darkness) {
+            for light in world.fixed_lights().collect::<Vec<_>>() {
+                world.dim_light(light, 0);
+            }
+        }
+        if self.contains(Mutator::DoubleItems) {
+            let floor_items = world
+                .components
+                .item
+                .iter()
+                .filter_map(|(entity, &item)| Some((world.entity_coord(entity)?, item)))
+                .collect::<Vec<_>>();
+            for (coord, item) in floor_items {
+                world.spawn_item_near(coord, item);
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Puzzle objectives which a level's map can set with a line such as
+//! `; objective: boulders-on-plates`. The objective is checked at the end of every turn, and
+//! the player is rewarded the first time it's met.
+use crate::{components::Tile, world::World};
+use gridbugs::coord_2d::Coord;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Objective {
+    /// Every pressure plate has a boulder on it
+    BouldersOnPlates,
+    /// Every brazier is lit
+    LightBraziers,
+}
+
+impl FromStr for Objective {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "boulders-on-plates" => Ok(Self::BouldersOnPlates),
+            "light-braziers" => Ok(Self::LightBraziers),
+            other => Err(format!(
+                "unknown objective \"{}\" (expected boulders-on-plates or light-braziers)",
+                other
+            )),
+        }
+    }
+}
+
+impl Objective {
+    /// Instructions shown when the player arrives on the level
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::BouldersOnPlates => "Push a boulder onto every pressure plate.",
+            Self::LightBraziers => "Light every brazier.",
+        }
+    }
+
+    /// How many of the things the objective asks for are done, out of how many there are
+    pub fn progress(self, world: &World) -> (usize, usize) {
+        match self {
+            Self::BouldersOnPlates => {
+                let plates = world.coords_with_tile(Tile::Plate).collect::<Vec<_>>();
+                let covered = plates
+                    .iter()
+                    .filter(|&&coord| world.boulder_at_coord(coord).is_some())
+                    .count();
+                (covered, plates.len())
+            }
+            Self::LightBraziers => {
+                let lit = world.coords_with_tile(Tile::BrazierLit).count();
+                let unlit = world.coords_with_tile(Tile::Brazier).count();
+                (lit, lit + unlit)
+            }
+        }
+    }
+
+    /// Where the things still to be done are: plates without a boulder, or unlit braziers
+    pub fn targets(self, world: &World) -> Vec<Coord> {
+        match self {
+            Self::BouldersOnPlates => world
+                .coords_with_tile(Tile::Plate)
+                .filter(|&coord| world.boulder_at_coord(coord).is_none())
+                .collect(),
+            Self::LightBraziers => world.coords_with_tile(Tile::Brazier).collect(),
+        }
+    }
+
+    /// The victory predicate. Levels with nothing to do can't be won.
+    pub fn is_met(self, world: &World) -> bool {
+        let (done, total) = self.progress(world);
+        total > 0 && done == total
+    }
+}
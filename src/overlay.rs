@@ -0,0 +1,77 @@
+//! Highlights drawn over the map for cursors, targeting lines and path previews. They're
+//! collected separately from the map itself, in world coordinates, and drawn in a pass of
+//! their own after it, so nothing about them touches the visibility grid. Highlight colours
+//! are translucent, so the map shows through them, and highlights on the same cell are
+//! blended together.
+use gridbugs::{coord_2d::Coord, rgb_int::Rgba32};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Highlight {
+    /// The cell a cursor is on
+    Cursor,
+    /// A cell of the path travel would take
+    Path,
+    /// A cell a thrown item would pass through
+    Aim,
+    /// A cell a thrown item wouldn't reach
+    OutOfReach,
+    /// A cell travel avoids
+    Excluded,
+}
+
+impl Highlight {
+    fn colour(self) -> Rgba32 {
+        match self {
+            Self::Cursor => Rgba32::new(0, 191, 191, 191),
+            Self::Path => Rgba32::new(63, 191, 63, 95),
+            Self::Aim => Rgba32::new(0, 127, 127, 127),
+            Self::OutOfReach => Rgba32::new(191, 0, 0, 159),
+            Self::Excluded => Rgba32::new(191, 63, 127, 47),
+        }
+    }
+}
+
+/// Composites `top` over `bottom`, both of which may be translucent
+fn over(top: Rgba32, bottom: Rgba32) -> Rgba32 {
+    let top_alpha = top.a as u32;
+    let bottom_alpha = bottom.a as u32 * (255 - top_alpha) / 255;
+    let alpha = top_alpha + bottom_alpha;
+    if alpha == 0 {
+        return Rgba32::new(0, 0, 0, 0);
+    }
+    let channel = |t: u8, b: u8| ((t as u32 * top_alpha + b as u32 * bottom_alpha) / alpha) as u8;
+    Rgba32::new(
+        channel(top.r, bottom.r),
+        channel(top.g, bottom.g),
+        channel(top.b, bottom.b),
+        alpha as u8,
+    )
+}
+
+#[derive(Default)]
+pub struct Overlay {
+    cells: HashMap<Coord, Rgba32>,
+}
+
+impl Overlay {
+    /// Adds a highlight to a cell, on top of any it already has
+    pub fn highlight(&mut self, coord: Coord, highlight: Highlight) {
+        let colour = highlight.colour();
+        self.cells
+            .entry(coord)
+            .and_modify(|existing| *existing = over(colour, *existing))
+            .or_insert(colour);
+    }
+
+    pub fn highlight_all(&mut self, coords: impl IntoIterator<Item = Coord>, highlight: Highlight) {
+        for coord in coords {
+            self.highlight(coord, highlight);
+        }
+    }
+
+    /// Each highlighted cell with its blended colour
+    pub fn cells(&self) -> impl Iterator<Item = (Coord, Rgba32)> + '_ {
+        self.cells.iter().map(|(&coord, &colour)| (coord, colour))
+    }
+}
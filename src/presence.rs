@@ -0,0 +1,95 @@
+//! Reports the state of the current run to discord's rich presence. The discord client is
+//! only compiled in with the "discord" feature, otherwise updates are ignored.
+use std::time::{Duration, Instant};
+
+/// Discord rejects activity updates sent more frequently than this
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresenceStatus {
+    pub depth: u32,
+    pub turn: u64,
+}
+
+#[cfg(feature = "discord")]
+mod client {
+    use super::PresenceStatus;
+    use discord_rich_presence::{activity::Activity, DiscordIpc, DiscordIpcClient};
+
+    const CLIENT_ID_ENV_VAR: &str = "RL1_DISCORD_CLIENT_ID";
+
+    pub struct Client(DiscordIpcClient);
+
+    impl Client {
+        /// Returns `None` if no client id is configured or discord isn't running
+        pub fn connect() -> Option<Self> {
+            let client_id = std::env::var(CLIENT_ID_ENV_VAR).ok()?;
+            let mut client = DiscordIpcClient::new(&client_id).ok()?;
+            client.connect().ok()?;
+            Some(Self(client))
+        }
+
+        pub fn set_status(&mut self, status: PresenceStatus) {
+            let state = format!("Depth {}", status.depth);
+            let details = format!("Turn {}", status.turn);
+            let activity = Activity::new().state(&state).details(&details);
+            if let Err(e) = self.0.set_activity(activity) {
+                eprintln!("failed to update discord presence: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "discord"))]
+mod client {
+    use super::PresenceStatus;
+
+    pub struct Client;
+
+    impl Client {
+        pub fn connect() -> Option<Self> {
+            None
+        }
+
+        pub fn set_status(&mut self, _status: PresenceStatus) {}
+    }
+}
+
+pub struct Presence {
+    client: Option<client::Client>,
+    last_status: Option<PresenceStatus>,
+    last_update: Option<Instant>,
+}
+
+impl Presence {
+    pub fn connect() -> Self {
+        Self {
+            client: client::Client::connect(),
+            last_status: None,
+            last_update: None,
+        }
+    }
+
+    /// Sends the status to discord if it changed. Depth changes are sent immediately and
+    /// other changes are rate limited.
+    pub fn update(&mut self, status: PresenceStatus) {
+        let client = match self.client.as_mut() {
+            Some(client) => client,
+            None => return,
+        };
+        if self.last_status == Some(status) {
+            return;
+        }
+        let depth_changed = self.last_status.map(|s| s.depth) != Some(status.depth);
+        let rate_limited = self
+            .last_update
+            .map(|last_update| last_update.elapsed() < MIN_UPDATE_INTERVAL)
+            .unwrap_or(false);
+        if rate_limited && !depth_changed {
+            return;
+        }
+        client.set_status(status);
+        self.last_status = Some(status);
+        self.last_update = Some(Instant::now());
+    }
+}
@@ -0,0 +1,101 @@
+//! The quick menu holds up to eight favourite items, one in each direction from its centre.
+//! Opening it and pressing a direction uses the item in that slot, so a potion can be drunk
+//! or a weapon drawn without going through the inventory. Items are assigned to slots from
+//! the inventory. Slots hold a kind of item rather than a particular one, so a slot keeps
+//! working as items of its kind are used up and picked up.
+use crate::components::Item;
+use gridbugs::direction::Direction;
+use serde::{Deserialize, Serialize};
+
+/// The direction of each slot from the centre of the menu, clockwise from the top
+pub const SLOT_DIRECTIONS: [Direction; 8] = [
+    Direction::North,
+    Direction::NorthEast,
+    Direction::East,
+    Direction::SouthEast,
+    Direction::South,
+    Direction::SouthWest,
+    Direction::West,
+    Direction::NorthWest,
+];
+
+/// What using an item from the quick menu does with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuickUse {
+    Quaff,
+    Wield,
+    Throw,
+}
+
+impl QuickUse {
+    pub fn for_item(item: Item) -> Self {
+        if item == Item::Potion {
+            Self::Quaff
+        } else if item.grip().is_some() {
+            Self::Wield
+        } else {
+            Self::Throw
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Quaff => "drink",
+            Self::Wield => "wield",
+            Self::Throw => "throw",
+        }
+    }
+}
+
+fn slot_index(direction: Direction) -> usize {
+    SLOT_DIRECTIONS
+        .iter()
+        .position(|&d| d == direction)
+        .unwrap()
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct QuickSlots {
+    slots: [Option<Item>; 8],
+}
+
+impl QuickSlots {
+    pub fn get(&self, direction: Direction) -> Option<Item> {
+        self.slots[slot_index(direction)]
+    }
+
+    /// Puts an item in a slot, or empties the slot if the item is already in it
+    pub fn assign(&mut self, direction: Direction, item: Item) {
+        let slot = &mut self.slots[slot_index(direction)];
+        *slot = if *slot == Some(item) {
+            None
+        } else {
+            Some(item)
+        };
+    }
+
+    /// The menu laid out as a three by three grid of labels around an empty centre
+    pub fn lines(&self, cell_width: usize) -> Vec<String> {
+        let label = |direction: Direction| match self.get(direction) {
+            Some(item) => format!("{} {}", QuickUse::for_item(item).name(), item.name()),
+            None => "-".to_string(),
+        };
+        let row = |directions: [Option<Direction>; 3]| {
+            directions
+                .into_iter()
+                .map(|direction| {
+                    let text = direction.map(&label).unwrap_or_default();
+                    format!("{:^width$}", text, width = cell_width)
+                })
+                .collect::<String>()
+        };
+        use Direction::*;
+        vec![
+            row([Some(NorthWest), Some(North), Some(NorthEast)]),
+            String::new(),
+            row([Some(West), None, Some(East)]),
+            String::new(),
+            row([Some(SouthWest), Some(South), Some(SouthEast)]),
+        ]
+    }
+}
@@ -0,0 +1,158 @@
+//! Recording a run as the seed and settings it started with followed by every key the player
+//! pressed, and replaying recordings through the same game logic. Recordings are JSON lines:
+//! a header, then one line per key press. Only runs started with "New Game" can be
+//! recorded, as loading a save part way through can't be replayed. Keys are recorded before
+//! they're mapped to actions, so replays must use the same controls file as the recording.
+use crate::{
+    controls::{key_name, parse_key},
+    ruleset::Ruleset,
+    settings::Settings,
+};
+use gridbugs::chargrid::input::KeyboardInput;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub struct RecordingArgs {
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+}
+
+impl RecordingArgs {
+    pub fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                record = opt_opt::<PathBuf, _>("FILE", "record")
+                    .desc("record the seed and every key pressed in new games to a file");
+                replay = opt_opt::<PathBuf, _>("FILE", "replay")
+                    .desc("play back a recording made with --record");
+            } in {
+                Self { record, replay }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Header {
+    pub seed: u64,
+    pub ruleset: Ruleset,
+    pub settings: Settings,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Time since the start of the run
+    time_ms: u64,
+    /// The turn the game was on when the key was pressed, for detecting desyncs
+    turn: u64,
+    key: String,
+}
+
+pub struct RecordedInput {
+    pub turn: u64,
+    pub keyboard_input: KeyboardInput,
+}
+
+pub struct Recorder {
+    writer: BufWriter<File>,
+    elapsed: Duration,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P, header: &Header) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, header)?;
+        writeln!(writer)?;
+        Ok(Self {
+            writer,
+            elapsed: Duration::ZERO,
+        })
+    }
+
+    pub fn tick(&mut self, since_last_tick: Duration) {
+        self.elapsed += since_last_tick;
+    }
+
+    pub fn record(&mut self, turn: u64, keyboard_input: KeyboardInput) -> io::Result<()> {
+        let entry = Entry {
+            time_ms: self.elapsed.as_millis() as u64,
+            turn,
+            key: key_name(keyboard_input),
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        writeln!(self.writer)?;
+        // flushed after every key so the recording survives a crash
+        self.writer.flush()
+    }
+}
+
+pub struct Replay {
+    pub header: Header,
+    entries: VecDeque<Entry>,
+    elapsed: Duration,
+    diverged: bool,
+}
+
+impl Replay {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+        let header = lines
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty recording"))?;
+        let header = serde_json::from_str::<Header>(header)?;
+        let entries = lines
+            .map(serde_json::from_str::<Entry>)
+            .collect::<Result<VecDeque<_>, _>>()?;
+        Ok(Self {
+            header,
+            entries,
+            elapsed: Duration::ZERO,
+            diverged: false,
+        })
+    }
+
+    pub fn tick(&mut self, since_last_tick: Duration) {
+        self.elapsed += since_last_tick;
+    }
+
+    /// The next recorded key press, if it's time for it
+    pub fn next_due(&mut self) -> Option<RecordedInput> {
+        loop {
+            let entry = self.entries.front()?;
+            if Duration::from_millis(entry.time_ms) > self.elapsed {
+                return None;
+            }
+            let entry = self.entries.pop_front()?;
+            match parse_key(&entry.key) {
+                Ok(keyboard_input) => {
+                    return Some(RecordedInput {
+                        turn: entry.turn,
+                        keyboard_input,
+                    })
+                }
+                Err(e) => eprintln!("skipping recorded key: {}", e),
+            }
+        }
+    }
+
+    /// Checks that a replayed key press arrived on the turn it was recorded on. Returns true
+    /// the first time one doesn't, meaning the replay no longer matches the original run.
+    pub fn check_diverged(&mut self, recorded_input: &RecordedInput, turn: u64) -> bool {
+        if self.diverged || recorded_input.turn == turn {
+            return false;
+        }
+        self.diverged = true;
+        true
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{ai::AiDifficulty, mutator::Mutators};
+use serde::{Deserialize, Serialize};
+
+/// How the levels of a run are laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RunMode {
+    /// The fixed levels of the dungeon, or a map loaded from a file
+    #[default]
+    Dungeon,
+    /// An endless descent through caves generated as the player reaches them
+    Endless,
+    /// Waves of monsters in a small arena
+    Arena,
+}
+
+impl RunMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Dungeon => "dungeon",
+            Self::Endless => "endless",
+            Self::Arena => "arena",
+        }
+    }
+}
+
+/// Rules chosen at the start of a run which stay fixed for the rest of it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Ruleset {
+    pub ai_difficulty: AiDifficulty,
+    /// Seed for the run's random number generator. A new game picks a random seed if this
+    /// isn't set.
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub mode: RunMode,
+    #[serde(default)]
+    pub mutators: Mutators,
+}
+
+impl Ruleset {
+    pub fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                ai_difficulty = opt_opt::<AiDifficulty, _>("DIFFICULTY", "ai-difficulty")
+                    .desc("how cunning monsters are (easy, normal or hard)");
+                seed = opt_opt::<u64, _>("INT", "seed")
+                    .desc("seed for the random number generator, to reproduce a run");
+            } in {
+                Self {
+                    ai_difficulty: ai_difficulty.unwrap_or(AiDifficulty::Normal),
+                    seed,
+                    mode: RunMode::Dungeon,
+                    mutators: Mutators::default(),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Reading and writing save files. Saves start with a small header recording how the body is
+//! stored and a checksum of the serialized game, so damaged or truncated saves are reported
+//! instead of loading garbage. Files without the header are assumed to be bare serialized
+//! games, as written by older versions.
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"RL1S";
+const FORMAT_VERSION: u8 = 1;
+const FLAG_COMPRESSED: u8 = 1;
+/// Magic, version, flags, payload length (u64) and checksum (u32)
+const HEADER_LENGTH: usize = 4 + 1 + 1 + 8 + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveFormat {
+    Compressed,
+    /// Uncompressed saves are larger but can be inspected with ordinary tools
+    Plain,
+}
+
+impl SaveFormat {
+    pub fn parser() -> impl meap::Parser<Item = Self> {
+        meap::let_map! {
+            let {
+                plain = flag("plain-saves").desc("write uncompressed save files, for debugging");
+            } in {
+                if plain { Self::Plain } else { Self::Compressed }
+            }
+        }
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Writes the serialized game to a file, replacing any previous save only once the new one
+/// has been written completely
+pub fn write<P: AsRef<Path>>(path: P, payload: &[u8], format: SaveFormat) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut bytes = Vec::with_capacity(HEADER_LENGTH + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.push(match format {
+        SaveFormat::Compressed => FLAG_COMPRESSED,
+        SaveFormat::Plain => 0,
+    });
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    match format {
+        SaveFormat::Compressed => {
+            let mut encoder = GzEncoder::new(bytes, Compression::default());
+            encoder.write_all(payload)?;
+            bytes = encoder.finish()?;
+        }
+        SaveFormat::Plain => bytes.extend_from_slice(payload),
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(tmp_path, path)
+}
+
+/// Reads the serialized game from a file written in any format, checking it arrived intact
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    if !bytes.starts_with(MAGIC) {
+        return Ok(bytes);
+    }
+    if bytes.len() < HEADER_LENGTH {
+        return Err(invalid_data("save file is truncated"));
+    }
+    let (header, body) = bytes.split_at(HEADER_LENGTH);
+    let version = header[4];
+    if version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "unsupported save format version {}",
+            version
+        )));
+    }
+    let flags = header[5];
+    let length = u64::from_le_bytes(header[6..14].try_into().unwrap());
+    let checksum = u32::from_le_bytes(header[14..18].try_into().unwrap());
+    let payload = if flags & FLAG_COMPRESSED != 0 {
+        // the length is only trusted once the checksum matches, so it doesn't size the
+        // buffer, and decompression stops just past it in case the body is damaged
+        let mut payload = Vec::new();
+        GzDecoder::new(body)
+            .take(length.saturating_add(1))
+            .read_to_end(&mut payload)
+            .map_err(|e| invalid_data(format!("save file is damaged: {}", e)))?;
+        payload
+    } else {
+        body.to_vec()
+    };
+    if payload.len() as u64 != length {
+        return Err(invalid_data("save file is truncated"));
+    }
+    if crc32fast::hash(&payload) != checksum {
+        return Err(invalid_data("save file is damaged (checksum mismatch)"));
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Writes a save, overwrites the payload length in its header, then reads it back
+    fn with_length(payload: &[u8], format: SaveFormat, length: u64) -> io::Result<Vec<u8>> {
+        let path =
+            std::env::temp_dir().join(format!("rl1-save-file-test-{:?}-{}.bin", format, length));
+        write(&path, payload, format)?;
+        let mut bytes = fs::read(&path)?;
+        bytes[6..14].copy_from_slice(&length.to_le_bytes());
+        fs::write(&path, bytes)?;
+        let result = read(&path);
+        fs::remove_file(&path)?;
+        result
+    }
+
+    #[test]
+    fn round_trip() {
+        for format in [SaveFormat::Plain, SaveFormat::Compressed] {
+            assert_eq!(with_length(b"game", format, 4).unwrap(), b"game");
+        }
+    }
+
+    #[test]
+    fn huge_length_is_rejected() {
+        for format in [SaveFormat::Plain, SaveFormat::Compressed] {
+            let error = with_length(b"game", format, u64::MAX).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
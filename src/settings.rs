@@ -0,0 +1,311 @@
+use crate::{
+    components::{Item, ItemCategory},
+    fov::FovAlgorithm,
+    travel::TravelInterrupts,
+};
+use gridbugs::rgb_int::Rgb24;
+use serde::{Deserialize, Serialize};
+use std::{fs, io, time::Duration};
+
+const SETTINGS_PATH: &str = "settings.json";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnimationSpeed {
+    Half,
+    #[default]
+    Normal,
+    OneAndAHalf,
+    Double,
+    Triple,
+    Instant,
+}
+
+impl AnimationSpeed {
+    pub const ALL: &'static [Self] = &[
+        Self::Half,
+        Self::Normal,
+        Self::OneAndAHalf,
+        Self::Double,
+        Self::Triple,
+        Self::Instant,
+    ];
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&s| s == self).unwrap()
+    }
+
+    pub fn faster(self) -> Self {
+        Self::ALL[(self.index() + 1).min(Self::ALL.len() - 1)]
+    }
+
+    pub fn slower(self) -> Self {
+        Self::ALL[self.index().saturating_sub(1)]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Half => "0.5x",
+            Self::Normal => "1x",
+            Self::OneAndAHalf => "1.5x",
+            Self::Double => "2x",
+            Self::Triple => "3x",
+            Self::Instant => "instant",
+        }
+    }
+
+    /// Scales a duration that elapses at normal speed. Returns `None` when animations should
+    /// complete instantly.
+    pub fn scale(self, duration: Duration) -> Option<Duration> {
+        match self {
+            Self::Half => Some(duration * 2),
+            Self::Normal => Some(duration),
+            Self::OneAndAHalf => Some(duration * 2 / 3),
+            Self::Double => Some(duration / 2),
+            Self::Triple => Some(duration / 3),
+            Self::Instant => None,
+        }
+    }
+}
+
+/// How long the player has to act before a turn passes without them. With the timer off the
+/// game waits for the player indefinitely.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TurnTimer {
+    #[default]
+    Off,
+    Slow,
+    Normal,
+    Fast,
+}
+
+impl TurnTimer {
+    pub const ALL: &'static [Self] = &[Self::Off, Self::Slow, Self::Normal, Self::Fast];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&t| t == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Slow => "2s",
+            Self::Normal => "1s",
+            Self::Fast => "0.5s",
+        }
+    }
+
+    pub fn interval(self) -> Option<Duration> {
+        match self {
+            Self::Off => None,
+            Self::Slow => Some(Duration::from_millis(2000)),
+            Self::Normal => Some(Duration::from_millis(1000)),
+            Self::Fast => Some(Duration::from_millis(500)),
+        }
+    }
+}
+
+/// Whether one kind of item is picked up automatically, which can override its category
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemRule {
+    /// Picked up if its category is
+    Category,
+    Always,
+    Never,
+}
+
+impl ItemRule {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Category => "by category",
+            Self::Always => "always",
+            Self::Never => "never",
+        }
+    }
+}
+
+/// The item categories the player picks up automatically when walking over them, along with
+/// exceptions for individual kinds of item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoPickup {
+    /// One bit per category, indexed by position in `ItemCategory::ALL`
+    categories: u8,
+    /// Items picked up whatever their category, one bit per item indexed by position in
+    /// `Item::ALL`
+    #[serde(default)]
+    always: u16,
+    /// Items left alone whatever their category, indexed like `always`
+    #[serde(default)]
+    never: u16,
+}
+
+impl AutoPickup {
+    fn bit(category: ItemCategory) -> u8 {
+        1 << ItemCategory::ALL
+            .iter()
+            .position(|&c| c == category)
+            .unwrap()
+    }
+
+    fn item_bit(item: Item) -> u16 {
+        1 << Item::ALL.iter().position(|&i| i == item).unwrap()
+    }
+
+    pub fn item_rule(self, item: Item) -> ItemRule {
+        if self.always & Self::item_bit(item) != 0 {
+            ItemRule::Always
+        } else if self.never & Self::item_bit(item) != 0 {
+            ItemRule::Never
+        } else {
+            ItemRule::Category
+        }
+    }
+
+    /// Moves an item on to the next rule, from following its category to always being
+    /// picked up, to never being picked up, and back
+    pub fn cycle_item_rule(&mut self, item: Item) {
+        let bit = Self::item_bit(item);
+        match self.item_rule(item) {
+            ItemRule::Category => self.always |= bit,
+            ItemRule::Always => {
+                self.always &= !bit;
+                self.never |= bit;
+            }
+            ItemRule::Never => self.never &= !bit,
+        }
+    }
+
+    /// Whether to pick up an item by walking over it, going by its own rule if it has one
+    /// and otherwise by its category
+    pub fn picks_up(self, item: Item) -> bool {
+        match self.item_rule(item) {
+            ItemRule::Always => true,
+            ItemRule::Never => false,
+            ItemRule::Category => item
+                .category()
+                .is_some_and(|category| self.contains(category)),
+        }
+    }
+
+    pub fn contains(self, category: ItemCategory) -> bool {
+        self.categories & Self::bit(category) != 0
+    }
+
+    pub fn toggle(&mut self, category: ItemCategory) {
+        self.categories ^= Self::bit(category);
+    }
+
+    /// Comma-separated names of the enabled categories
+    pub fn name(self) -> String {
+        let names = ItemCategory::ALL
+            .iter()
+            .filter(|&&category| self.contains(category))
+            .map(|category| category.name())
+            .collect::<Vec<_>>();
+        let name = if names.is_empty() {
+            "off".to_string()
+        } else {
+            names.join(", ")
+        };
+        match (self.always | self.never).count_ones() {
+            0 => name,
+            1 => format!("{} (1 exception)", name),
+            n => format!("{} ({} exceptions)", name, n),
+        }
+    }
+}
+
+impl Default for AutoPickup {
+    fn default() -> Self {
+        Self {
+            categories: Self::bit(ItemCategory::Gold),
+            always: 0,
+            never: 0,
+        }
+    }
+}
+
+/// Warms or cools the colour of everything lit on the map, to suit the player's display or
+/// taste. Positive temperatures are warmer, pushing colours towards red, and negative ones
+/// are cooler, pushing them towards blue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WhiteBalance {
+    temperature: i8,
+}
+
+impl WhiteBalance {
+    const MAX_TEMPERATURE: i8 = 5;
+    /// Percentage by which each step of temperature raises one of red and blue and lowers
+    /// the other
+    const PERCENT_PER_STEP: i32 = 6;
+
+    pub fn warmer(self) -> Self {
+        Self {
+            temperature: (self.temperature + 1).min(Self::MAX_TEMPERATURE),
+        }
+    }
+
+    pub fn cooler(self) -> Self {
+        Self {
+            temperature: (self.temperature - 1).max(-Self::MAX_TEMPERATURE),
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self.temperature {
+            0 => "neutral".to_string(),
+            t if t > 0 => format!("warm {}", t),
+            t => format!("cool {}", -t),
+        }
+    }
+
+    pub fn apply(self, colour: Rgb24) -> Rgb24 {
+        if self.temperature == 0 {
+            return colour;
+        }
+        let shift = self.temperature as i32 * Self::PERCENT_PER_STEP;
+        let scale = |channel: u8, percent: i32| (channel as i32 * percent / 100).min(255) as u8;
+        Rgb24::new(
+            scale(colour.r, 100 + shift),
+            colour.g,
+            scale(colour.b, 100 - shift),
+        )
+    }
+}
+
+/// Minimum time between two identical key presses being accepted at normal speed. Holding a
+/// key down makes the OS generate repeated presses, and this limits how fast they are applied.
+pub const KEY_REPEAT_INTERVAL: Duration = Duration::from_millis(80);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub animation_speed: AnimationSpeed,
+    pub fov_algorithm: FovAlgorithm,
+    pub turn_timer: TurnTimer,
+    pub auto_pickup: AutoPickup,
+    /// Shows the run timer and exports splits when a run ends
+    pub speedrun_timer: bool,
+    pub travel_interrupts: TravelInterrupts,
+    pub white_balance: WhiteBalance,
+}
+
+impl Settings {
+    /// Loads the settings from disk, using the defaults if there aren't any saved yet
+    pub fn load() -> Self {
+        fs::read_to_string(SETTINGS_PATH)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(SETTINGS_PATH, serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn key_repeat_interval(&self) -> Duration {
+        self.animation_speed
+            .scale(KEY_REPEAT_INTERVAL)
+            .unwrap_or(Duration::ZERO)
+    }
+}
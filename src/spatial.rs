@@ -11,4 +11,3 @@ spatial_table::declare_layers_module! {
 pub use layers::{Layer, Layers};
 pub type SpatialTable = spatial_table::SpatialTable<Layers>;
 pub type Location = spatial_table::Location<Layer>;
-pub use spatial_table::UpdateError;
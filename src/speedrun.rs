@@ -0,0 +1,92 @@
+//! Timing runs for speedrunners. The timer counts real time spent playing, stopping while the
+//! game is paused, and records a split the first time the player reaches each depth. With
+//! the speedrun timer option on, the timer is shown beside the map and the splits are
+//! written to a file when the run ends.
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::Duration,
+};
+
+pub const SPLITS_PATH: &str = "splits.csv";
+
+/// Formats a duration as minutes, seconds and hundredths, e.g. "12:34.56"
+pub fn format_duration(duration: Duration) -> String {
+    let centis = duration.as_millis() / 10;
+    format!(
+        "{}:{:02}.{:02}",
+        centis / 6000,
+        (centis / 100) % 60,
+        centis % 100
+    )
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Split {
+    pub depth: u32,
+    /// Time since the start of the run
+    pub time: Duration,
+    pub turn: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunTimer {
+    elapsed: Duration,
+    /// The first arrival at each depth below the first, in order
+    splits: Vec<Split>,
+}
+
+impl RunTimer {
+    pub fn tick(&mut self, since_last_tick: Duration) {
+        self.elapsed += since_last_tick;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Records a split if this is the deepest the player has been
+    pub fn reach_depth(&mut self, depth: u32, turn: u64) {
+        let deepest = self.splits.last().map(|split| split.depth).unwrap_or(1);
+        if depth > deepest {
+            self.splits.push(Split {
+                depth,
+                time: self.elapsed,
+                turn,
+            });
+        }
+    }
+
+    /// The most recent split, if any depth below the first has been reached
+    pub fn last_split(&self) -> Option<&Split> {
+        self.splits.last()
+    }
+
+    /// Writes each split as a row of CSV, with the time spent on the level before it and the
+    /// total time so far, followed by a row for the end of the run
+    pub fn export(&self, turn: u64) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(SPLITS_PATH)?);
+        writeln!(writer, "split,segment,time,turn")?;
+        let mut previous = Duration::ZERO;
+        for split in &self.splits {
+            writeln!(
+                writer,
+                "depth {},{},{},{}",
+                split.depth,
+                format_duration(split.time - previous),
+                format_duration(split.time),
+                split.turn
+            )?;
+            previous = split.time;
+        }
+        writeln!(
+            writer,
+            "end,{},{},{}",
+            format_duration(self.elapsed - previous),
+            format_duration(self.elapsed),
+            turn
+        )?;
+        writer.flush()
+    }
+}
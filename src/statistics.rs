@@ -0,0 +1,163 @@
+//! Statistics gathered across every run played under a profile. The journal of each finished
+//! run is stored in the profile's directory, and the statistics screen is worked out from
+//! all of them whenever it's opened, so anything it counts must be recorded in the journal.
+use crate::{
+    components::{Item, Tile},
+    journal::{GameEvent, Journal},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const PROFILES_DIR: &str = "profiles";
+pub const DEFAULT_PROFILE: &str = "default";
+/// Number of rows shown in each table of the statistics screen
+const TOP_ROWS: usize = 8;
+
+/// What's kept of a finished run
+#[derive(Serialize, Deserialize)]
+pub struct StoredRun {
+    pub won: bool,
+    pub turns: u64,
+    pub journal: Journal,
+}
+
+/// A named set of stored runs
+pub struct Profile {
+    name: String,
+}
+
+impl Profile {
+    pub fn new(name: Option<String>) -> Self {
+        Self {
+            name: name.unwrap_or_else(|| DEFAULT_PROFILE.to_string()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn runs_dir(&self) -> PathBuf {
+        PathBuf::from(PROFILES_DIR).join(&self.name).join("runs")
+    }
+
+    /// Stores a finished run in a new file in the profile's directory
+    pub fn store(&self, run: &StoredRun) -> io::Result<()> {
+        let dir = self.runs_dir();
+        fs::create_dir_all(&dir)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let path = dir.join(format!("run-{}.json", timestamp));
+        fs::write(path, serde_json::to_string(run)?)
+    }
+
+    /// Aggregates every stored run of the profile, skipping any which can't be read
+    pub fn statistics(&self) -> Statistics {
+        let mut statistics = Statistics::default();
+        let entries = match fs::read_dir(self.runs_dir()) {
+            Ok(entries) => entries,
+            Err(_) => return statistics,
+        };
+        for entry in entries.flatten() {
+            let run = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|s| serde_json::from_str::<StoredRun>(&s).ok());
+            if let Some(run) = run {
+                statistics.add(&run);
+            }
+        }
+        statistics
+    }
+}
+
+fn killer_name(killer: Option<Tile>) -> &'static str {
+    killer.map(|tile| tile.name()).unwrap_or("unknown causes")
+}
+
+fn weapon_name(weapon: Option<Item>) -> &'static str {
+    weapon.map(|item| item.name()).unwrap_or("bare hands")
+}
+
+/// Counts sorted from most to least common, keeping the first `TOP_ROWS`
+fn top(counts: &BTreeMap<&'static str, u32>) -> Vec<(&'static str, u32)> {
+    let mut counts = counts
+        .iter()
+        .map(|(&name, &count)| (name, count))
+        .collect::<Vec<_>>();
+    counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    counts.truncate(TOP_ROWS);
+    counts
+}
+
+#[derive(Default)]
+pub struct Statistics {
+    runs: u32,
+    wins: u32,
+    turns: u64,
+    kills: BTreeMap<&'static str, u32>,
+    deaths: BTreeMap<&'static str, u32>,
+    weapon_kills: BTreeMap<&'static str, u32>,
+}
+
+impl Statistics {
+    fn add(&mut self, run: &StoredRun) {
+        self.runs += 1;
+        if run.won {
+            self.wins += 1;
+        }
+        self.turns += run.turns;
+        for entry in run.journal.entries() {
+            match entry.event {
+                GameEvent::Died {
+                    victim: Some(Tile::Player),
+                    killer,
+                } => *self.deaths.entry(killer_name(killer)).or_default() += 1,
+                GameEvent::Died {
+                    victim: Some(victim),
+                    killer: Some(Tile::Player),
+                } => *self.kills.entry(victim.name()).or_default() += 1,
+                GameEvent::KilledWith(weapon) => {
+                    *self.weapon_kills.entry(weapon_name(weapon)).or_default() += 1
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// The weapon the most monsters have been killed with
+    fn favourite_weapon(&self) -> Option<(&'static str, u32)> {
+        top(&self.weapon_kills).first().cloned()
+    }
+
+    /// The lines of the statistics screen
+    pub fn lines(&self) -> Vec<String> {
+        if self.runs == 0 {
+            return Vec::new();
+        }
+        let mut lines = vec![
+            format!("Runs: {} ({} won)", self.runs, self.wins),
+            format!("Turns played: {}", self.turns),
+        ];
+        if let Some((weapon, kills)) = self.favourite_weapon() {
+            lines.push(format!("Favourite weapon: {} ({} kills)", weapon, kills));
+        }
+        for (title, counts) in [("Kills", &self.kills), ("Deaths", &self.deaths)] {
+            lines.push(String::new());
+            lines.push(title.to_string());
+            if counts.is_empty() {
+                lines.push("  none".to_string());
+            }
+            for (name, count) in top(counts) {
+                lines.push(format!("  {:4} {}", count, name));
+            }
+        }
+        lines
+    }
+}
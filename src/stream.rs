@@ -0,0 +1,116 @@
+//! Mirrors each rendered frame to an external consumer (e.g. a web viewer or stream overlay)
+//! as a line of JSON. Frames are only written when they differ from the previous frame.
+use gridbugs::chargrid::prelude::*;
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    io::{self, BufWriter, Write},
+    net::TcpStream,
+};
+
+pub enum StreamTarget {
+    Stdout,
+    Tcp(String),
+}
+
+impl StreamTarget {
+    pub fn parser() -> impl meap::Parser<Item = Option<Self>> {
+        meap::choose_at_most_one! {
+            flag("stream-stdout").some_if(Self::Stdout),
+            opt_opt::<String, _>("ADDR", "stream-tcp").map(|addr| addr.map(Self::Tcp)),
+        }
+    }
+
+    pub fn connect(self) -> io::Result<FrameStream> {
+        let writer: Box<dyn Write> = match self {
+            Self::Stdout => Box::new(io::stdout()),
+            Self::Tcp(addr) => Box::new(TcpStream::connect(addr)?),
+        };
+        Ok(FrameStream {
+            writer: BufWriter::new(writer),
+            previous: Vec::new(),
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+struct StreamCell {
+    ch: char,
+    fg: [u8; 3],
+    bg: [u8; 3],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    bold: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    underline: bool,
+}
+
+#[derive(Serialize)]
+struct StreamFrame<'a> {
+    width: u32,
+    height: u32,
+    cells: &'a [StreamCell],
+}
+
+pub struct FrameStream {
+    writer: BufWriter<Box<dyn Write>>,
+    previous: Vec<StreamCell>,
+}
+
+impl FrameStream {
+    fn write_frame(&mut self, fb: &FrameBuffer) -> io::Result<()> {
+        let cells = fb
+            .iter()
+            .map(|cell| StreamCell {
+                ch: cell.character,
+                fg: [cell.foreground.r, cell.foreground.g, cell.foreground.b],
+                bg: [cell.background.r, cell.background.g, cell.background.b],
+                bold: cell.bold,
+                underline: cell.underline,
+            })
+            .collect::<Vec<_>>();
+        if cells == self.previous {
+            return Ok(());
+        }
+        let frame = StreamFrame {
+            width: fb.size().width(),
+            height: fb.size().height(),
+            cells: &cells,
+        };
+        serde_json::to_writer(&mut self.writer, &frame)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        self.previous = cells;
+        Ok(())
+    }
+}
+
+/// Wraps a component, writing everything rendered so far to the frame stream after the
+/// component renders
+pub struct Streamed<C> {
+    pub component: C,
+    pub frame_stream: RefCell<Option<FrameStream>>,
+}
+
+impl<C: Component> Component for Streamed<C> {
+    type Output = C::Output;
+    type State = C::State;
+
+    fn render(&self, state: &Self::State, ctx: Ctx, fb: &mut FrameBuffer) {
+        self.component.render(state, ctx, fb);
+        let mut frame_stream = self.frame_stream.borrow_mut();
+        if let Some(stream) = frame_stream.as_mut() {
+            if let Err(e) = stream.write_frame(fb) {
+                eprintln!("failed to stream frame, stopping stream: {}", e);
+                *frame_stream = None;
+            }
+        }
+    }
+
+    fn update(&mut self, state: &mut Self::State, ctx: Ctx, event: Event) -> Self::Output {
+        self.component.update(state, ctx, event)
+    }
+
+    fn size(&self, state: &Self::State, ctx: Ctx) -> Size {
+        self.component.size(state, ctx)
+    }
+}
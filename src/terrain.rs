@@ -0,0 +1,359 @@
+//! Parsing levels from text maps. The built-in dungeon is baked into the binary, and a
+//! single-level dungeon can be loaded from a file with `--map`, in which case the map is
+//! checked up front so mistakes are reported instead of crashing part way through a parse.
+//! Lines starting with ';' hold settings for the level rather than terrain.
+use crate::{
+    components::{Item, Spawner, Tile, Trap},
+    darkness::Hazard,
+    objective::Objective,
+    spatial::{Layer, Location},
+    world::World,
+};
+use gridbugs::{
+    coord_2d::{Coord, Size},
+    entity_table::Entity,
+    rgb_int::Rgb24,
+};
+use std::{fmt, fs, io, path::Path};
+
+/// Terrain for each level of the built-in dungeon, from the top down
+pub const LEVELS: &[&str] = &[
+    include_str!("./terrain.txt"),
+    include_str!("./terrain2.txt"),
+    include_str!("./terrain3.txt"),
+];
+
+/// Where a character is in the map's text, with 1-based line and column numbers as they
+/// appear in the file. Lines holding settings count, so these don't match map coords.
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    line: usize,
+    column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum TerrainError {
+    Empty,
+    UnknownChar {
+        ch: char,
+        position: Position,
+    },
+    RaggedRow {
+        line: usize,
+        width: usize,
+        expected: usize,
+    },
+    TooLarge {
+        width: usize,
+        height: usize,
+    },
+    MissingPlayer,
+    MultiplePlayers {
+        first: Position,
+        second: Position,
+    },
+    BadSetting(String),
+}
+
+impl fmt::Display for TerrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the map is empty"),
+            Self::UnknownChar { ch, position } => {
+                write!(f, "unknown character {:?} at {}", ch, position)
+            }
+            Self::RaggedRow {
+                line,
+                width,
+                expected,
+            } => write!(
+                f,
+                "line {} is {} characters wide but the first row is {}",
+                line, width, expected
+            ),
+            Self::TooLarge { width, height } => write!(
+                f,
+                "the map is {}x{} but can be at most {}x{}",
+                width,
+                height,
+                u16::MAX,
+                u16::MAX
+            ),
+            Self::MissingPlayer => write!(f, "the map has no player ('@')"),
+            Self::MultiplePlayers { first, second } => write!(
+                f,
+                "the map has more than one player ('@'), at {} and {}",
+                first, second
+            ),
+            Self::BadSetting(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub struct Terrain {
+    pub world: World,
+    pub player_entity: Option<Entity>,
+    pub objective: Option<Objective>,
+    pub hazard: Option<Hazard>,
+}
+
+/// Applies a `; key: value` setting line
+fn parse_setting(
+    line: &str,
+    objective: &mut Option<Objective>,
+    hazard: &mut Option<Hazard>,
+) -> Result<(), TerrainError> {
+    let (key, value) = line.split_once(':').ok_or_else(|| {
+        TerrainError::BadSetting(format!("expected \"key: value\" in {:?}", line))
+    })?;
+    match key.trim() {
+        "objective" => {
+            *objective = Some(value.trim().parse().map_err(TerrainError::BadSetting)?);
+            Ok(())
+        }
+        "hazard" => {
+            *hazard = Some(value.trim().parse().map_err(TerrainError::BadSetting)?);
+            Ok(())
+        }
+        other => Err(TerrainError::BadSetting(format!(
+            "unknown setting \"{}\"",
+            other
+        ))),
+    }
+}
+
+impl Terrain {
+    pub fn parse(s: &str) -> Result<Self, TerrainError> {
+        let mut objective = None;
+        let mut hazard = None;
+        // each row of the map with the 1-based number of the line it's on
+        let mut rows = Vec::new();
+        for (i, line) in s
+            .split('\n')
+            .map(|row| row.trim_end_matches('\r'))
+            .enumerate()
+        {
+            if let Some(setting) = line.strip_prefix(';') {
+                parse_setting(setting, &mut objective, &mut hazard)?;
+            } else if !line.is_empty() {
+                rows.push((i + 1, line));
+            }
+        }
+        let width = rows.first().ok_or(TerrainError::Empty)?.1.chars().count();
+        for &(line, row) in &rows {
+            let row_width = row.chars().count();
+            if row_width != width {
+                return Err(TerrainError::RaggedRow {
+                    line,
+                    width: row_width,
+                    expected: width,
+                });
+            }
+        }
+        let size = match (u16::try_from(width), u16::try_from(rows.len())) {
+            (Ok(width), Ok(height)) => Size::new_u16(width, height),
+            _ => {
+                return Err(TerrainError::TooLarge {
+                    width,
+                    height: rows.len(),
+                })
+            }
+        };
+        let mut world = World::new(size);
+        let mut player = None;
+        for (y, &(line, row)) in rows.iter().enumerate() {
+            for (x, ch) in row.chars().enumerate() {
+                let coord = Coord::new(x as i32, y as i32);
+                let position = Position {
+                    line,
+                    column: x + 1,
+                };
+                match ch {
+                    '.' => {
+                        world.spawn_floor(coord);
+                    }
+                    'R' => {
+                        world.spawn_floor(coord);
+                        world.spawn_flickering_light(coord, Rgb24::new(255, 0, 0));
+                    }
+                    'G' => {
+                        world.spawn_floor(coord);
+                        world.spawn_light(coord, Rgb24::new(0, 255, 0));
+                    }
+                    '#' => {
+                        world.spawn_wall(coord);
+                    }
+                    'g' => {
+                        world.spawn_floor(coord);
+                        world.spawn_goblin(coord);
+                    }
+                    'o' => {
+                        world.spawn_floor(coord);
+                        world.spawn_orc(coord);
+                    }
+                    '!' => {
+                        world.spawn_floor(coord);
+                        world.spawn_item(coord, Item::Potion);
+                    }
+                    '-' => {
+                        world.spawn_floor(coord);
+                        world.spawn_item(coord, Item::Key);
+                    }
+                    ')' => {
+                        world.spawn_floor(coord);
+                        world.spawn_item(coord, Item::Dagger);
+                    }
+                    '|' => {
+                        world.spawn_floor(coord);
+                        world.spawn_item(coord, Item::Sword);
+                    }
+                    '/' => {
+                        world.spawn_floor(coord);
+                        world.spawn_item(coord, Item::Greataxe);
+                    }
+                    ']' => {
+                        world.spawn_floor(coord);
+                        world.spawn_item(coord, Item::Shield);
+                    }
+                    '+' => {
+                        world.spawn_floor(coord);
+                        world.spawn_door(coord);
+                    }
+                    '^' => {
+                        world.spawn_floor(coord);
+                        world.spawn_trap(coord, Trap::Spikes);
+                    }
+                    'N' => {
+                        world.spawn_floor(coord);
+                        world.spawn_spawner(coord, Spawner::Nest);
+                    }
+                    'P' => {
+                        world.spawn_floor(coord);
+                        world.spawn_spawner(coord, Spawner::Portal);
+                    }
+                    '>' => {
+                        world.spawn_floor(coord);
+                        world.spawn_stairs(coord, Tile::StairsDown);
+                    }
+                    '<' => {
+                        world.spawn_floor(coord);
+                        world.spawn_stairs(coord, Tile::StairsUp);
+                    }
+                    '_' => {
+                        world.spawn_plate(coord);
+                    }
+                    'O' => {
+                        world.spawn_floor(coord);
+                        world.spawn_boulder(coord);
+                    }
+                    'Y' => {
+                        world.spawn_floor(coord);
+                        world.spawn_brazier(coord);
+                    }
+                    '@' => {
+                        if let Some((first, _)) = player {
+                            return Err(TerrainError::MultiplePlayers {
+                                first,
+                                second: position,
+                            });
+                        }
+                        world.spawn_floor(coord);
+                        let location = Location {
+                            coord,
+                            layer: Some(Layer::Character),
+                        };
+                        let entity = world.insert_entity_data(location, World::make_player());
+                        player = Some((position, entity));
+                    }
+                    ch => return Err(TerrainError::UnknownChar { ch, position }),
+                }
+            }
+        }
+        Ok(Terrain {
+            world,
+            player_entity: player.map(|(_, entity)| entity),
+            objective,
+            hazard,
+        })
+    }
+}
+
+/// The maps of each level of a dungeon, from the top down. The player starts on the first.
+#[derive(Debug, Clone)]
+pub struct Maps {
+    levels: Vec<String>,
+}
+
+impl Maps {
+    pub fn builtin() -> Self {
+        Self {
+            levels: LEVELS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Loads a single level dungeon from a map file, checking that it's valid
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let level = fs::read_to_string(path)?;
+        Self::single(level).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// A single level dungeon, checking that the map is valid
+    pub fn single(level: String) -> Result<Self, TerrainError> {
+        if Terrain::parse(&level)?.player_entity.is_none() {
+            return Err(TerrainError::MissingPlayer);
+        }
+        Ok(Self {
+            levels: vec![level],
+        })
+    }
+
+    /// Parses every level. Maps are checked when they're loaded, so this doesn't fail.
+    pub fn parse(&self) -> Vec<Terrain> {
+        self.levels
+            .iter()
+            .map(|level| Terrain::parse(level).expect("invalid map"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MAP: &str = "; objective: boulders-on-plates\n\n#####\n#.@.#\n#####\n";
+
+    fn error(map: &str) -> String {
+        Terrain::parse(map).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn positions_count_setting_lines() {
+        assert_eq!(
+            error(&MAP.replace(".@.", ".@?")),
+            "unknown character '?' at line 4, column 4"
+        );
+        assert_eq!(
+            error(&MAP.replace(".@.", "@.@")),
+            "the map has more than one player ('@'), at line 4, column 2 and line 4, column 4"
+        );
+        assert_eq!(
+            error(&MAP.replace("#.@.#", "#.@.##")),
+            "line 4 is 6 characters wide but the first row is 5"
+        );
+    }
+
+    #[test]
+    fn oversized_map_is_rejected() {
+        let map = format!("@{}", ".".repeat(u16::MAX as usize));
+        assert!(matches!(
+            Terrain::parse(&map),
+            Err(TerrainError::TooLarge { .. })
+        ));
+    }
+}
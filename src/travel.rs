@@ -0,0 +1,323 @@
+use crate::{
+    components::{HitPoints, Tile},
+    visibility::VisibilityGrid,
+};
+use gridbugs::{
+    coord_2d::Coord,
+    direction::Direction,
+    entity_table::{ComponentTable, Entity},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+/// Time between steps while travelling at normal animation speed
+pub const TRAVEL_STEP_DURATION: Duration = Duration::from_millis(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// The nearest cell next to a part of the map the player hasn't seen
+    Unexplored,
+    Coord(Coord),
+}
+
+/// Cells within this distance of an exclusion's centre are excluded
+pub const EXCLUSION_RADIUS: u32 = 3;
+
+/// A region marked by the player as somewhere travel should avoid, such as the area around
+/// a trap or a monster they'd rather not wake
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exclusion {
+    pub centre: Coord,
+    pub radius: u32,
+}
+
+impl Exclusion {
+    pub fn contains(self, coord: Coord) -> bool {
+        (coord - self.centre).magnitude2() <= self.radius * self.radius
+    }
+}
+
+/// The exclusions on a level
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Exclusions {
+    exclusions: Vec<Exclusion>,
+}
+
+impl Exclusions {
+    /// Adds an exclusion centred on the coord, or removes the one there is, returning
+    /// whether one was added
+    pub fn toggle(&mut self, centre: Coord) -> bool {
+        let len = self.exclusions.len();
+        self.exclusions
+            .retain(|exclusion| exclusion.centre != centre);
+        if self.exclusions.len() < len {
+            return false;
+        }
+        self.exclusions.push(Exclusion {
+            centre,
+            radius: EXCLUSION_RADIUS,
+        });
+        true
+    }
+
+    pub fn contains(&self, coord: Coord) -> bool {
+        self.exclusions
+            .iter()
+            .any(|exclusion| exclusion.contains(coord))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Exclusion> + '_ {
+        self.exclusions.iter().cloned()
+    }
+}
+
+/// How low the player's health must fall for travel to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HealthThreshold {
+    Off,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl HealthThreshold {
+    pub const ALL: &'static [Self] = &[Self::Off, Self::Quarter, Self::Half, Self::ThreeQuarters];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&t| t == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Quarter => "below 25%",
+            Self::Half => "below 50%",
+            Self::ThreeQuarters => "below 75%",
+        }
+    }
+
+    fn percent(self) -> Option<u32> {
+        match self {
+            Self::Off => None,
+            Self::Quarter => Some(25),
+            Self::Half => Some(50),
+            Self::ThreeQuarters => Some(75),
+        }
+    }
+}
+
+/// The situations which stop the player travelling, chosen in the options
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TravelInterrupts {
+    /// A hostile the player wasn't already watching comes into view
+    pub new_monster: bool,
+    pub low_health: HealthThreshold,
+    /// The player steps onto a cell with an item on it
+    pub item: bool,
+}
+
+impl Default for TravelInterrupts {
+    fn default() -> Self {
+        Self {
+            new_monster: true,
+            low_health: HealthThreshold::Half,
+            item: false,
+        }
+    }
+}
+
+/// What the player can see and how they're doing, checked before each step of travel
+pub struct TravelStatus {
+    /// Each hostile the player can see, with its name
+    pub visible_hostiles: Vec<(Entity, &'static str)>,
+    pub hit_points: Option<HitPoints>,
+    /// The name of the item the player is standing on, if any
+    pub item_underfoot: Option<String>,
+}
+
+/// The player walking towards a destination one step at a time until they arrive or
+/// something interrupts them
+pub struct Travel {
+    pub destination: Destination,
+    until_next_step: Duration,
+    interrupts: TravelInterrupts,
+    /// Hostiles which were in view at the last step, so only new arrivals interrupt
+    seen_hostiles: ComponentTable<()>,
+    /// Whether the player started on an item, so that one doesn't interrupt
+    started_on_item: bool,
+}
+
+impl Travel {
+    /// Starts travelling, unless one of the interruption rules would stop the first step
+    pub fn start(
+        destination: Destination,
+        interrupts: TravelInterrupts,
+        status: &TravelStatus,
+    ) -> Result<Self, String> {
+        if interrupts.new_monster {
+            if let Some(&(_, name)) = status.visible_hostiles.first() {
+                return Err(format!("Not with the {} nearby!", name));
+            }
+        }
+        if is_low_health(interrupts, status) {
+            return Err("You're too badly hurt to travel.".to_string());
+        }
+        Ok(Self {
+            destination,
+            until_next_step: Duration::ZERO,
+            interrupts,
+            seen_hostiles: ComponentTable::default(),
+            started_on_item: status.item_underfoot.is_some(),
+        })
+    }
+
+    /// Checks the rules before a step, returning a message explaining why travel stopped if
+    /// one of them applies
+    pub fn interruption(&mut self, status: &TravelStatus) -> Option<String> {
+        let new_hostile = status
+            .visible_hostiles
+            .iter()
+            .find(|&&(entity, _)| !self.seen_hostiles.contains(entity))
+            .map(|&(_, name)| name);
+        self.seen_hostiles.clear();
+        for &(entity, _) in &status.visible_hostiles {
+            self.seen_hostiles.insert(entity, ());
+        }
+        if self.interrupts.new_monster {
+            if let Some(name) = new_hostile {
+                return Some(format!("You see a {}.", name));
+            }
+        }
+        if is_low_health(self.interrupts, status) {
+            return Some("You stop, badly hurt.".to_string());
+        }
+        let on_item = std::mem::take(&mut self.started_on_item);
+        if self.interrupts.item && !on_item {
+            if let Some(name) = status.item_underfoot.as_ref() {
+                return Some(format!("You stop at the {}.", name));
+            }
+        }
+        None
+    }
+
+    /// Advances by the time since the last tick. A `step_duration` of `None` takes a step
+    /// every tick. Returns true if it's time to take the next step.
+    pub fn tick(&mut self, since_last_tick: Duration, step_duration: Option<Duration>) -> bool {
+        self.until_next_step = self.until_next_step.saturating_sub(since_last_tick);
+        if self.until_next_step > Duration::ZERO {
+            return false;
+        }
+        self.until_next_step = step_duration.unwrap_or(Duration::ZERO);
+        true
+    }
+}
+
+fn is_low_health(interrupts: TravelInterrupts, status: &TravelStatus) -> bool {
+    match (interrupts.low_health.percent(), status.hit_points) {
+        (Some(percent), Some(hit_points)) => hit_points.current * 100 < hit_points.max * percent,
+        _ => false,
+    }
+}
+
+/// True if the player remembers the cell as somewhere safe to walk. Only what the player
+/// has seen is considered, so travelling doesn't reveal anything about the map.
+fn is_remembered_passable(visibility_grid: &VisibilityGrid, coord: Coord) -> bool {
+    if !visibility_grid.is_known(coord) {
+        return false;
+    }
+    let tile_layers = match visibility_grid.cell(coord) {
+        Some(cell) => cell.tile_layers(),
+        None => return false,
+    };
+    if tile_layers.floor.is_none() {
+        return false;
+    }
+    !matches!(
+        tile_layers.feature.map(|feature| feature.tile),
+        Some(
+            Tile::Wall
+                | Tile::Trap
+                | Tile::Nest
+                | Tile::Portal
+                | Tile::Boulder
+                | Tile::Brazier
+                | Tile::BrazierLit,
+        )
+    )
+}
+
+/// True if the cell borders a cell the player has never seen
+fn is_frontier(visibility_grid: &VisibilityGrid, coord: Coord) -> bool {
+    Direction::all().any(|direction| {
+        let neighbour = coord + direction.coord();
+        visibility_grid.cell(neighbour).is_some() && !visibility_grid.is_known(neighbour)
+    })
+}
+
+/// The cells along the shortest remembered path from `start` to the destination, not
+/// including `start`, or `None` if the player is already there or doesn't know a way. The
+/// path only enters excluded cells to reach a destination inside an exclusion or to leave
+/// one the player is already in.
+pub fn path(
+    visibility_grid: &VisibilityGrid,
+    exclusions: &Exclusions,
+    start: Coord,
+    destination: Destination,
+) -> Option<Vec<Coord>> {
+    let may_enter = |from: Coord, to: Coord| {
+        !exclusions.contains(to)
+            || exclusions.contains(from)
+            || destination == Destination::Coord(to)
+    };
+    let is_goal = |coord: Coord| match destination {
+        Destination::Unexplored => is_frontier(visibility_grid, coord),
+        Destination::Coord(goal) => coord == goal,
+    };
+    // each cell reached maps to the cell it was reached from
+    let mut came_from = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(coord) = queue.pop_front() {
+        if coord != start && is_goal(coord) {
+            let mut path = vec![coord];
+            let mut current = coord;
+            while let Some(&previous) = came_from.get(&current) {
+                if previous == start {
+                    break;
+                }
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for direction in Direction::all() {
+            let neighbour = coord + direction.coord();
+            if neighbour != start
+                && is_remembered_passable(visibility_grid, neighbour)
+                && may_enter(coord, neighbour)
+                && !came_from.contains_key(&neighbour)
+            {
+                came_from.insert(neighbour, coord);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+    None
+}
+
+/// The first step along the shortest remembered path from `start` to the destination
+pub fn first_step(
+    visibility_grid: &VisibilityGrid,
+    exclusions: &Exclusions,
+    start: Coord,
+    destination: Destination,
+) -> Option<Direction> {
+    let &next = path(visibility_grid, exclusions, start, destination)?.first()?;
+    Direction::all().find(|direction| start + direction.coord() == next)
+}
@@ -1,12 +1,14 @@
-use crate::{components::Tile, world::World};
+use crate::{components::Tile, fov::FovStrategy, line, world::World};
 use gridbugs::{
-    entity_table::Entity,
+    entity_table::{ComponentTable, Entity},
     grid_2d::{Coord, CoordIter, Grid, GridEnumerate, Size},
     rgb_int::Rgb24,
-    shadowcast::{vision_distance, Context as ShadowcastContext, DirectionBitmap, InputGrid},
+    shadowcast::{vision_distance, DirectionBitmap, InputGrid, VisionDistance},
 };
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Rational {
     pub numerator: u32,
     pub denominator: u32,
@@ -17,13 +19,59 @@ const VISION_DISTANCE_SQUARED: u32 = 500;
 pub const VISION_DISTANCE: vision_distance::Circle =
     vision_distance::Circle::new_squared(VISION_DISTANCE_SQUARED);
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Light {
     pub colour: Rgb24,
     pub vision_distance: vision_distance::Circle,
     pub diminish: Rational,
 }
 
+/// Makes a light's brightness vary over time, like a flame
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Flicker {
+    base_colour: Rgb24,
+    /// The dimmest the light gets, out of 255
+    min_brightness: u8,
+    frame_duration: Duration,
+    frame: u32,
+    until_next_frame: Duration,
+}
+
+impl Flicker {
+    pub fn new(base_colour: Rgb24, min_brightness: u8, frame_duration: Duration) -> Self {
+        Self {
+            base_colour,
+            min_brightness,
+            frame_duration,
+            frame: 0,
+            until_next_frame: frame_duration,
+        }
+    }
+
+    /// Advances the flicker by the time since the last tick, returning the light's new colour
+    /// if it changed
+    pub fn tick(&mut self, since_last_tick: Duration) -> Option<Rgb24> {
+        self.until_next_frame = self.until_next_frame.saturating_sub(since_last_tick);
+        if self.until_next_frame > Duration::ZERO {
+            return None;
+        }
+        self.until_next_frame = self.frame_duration;
+        self.frame = self.frame.wrapping_add(1);
+        // cheap hash of the frame number so the flicker looks irregular without drawing from
+        // the game's rng, which would make runs depend on the frame rate
+        let noise = self.frame.wrapping_mul(2_654_435_761) >> 16;
+        let range = 256 - self.min_brightness as u32;
+        let brightness = self.min_brightness as u32 + noise % range;
+        Some(self.base_colour.normalised_scalar_mul(brightness as u8))
+    }
+
+    /// Scales down the colour the light flickers around, returning the new colour
+    pub fn dim(&mut self, brightness: u8) -> Rgb24 {
+        self.base_colour = self.base_colour.normalised_scalar_mul(brightness);
+        self.base_colour
+    }
+}
+
 pub struct Visibility;
 
 impl InputGrid for Visibility {
@@ -40,13 +88,13 @@ impl InputGrid for Visibility {
 #[derive(Debug, Clone, Copy)]
 pub struct Omniscient;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct EntityTile {
     pub entity: Entity,
     pub tile: Tile,
 }
 
-#[derive()]
+#[derive(Serialize, Deserialize)]
 pub struct TileLayers {
     pub floor: Option<EntityTile>,
     pub feature: Option<EntityTile>,
@@ -54,11 +102,20 @@ pub struct TileLayers {
     pub item: Option<EntityTile>,
 }
 
-#[derive()]
+impl TileLayers {
+    /// The tile which is drawn on top when all layers are rendered
+    pub fn top(&self) -> Option<EntityTile> {
+        self.character.or(self.item).or(self.feature).or(self.floor)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct VisibilityCell {
     last_seen: u64,
     last_seen_next: u64,
     last_lit: u64,
+    /// When the cell was last seen only thanks to darkvision, being too dark to see otherwise
+    last_seen_in_darkness: u64,
     visible_directions: DirectionBitmap,
     light_colour: Rgb24,
     tile_layers: TileLayers,
@@ -70,6 +127,7 @@ impl Default for VisibilityCell {
             last_seen: 0,
             last_seen_next: 0,
             last_lit: 0,
+            last_seen_in_darkness: 0,
             visible_directions: DirectionBitmap::empty(),
             light_colour: Rgb24::new(0, 0, 0),
             tile_layers: TileLayers {
@@ -87,7 +145,9 @@ impl VisibilityCell {
         &self.tile_layers
     }
     pub fn visibility(&self, count: u64) -> CellVisibility {
-        if self.last_seen == count {
+        if self.last_seen_in_darkness == count {
+            CellVisibility::CurrentlyVisibleInDarkness
+        } else if self.last_seen == count {
             let light_colour = if self.last_lit == count {
                 Some(self.light_colour)
             } else {
@@ -102,15 +162,223 @@ impl VisibilityCell {
     }
 }
 
+/// The cells visible from a point, kept until something changes which could affect them
+struct CachedFov {
+    eye: Coord,
+    cells: Vec<(Coord, DirectionBitmap, u8)>,
+}
+
+impl CachedFov {
+    fn compute(
+        eye: Coord,
+        world: &World,
+        vision_distance: vision_distance::Circle,
+        fov: &mut dyn FovStrategy,
+    ) -> Self {
+        let mut cells = Vec::new();
+        fov.for_each_visible(
+            eye,
+            world,
+            vision_distance,
+            &mut |coord, directions, visibility| cells.push((coord, directions, visibility)),
+        );
+        Self { eye, cells }
+    }
+
+    /// The cached cells are out of date if the eye moved or the opacity of a cell in range
+    /// changed
+    fn is_stale(
+        &self,
+        eye: Coord,
+        vision_distance: vision_distance::Circle,
+        opacity_changes: &[Coord],
+    ) -> bool {
+        self.eye != eye
+            || opacity_changes
+                .iter()
+                .any(|&coord| vision_distance.in_range(coord - eye))
+    }
+}
+
+/// The light which static lights cast on each cell, along with the directions from which each
+/// contribution can be seen. This only changes when the map does, so rather than recomputing
+/// it on every update it's kept until a static light is added, removed or dimmed, or the
+/// opacity of a cell in range of a static light changes.
+struct StaticLightField {
+    lights: Vec<(Entity, Coord, Rgb24, vision_distance::Circle)>,
+    contributions: Grid<Vec<(DirectionBitmap, Rgb24)>>,
+}
+
+impl StaticLightField {
+    fn compute(world: &World, fov: &mut dyn FovStrategy) -> Self {
+        let mut contributions = Grid::new_fn(world.size(), |_| Vec::new());
+        let mut lights = Vec::new();
+        for (entity, light_coord, light) in world.static_lights() {
+            lights.push((entity, light_coord, light.colour, light.vision_distance));
+            fov.for_each_visible(
+                light_coord,
+                world,
+                light.vision_distance,
+                &mut |cell_coord, visible_directions, visibility| {
+                    let colour = light_contribution(light, light_coord, cell_coord, visibility);
+                    if let Some(cell) = contributions.get_mut(cell_coord) {
+                        cell.push((visible_directions, colour));
+                    }
+                },
+            );
+        }
+        Self {
+            lights,
+            contributions,
+        }
+    }
+
+    fn is_stale(&self, world: &World, opacity_changes: &[Coord]) -> bool {
+        let lights_changed = !self
+            .lights
+            .iter()
+            .map(|&(entity, coord, colour, _)| (entity, coord, colour))
+            .eq(world
+                .static_lights()
+                .map(|(entity, coord, light)| (entity, coord, light.colour)));
+        lights_changed
+            || self
+                .lights
+                .iter()
+                .any(|&(_, light_coord, _, vision_distance)| {
+                    opacity_changes
+                        .iter()
+                        .any(|&coord| vision_distance.in_range(coord - light_coord))
+                })
+    }
+
+    /// The total light reaching a cell from the static lights which can be seen from any of
+    /// the given directions, or `None` if no static light reaches the cell
+    fn light_colour(&self, coord: Coord, visible_directions: DirectionBitmap) -> Option<Rgb24> {
+        self.contributions
+            .get(coord)?
+            .iter()
+            .filter(|(directions, _)| !(*directions & visible_directions).is_empty())
+            .fold(None, |total: Option<Rgb24>, &(_, colour)| {
+                Some(total.unwrap_or(Rgb24::new(0, 0, 0)).saturating_add(colour))
+            })
+    }
+}
+
+/// Reuses the FOV of the player and each dynamic light between updates, only recomputing the
+/// ones which could have changed
+#[derive(Default)]
+struct FovCache {
+    player: Option<CachedFov>,
+    lights: ComponentTable<CachedFov>,
+    static_light_field: Option<StaticLightField>,
+}
+
+fn light_contribution(
+    light: &Light,
+    light_coord: Coord,
+    cell_coord: Coord,
+    visibility: u8,
+) -> Rgb24 {
+    let distance_squared = (light_coord - cell_coord).magnitude2();
+    let inverse_light_intensity =
+        (distance_squared * light.diminish.numerator) / light.diminish.denominator;
+    light
+        .colour
+        .scalar_div(inverse_light_intensity.max(1))
+        .normalised_scalar_mul(visibility)
+}
+
+/// The total light reaching a cell from every light in the world, not counting ambient light.
+/// Unlike the visibility grid this doesn't depend on what the player can see, so it can be
+/// asked about any cell. A light reaches a cell if nothing opaque is on the line between them.
+/// Returns `None` if the cell isn't on the map.
+pub fn light_level(world: &World, coord: Coord) -> Option<Rgb24> {
+    world.checked_coord(coord)?;
+    let total = world
+        .static_lights()
+        .chain(world.dynamic_lights())
+        .filter(|(_, light_coord, light)| light.vision_distance.in_range(coord - *light_coord))
+        .filter(|&(_, light_coord, _)| {
+            line::cells(light_coord, coord)
+                .into_iter()
+                .filter(|&cell| cell != coord)
+                .all(|cell| world.get_opacity_at_coord(cell) < 255)
+        })
+        .fold(Rgb24::new(0, 0, 0), |total, (_, light_coord, light)| {
+            total.saturating_add(light_contribution(light, light_coord, coord, 255))
+        });
+    Some(total)
+}
+
+fn update_tile_layers(tile_layers: &mut TileLayers, world: &World, coord: Coord) {
+    let layers = match world.spatial_table.layers_at(coord) {
+        Some(layers) => layers,
+        None => return,
+    };
+    if let Some(entity) = layers.floor {
+        if let Some(&tile) = world.components.tile.get(entity) {
+            tile_layers.floor = Some(EntityTile { entity, tile });
+        }
+    } else {
+        tile_layers.floor = None;
+    }
+    if let Some(entity) = layers.feature {
+        if let Some(&tile) = world.components.tile.get(entity) {
+            tile_layers.feature = Some(EntityTile { entity, tile });
+        }
+    } else {
+        tile_layers.feature = None;
+    }
+    if let Some(entity) = layers.character {
+        if let Some(&tile) = world.components.tile.get(entity) {
+            tile_layers.character = Some(EntityTile { entity, tile });
+        }
+    } else {
+        tile_layers.character = None;
+    }
+    if let Some(entity) = layers.item {
+        if let Some(&tile) = world.components.tile.get(entity) {
+            tile_layers.item = Some(EntityTile { entity, tile });
+        }
+    } else {
+        tile_layers.item = None;
+    }
+}
+
+fn refresh<'a>(
+    cached: &'a mut Option<CachedFov>,
+    eye: Coord,
+    world: &World,
+    vision_distance: vision_distance::Circle,
+    opacity_changes: &[Coord],
+    fov: &mut dyn FovStrategy,
+) -> &'a [(Coord, DirectionBitmap, u8)] {
+    let stale = cached
+        .as_ref()
+        .map(|cached| cached.is_stale(eye, vision_distance, opacity_changes))
+        .unwrap_or(true);
+    if stale {
+        *cached = Some(CachedFov::compute(eye, world, vision_distance, fov));
+    }
+    &cached.as_ref().unwrap().cells
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct VisibilityGrid {
     grid: Grid<VisibilityCell>,
     count: u64,
+    #[serde(skip)]
+    fov_cache: FovCache,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CellVisibility {
     NeverVisible,
     PreviouslyVisible,
     CurrentlyVisibleWithLightColour(Option<Rgb24>),
+    /// Too dark to see, but close enough to be seen with darkvision
+    CurrentlyVisibleInDarkness,
 }
 
 impl VisibilityGrid {
@@ -118,25 +386,69 @@ impl VisibilityGrid {
         Self {
             grid: Grid::new_default(size),
             count: 1,
+            fov_cache: FovCache::default(),
         }
     }
+
+    /// Forgets all saved FOVs, which is necessary after changing FOV algorithm or after the
+    /// level has been changed without the grid being updated
+    pub fn clear_fov_cache(&mut self) {
+        self.fov_cache = FovCache::default();
+    }
     pub fn count(&self) -> u64 {
         self.count
     }
-    pub fn enumerate(&self) -> GridEnumerate<VisibilityCell> {
+    pub fn enumerate(&self) -> GridEnumerate<'_, VisibilityCell> {
         self.grid.enumerate()
     }
 
+    pub fn cell(&self, coord: Coord) -> Option<&VisibilityCell> {
+        self.grid.get(coord)
+    }
+
+    /// True if any cell within range of a light at `centre` was in the player's line of sight
+    /// at the last update, lit or not, so changing the light could change what they see
+    pub fn is_light_in_view(
+        &self,
+        centre: Coord,
+        vision_distance: vision_distance::Circle,
+    ) -> bool {
+        let radius = (vision_distance.distance_squared() as f64).sqrt().ceil() as i32;
+        (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| Coord::new(dx, dy)))
+            .filter(|&delta| vision_distance.in_range(delta))
+            .filter_map(|delta| self.grid.get(centre + delta))
+            .any(|cell| cell.last_seen_next == self.count)
+    }
+
+    pub fn is_currently_visible(&self, coord: Coord) -> bool {
+        self.grid
+            .get(coord)
+            .map(|cell| cell.last_seen == self.count)
+            .unwrap_or(false)
+    }
+
+    /// True if the cell at the given coord has ever been seen
+    pub fn is_known(&self, coord: Coord) -> bool {
+        self.grid
+            .get(coord)
+            .map(|cell| cell.last_seen != 0)
+            .unwrap_or(false)
+    }
+
     pub fn update(
         &mut self,
         player_coord: Coord,
         world: &World,
-        shadowcast_context: &mut ShadowcastContext<u8>,
+        opacity_changes: &[Coord],
+        fov: &mut dyn FovStrategy,
+        darkvision: Option<vision_distance::Circle>,
         omniscient: Option<Omniscient>,
     ) {
         self.count += 1;
         let count = self.count;
         let grid = &mut self.grid;
+        let fov_cache = &mut self.fov_cache;
         if let Some(Omniscient) = omniscient {
             for coord in CoordIter::new(world.size()) {
                 let cell = grid.get_checked_mut(coord);
@@ -145,106 +457,102 @@ impl VisibilityGrid {
                 cell.visible_directions = DirectionBitmap::all();
                 cell.last_lit = count;
                 cell.light_colour = Rgb24::new_grey(255);
-                let layers = world.spatial_table.layers_at_checked(coord);
-                if let Some(entity) = layers.floor {
-                    if let Some(&tile) = world.components.tile.get(entity) {
-                        cell.tile_layers.floor = Some(EntityTile { entity, tile });
-                    }
-                } else {
-                    cell.tile_layers.floor = None;
+                update_tile_layers(&mut cell.tile_layers, world, coord);
+            }
+            return;
+        }
+        let static_light_field_stale = fov_cache
+            .static_light_field
+            .as_ref()
+            .map(|field| field.is_stale(world, opacity_changes))
+            .unwrap_or(true);
+        if static_light_field_stale {
+            fov_cache.static_light_field = Some(StaticLightField::compute(world, fov));
+        }
+        let static_light_field = fov_cache.static_light_field.as_ref().unwrap();
+        let player_cells = refresh(
+            &mut fov_cache.player,
+            player_coord,
+            world,
+            VISION_DISTANCE,
+            opacity_changes,
+            fov,
+        );
+        for &(coord, visible_directions, _visibility) in player_cells {
+            let cell = match grid.get_mut(coord) {
+                Some(cell) => cell,
+                None => continue,
+            };
+            cell.last_seen_next = count;
+            cell.visible_directions = visible_directions;
+            cell.last_lit = count;
+            cell.light_colour = AMBIENT_COL;
+            if let Some(light_colour) = static_light_field.light_colour(coord, visible_directions) {
+                cell.light_colour = cell.light_colour.saturating_add(light_colour);
+                if cell.light_colour.saturating_channel_total() > 31 {
+                    update_tile_layers(&mut cell.tile_layers, world, coord);
+                    cell.last_seen = count;
                 }
-                if let Some(entity) = layers.feature {
-                    if let Some(&tile) = world.components.tile.get(entity) {
-                        cell.tile_layers.feature = Some(EntityTile { entity, tile });
+            }
+        }
+        let removed_lights = fov_cache
+            .lights
+            .entities()
+            .filter(|&entity| !world.components.light.contains(entity))
+            .collect::<Vec<_>>();
+        for entity in removed_lights {
+            fov_cache.lights.remove(entity);
+        }
+        for (entity, light_coord, light) in world.dynamic_lights() {
+            let stale = fov_cache
+                .lights
+                .get(entity)
+                .map(|cached| cached.is_stale(light_coord, light.vision_distance, opacity_changes))
+                .unwrap_or(true);
+            if stale {
+                let cached = CachedFov::compute(light_coord, world, light.vision_distance, fov);
+                fov_cache.lights.insert(entity, cached);
+            }
+            let cells = match fov_cache.lights.get(entity) {
+                Some(cached) => &cached.cells,
+                None => continue,
+            };
+            for &(cell_coord, visible_directions, visibility) in cells {
+                let cell = match grid.get_mut(cell_coord) {
+                    Some(cell) => cell,
+                    None => continue,
+                };
+                if cell.last_seen_next == count
+                    && !(visible_directions & cell.visible_directions).is_empty()
+                {
+                    cell.light_colour = cell.light_colour.saturating_add(light_contribution(
+                        light,
+                        light_coord,
+                        cell_coord,
+                        visibility,
+                    ));
+                    if cell.last_seen != count && cell.light_colour.saturating_channel_total() > 31
+                    {
+                        update_tile_layers(&mut cell.tile_layers, world, cell_coord);
+                        cell.last_seen = count;
                     }
-                } else {
-                    cell.tile_layers.feature = None;
                 }
-                if let Some(entity) = layers.character {
-                    if let Some(&tile) = world.components.tile.get(entity) {
-                        cell.tile_layers.character = Some(EntityTile { entity, tile });
-                    }
-                } else {
-                    cell.tile_layers.character = None;
+            }
+        }
+        // darkvision only fills in what's still too dark to see once every light is counted
+        if let Some(darkvision) = darkvision {
+            for &(coord, _, _) in player_cells {
+                if !darkvision.in_range(coord - player_coord) {
+                    continue;
                 }
-                if let Some(entity) = layers.item {
-                    if let Some(&tile) = world.components.tile.get(entity) {
-                        cell.tile_layers.item = Some(EntityTile { entity, tile });
+                if let Some(cell) = grid.get_mut(coord) {
+                    if cell.last_seen != count {
+                        update_tile_layers(&mut cell.tile_layers, world, coord);
+                        cell.last_seen = count;
+                        cell.last_seen_in_darkness = count;
                     }
-                } else {
-                    cell.tile_layers.item = None;
                 }
             }
-        } else {
-            shadowcast_context.for_each_visible(
-                player_coord,
-                &Visibility,
-                world,
-                VISION_DISTANCE,
-                255,
-                |coord, visible_directions, _visibility| {
-                    let cell = grid.get_checked_mut(coord);
-                    cell.last_seen_next = count;
-                    cell.visible_directions = visible_directions;
-                    cell.last_lit = count;
-                    cell.light_colour = AMBIENT_COL;
-                },
-            );
-        }
-        for (light_coord, light) in world.all_lights_by_coord() {
-            shadowcast_context.for_each_visible(
-                light_coord,
-                &Visibility,
-                world,
-                light.vision_distance,
-                255,
-                |cell_coord, visible_directions, visibility| {
-                    let cell = grid.get_checked_mut(cell_coord);
-                    if cell.last_seen_next == count
-                        && !(visible_directions & cell.visible_directions).is_empty()
-                    {
-                        let distance_squared = (light_coord - cell_coord).magnitude2();
-                        let inverse_light_intensity = (distance_squared * light.diminish.numerator)
-                            / light.diminish.denominator;
-                        let light_colour = light.colour.scalar_div(inverse_light_intensity.max(1));
-                        cell.light_colour = cell
-                            .light_colour
-                            .saturating_add(light_colour.normalised_scalar_mul(visibility));
-                        if cell.light_colour.saturating_channel_total() > 31 {
-                            let layers = world.spatial_table.layers_at_checked(cell_coord);
-                            if let Some(entity) = layers.floor {
-                                if let Some(&tile) = world.components.tile.get(entity) {
-                                    cell.tile_layers.floor = Some(EntityTile { entity, tile });
-                                }
-                            } else {
-                                cell.tile_layers.floor = None;
-                            }
-                            if let Some(entity) = layers.feature {
-                                if let Some(&tile) = world.components.tile.get(entity) {
-                                    cell.tile_layers.feature = Some(EntityTile { entity, tile });
-                                }
-                            } else {
-                                cell.tile_layers.feature = None;
-                            }
-                            if let Some(entity) = layers.character {
-                                if let Some(&tile) = world.components.tile.get(entity) {
-                                    cell.tile_layers.character = Some(EntityTile { entity, tile });
-                                }
-                            } else {
-                                cell.tile_layers.character = None;
-                            }
-                            if let Some(entity) = layers.item {
-                                if let Some(&tile) = world.components.tile.get(entity) {
-                                    cell.tile_layers.item = Some(EntityTile { entity, tile });
-                                }
-                            } else {
-                                cell.tile_layers.item = None;
-                            }
-                            cell.last_seen = count;
-                        }
-                    }
-                },
-            );
         }
     }
 }
@@ -1,19 +1,69 @@
-use crate::visibility::{Light, Rational};
+use crate::visibility::{Flicker, Light, Rational};
 use crate::{
-    components::{Components, EntityData, Tile},
+    ai::{self, AiBehaviours, Movement},
+    components::{
+        Components, Description, Disposition, DoorState, EntityData, Grip, Hand, HitPoints,
+        Inventory, Item, Npc, Spawner, Stamina, Territory, Tile, Trap,
+    },
     spatial::{Layer, Location, SpatialTable},
 };
 use gridbugs::{
     coord_2d::{Coord, Size},
+    direction::Direction,
     entity_table::{Entity, EntityAllocator},
     rgb_int::Rgb24,
     shadowcast::vision_distance::Circle,
 };
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
+/// Whether toggling an item put it in a hand or took it out of one
+pub enum EquipOutcome {
+    Equipped(Hand),
+    Unequipped,
+}
+
+/// A character removed from a world along with the contents of its inventory, so it can be
+/// inserted into a different world
+pub struct CharacterData {
+    entity_data: EntityData,
+    items: Vec<EntityData>,
+}
+
+const POTION_HEALING: u32 = 5;
+const FLICKER_FRAME_DURATION: Duration = Duration::from_millis(120);
+const TORCH_COLOUR: Rgb24 = Rgb24::new(255, 127, 31);
+/// How far goblins will stray from where they were spawned
+const GOBLIN_TERRITORY_RADIUS: u32 = 7;
+/// Energy spent on a typical action
+pub const ACTION_COST: u32 = 100;
+/// Characters with this speed act once per turn
+pub const NORMAL_SPEED: u32 = 10;
+/// Number of scheduler ticks in a turn at normal speed
+pub const TICKS_PER_TURN: u64 = (ACTION_COST / NORMAL_SPEED) as u64;
+/// Most energy a character can bank, so characters which go a long time without acting (or
+/// are given extra turns) can't save up a burst of actions
+const MAX_ENERGY: u32 = 2 * ACTION_COST;
+const GOBLIN_SPEED: u32 = 12;
+const ORC_SPEED: u32 = 8;
+const SHADE_SPEED: u32 = 10;
+/// Colour of the faint glow of a potion in flight
+const POTION_GLOW_COLOUR: Rgb24 = Rgb24::new(127, 0, 31);
+/// Colour of the flash where a thrown item strikes something
+const IMPACT_FLASH_COLOUR: Rgb24 = Rgb24::new(255, 255, 191);
+/// Lights dimmer than this, summed over their channels, are put out
+const MIN_LIGHT_CHANNEL_TOTAL: u32 = 48;
+/// How far from where a monster died its loot can land
+const LOOT_SCATTER_DISTANCE: i32 = 2;
+
+#[derive(Serialize, Deserialize)]
 pub struct World {
     pub entity_allocator: EntityAllocator,
     pub components: Components,
     pub spatial_table: SpatialTable,
+    /// Coords whose opacity has changed since the last visibility update
+    #[serde(skip)]
+    opacity_changes: Vec<Coord>,
 }
 
 impl World {
@@ -21,10 +71,38 @@ impl World {
         self.spatial_table.grid_size()
     }
 
+    /// Sets an entity's tile, along with the colours it's drawn in
+    fn insert_tile(&mut self, entity: Entity, tile: Tile) {
+        self.components.tile.insert(entity, tile);
+        self.components
+            .colour_hint
+            .insert(entity, tile.colour_hint());
+    }
+
+    /// Whether a coord is on the map. Queries about coords which aren't on the map are answered
+    /// with `None` or `false` rather than panicking, and updates to them fail.
+    pub fn contains_coord(&self, coord: Coord) -> bool {
+        coord.is_valid(self.size())
+    }
+
+    /// The coord, if it's on the map
+    pub fn checked_coord(&self, coord: Coord) -> Option<Coord> {
+        Some(coord).filter(|&coord| self.contains_coord(coord))
+    }
+
     pub fn entity_coord(&self, entity: Entity) -> Option<Coord> {
         self.spatial_table.coord_of(entity)
     }
 
+    /// The tile of the topmost thing in a cell
+    pub fn tile_at_coord(&self, coord: Coord) -> Option<Tile> {
+        let layers = self.spatial_table.layers_at(coord)?;
+        [layers.character, layers.item, layers.feature, layers.floor]
+            .into_iter()
+            .flatten()
+            .find_map(|entity| self.components.tile.get(entity).cloned())
+    }
+
     pub fn get_opacity_at_coord(&self, coord: Coord) -> u8 {
         self.spatial_table
             .layers_at(coord)
@@ -33,20 +111,493 @@ impl World {
             .unwrap_or(0)
     }
 
-    pub fn all_lights_by_coord<'a>(&'a self) -> impl 'a + Iterator<Item = (Coord, &'a Light)> {
+    pub fn is_solid_feature_at_coord(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|c| c.feature)
+            .map(|e| self.components.solid.contains(e))
+            .unwrap_or(false)
+    }
+
+    /// Returns the door at the coord if there is one in the given state
+    pub fn door_at_coord(&self, coord: Coord, door_state: DoorState) -> Option<Entity> {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|c| c.feature)
+            .filter(|&e| self.components.door_state.get(e) == Some(&door_state))
+    }
+
+    pub fn open_door(&mut self, door: Entity) {
+        self.components.door_state.insert(door, DoorState::Open);
+        self.insert_tile(door, Tile::DoorOpen);
+        self.components.solid.remove(door);
+        self.components.opacity.remove(door);
+        self.record_opacity_change(door);
+    }
+
+    pub fn close_door(&mut self, door: Entity) {
+        self.components.door_state.insert(door, DoorState::Closed);
+        self.insert_tile(door, Tile::DoorClosed);
+        self.components.solid.insert(door, ());
+        self.components.opacity.insert(door, 255);
+        self.record_opacity_change(door);
+    }
+
+    /// True if the coord has a floor and no solid feature
+    pub fn is_walkable_coord(&self, coord: Coord) -> bool {
+        if let Some(layers) = self.spatial_table.layers_at(coord) {
+            layers.floor.is_some() && !self.is_solid_feature_at_coord(coord)
+        } else {
+            false
+        }
+    }
+
+    pub fn is_character_at_coord(&self, coord: Coord) -> bool {
+        self.spatial_table
+            .layers_at(coord)
+            .map(|c| c.character.is_some())
+            .unwrap_or(false)
+    }
+
+    pub fn character_at_coord(&self, coord: Coord) -> Option<Entity> {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|c| c.character)
+    }
+
+    pub fn trap_at_coord(&self, coord: Coord) -> Option<Trap> {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|c| c.feature)
+            .and_then(|e| self.components.trap.get(e).cloned())
+    }
+
+    pub fn spawner_at_coord(&self, coord: Coord) -> Option<Entity> {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|c| c.feature)
+            .filter(|&e| self.components.spawner.contains(e))
+    }
+
+    pub fn item_at_coord(&self, coord: Coord) -> Option<Entity> {
+        self.spatial_table.layers_at(coord).and_then(|c| c.item)
+    }
+
+    /// Moves the item at a character's feet into its inventory. Returns the item if it was
+    /// picked up.
+    pub fn pick_up_item(&mut self, character: Entity) -> Option<Entity> {
+        let coord = self.entity_coord(character)?;
+        let item = self.item_at_coord(coord)?;
+        let inventory = self.components.inventory.get_mut(character)?;
+        if !inventory.insert(item) {
+            return None;
+        }
+        self.spatial_table.remove(item);
+        Some(item)
+    }
+
+    /// Moves an item out of a character's inventory onto the floor at its feet. Returns the
+    /// item if it was dropped, which fails if there is already an item on the floor.
+    pub fn drop_item(&mut self, character: Entity, index: usize) -> Option<Entity> {
+        let coord = self.entity_coord(character)?;
+        if self.item_at_coord(coord).is_some() {
+            return None;
+        }
+        let item = self
+            .components
+            .inventory
+            .get_mut(character)?
+            .remove(index)?;
+        self.components.equipped.remove(item);
+        self.spatial_table
+            .update(
+                item,
+                Location {
+                    coord,
+                    layer: Some(Layer::Item),
+                },
+            )
+            .unwrap();
+        Some(item)
+    }
+
+    /// Removes an item from a character's inventory without placing it on the map
+    pub fn take_inventory_item(&mut self, character: Entity, index: usize) -> Option<Entity> {
+        let item = self
+            .components
+            .inventory
+            .get_mut(character)?
+            .remove(index)?;
+        self.components.equipped.remove(item);
+        Some(item)
+    }
+
+    fn equipped_entity(&self, character: Entity, hand: Hand) -> Option<Entity> {
+        self.components
+            .inventory
+            .get(character)?
+            .items()
+            .iter()
+            .cloned()
+            .find(|&item| self.components.equipped.get(item) == Some(&hand))
+    }
+
+    /// The item a character holds in the given hand
+    pub fn equipped_item(&self, character: Entity, hand: Hand) -> Option<Item> {
+        let entity = self.equipped_entity(character, hand)?;
+        self.components.item.get(entity).cloned()
+    }
+
+    /// The total weight of the items in a character's inventory
+    pub fn carried_weight(&self, character: Entity) -> u32 {
+        self.components
+            .inventory
+            .get(character)
+            .map(|inventory| {
+                inventory
+                    .items()
+                    .iter()
+                    .filter_map(|&item| self.components.item.get(item))
+                    .map(|item| item.weight())
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    fn unequip(&mut self, character: Entity, hand: Hand) {
+        if let Some(item) = self.equipped_entity(character, hand) {
+            self.components.equipped.remove(item);
+        }
+    }
+
+    /// Equips the item at the given index of a character's inventory, putting away whatever
+    /// it replaces, or puts it away if it's already equipped. A one-handed weapon goes in the
+    /// off hand if the main hand already holds one, so characters can dual wield. Returns
+    /// `None` if the item can't be equipped.
+    pub fn toggle_equipped(&mut self, character: Entity, index: usize) -> Option<EquipOutcome> {
+        let item = *self
+            .components
+            .inventory
+            .get(character)?
+            .items()
+            .get(index)?;
+        let grip = self.components.item.get(item)?.grip()?;
+        if self.components.equipped.remove(item).is_some() {
+            return Some(EquipOutcome::Unequipped);
+        }
+        self.unequip(character, Hand::Both);
+        let hand = match grip {
+            Grip::TwoHanded => {
+                self.unequip(character, Hand::Main);
+                self.unequip(character, Hand::Off);
+                Hand::Both
+            }
+            Grip::Shield => {
+                self.unequip(character, Hand::Off);
+                Hand::Off
+            }
+            Grip::OneHanded => {
+                let main = self.equipped_item(character, Hand::Main);
+                let off = self.equipped_item(character, Hand::Off);
+                match (main, off) {
+                    (None, _) => Hand::Main,
+                    (Some(main), None) if main.grip() == Some(Grip::OneHanded) => Hand::Off,
+                    _ => {
+                        self.unequip(character, Hand::Main);
+                        Hand::Main
+                    }
+                }
+            }
+        };
+        self.components.equipped.insert(item, hand);
+        Some(EquipOutcome::Equipped(hand))
+    }
+
+    /// Places an item which isn't on the map onto the floor. Returns false if there is
+    /// already an item at the coord.
+    pub fn place_item(&mut self, item: Entity, coord: Coord) -> bool {
+        if self.item_at_coord(coord).is_some() || !self.is_walkable_coord(coord) {
+            return false;
+        }
+        self.spatial_table
+            .update(
+                item,
+                Location {
+                    coord,
+                    layer: Some(Layer::Item),
+                },
+            )
+            .is_ok()
+    }
+
+    pub fn inventory_index_of(&self, character: Entity, item: Item) -> Option<usize> {
+        self.components
+            .inventory
+            .get(character)?
+            .items()
+            .iter()
+            .position(|&entity| self.components.item.get(entity) == Some(&item))
+    }
+
+    /// Drinks the potion at the given index of a character's inventory, restoring some of
+    /// its hit points. Returns false if there's no potion at that index.
+    pub fn drink_potion(&mut self, character: Entity, index: usize) -> bool {
+        let potion = match self.components.inventory.get(character) {
+            Some(inventory) => match inventory.items().get(index) {
+                Some(&item) if self.components.item.get(item) == Some(&Item::Potion) => item,
+                _ => return false,
+            },
+            None => return false,
+        };
+        if let Some(inventory) = self.components.inventory.get_mut(character) {
+            inventory.remove(index);
+        }
+        self.remove_entity(potion);
+        if let Some(hit_points) = self.components.hit_points.get_mut(character) {
+            hit_points.current = (hit_points.current + POTION_HEALING).min(hit_points.max);
+        }
+        true
+    }
+
+    /// Reduces the hit points of an entity. Returns true if this reduces its hit points to 0.
+    pub fn damage_character(&mut self, entity: Entity, damage: u32) -> bool {
+        if let Some(hit_points) = self.components.hit_points.get_mut(entity) {
+            hit_points.current = hit_points.current.saturating_sub(damage);
+            hit_points.current == 0
+        } else {
+            false
+        }
+    }
+
+    pub fn remove_entity(&mut self, entity: Entity) {
+        if self.components.opacity.contains(entity) {
+            self.record_opacity_change(entity);
+        }
+        self.components.remove_entity(entity);
+        self.spatial_table.remove(entity);
+        self.entity_allocator.free(entity);
+    }
+
+    /// Finds a walkable coord with no character on it, at least `min_distance` cells away
+    /// from `centre` (measured as a chebyshev distance), preferring coords closer to `centre`
+    pub fn nearest_free_coord(&self, centre: Coord, min_distance: i32) -> Option<Coord> {
+        let size = self.size();
+        let max_distance = size.width().max(size.height()) as i32;
+        for distance in min_distance..max_distance {
+            for y in -distance..=distance {
+                for x in -distance..=distance {
+                    if x.abs() != distance && y.abs() != distance {
+                        continue;
+                    }
+                    let coord = centre + Coord::new(x, y);
+                    if self.is_walkable_coord(coord) && !self.is_character_at_coord(coord) {
+                        return Some(coord);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Gives each character energy according to its speed, with NPCs' speeds multiplied by
+    /// `npc_speed_factor`. Frozen characters gain nothing until they thaw.
+    pub fn tick_energy(&mut self, npc_speed_factor: u32) {
+        for (entity, &speed) in self.components.speed.iter() {
+            if let Some(frozen) = self.components.frozen.get_mut(entity) {
+                *frozen = frozen.saturating_sub(1);
+                if *frozen == 0 {
+                    self.components.frozen.remove(entity);
+                }
+                continue;
+            }
+            let speed = if self.components.npc.contains(entity) {
+                speed * npc_speed_factor
+            } else {
+                speed
+            };
+            match self.components.energy.get_mut(entity) {
+                Some(energy) => *energy = (*energy + speed).min(MAX_ENERGY),
+                None => {
+                    self.components.energy.insert(entity, speed.min(MAX_ENERGY));
+                }
+            }
+        }
+    }
+
+    /// Stops a character from acting for a number of turns, losing any energy it had saved
+    pub fn freeze(&mut self, entity: Entity, turns: u64) {
+        let ticks = turns * TICKS_PER_TURN;
+        let frozen = self.components.frozen.get(entity).copied().unwrap_or(0);
+        self.components.frozen.insert(entity, frozen.max(ticks));
+        self.components.energy.insert(entity, 0);
+    }
+
+    /// True if the character drifts above the ground, out of reach of traps
+    pub fn floats(&self, entity: Entity) -> bool {
+        self.components
+            .npc
+            .get(entity)
+            .is_some_and(|npc| npc.movement.floats)
+    }
+
+    pub fn is_frozen(&self, entity: Entity) -> bool {
+        self.components.frozen.contains(entity)
+    }
+
+    /// How far a character can see in the dark, if it has darkvision
+    pub fn darkvision(&self, entity: Entity) -> Option<Circle> {
+        self.components
+            .darkvision
+            .get(entity)
+            .map(|&cells| Circle::new_squared(cells * cells))
+    }
+
+    /// Makes a character stumble about for a number of turns
+    pub fn confuse(&mut self, entity: Entity, turns: u64) {
+        let ticks = turns * TICKS_PER_TURN;
+        let confused = self.components.confused.get(entity).copied().unwrap_or(0);
+        self.components.confused.insert(entity, confused.max(ticks));
+    }
+
+    pub fn is_confused(&self, entity: Entity) -> bool {
+        self.components.confused.contains(entity)
+    }
+
+    /// Turns an NPC to the player's side for a number of turns
+    pub fn charm(&mut self, entity: Entity, turns: u64) {
+        let npc = match self.components.npc.get_mut(entity) {
+            Some(npc) => npc,
+            None => return,
+        };
+        npc.disposition = Disposition::Friendly;
+        let ticks = turns * TICKS_PER_TURN;
+        let charmed = self.components.charmed.get(entity).copied().unwrap_or(0);
+        self.components.charmed.insert(entity, charmed.max(ticks));
+    }
+
+    pub fn is_charmed(&self, entity: Entity) -> bool {
+        self.components.charmed.contains(entity)
+    }
+
+    /// Counts down confusion and charms by a tick, returning the NPCs whose charm wore off
+    /// and which are hostile again
+    pub fn tick_statuses(&mut self) -> Vec<Entity> {
+        let mut recovered = Vec::new();
+        for (entity, confused) in self.components.confused.iter_mut() {
+            *confused = confused.saturating_sub(1);
+            if *confused == 0 {
+                recovered.push(entity);
+            }
+        }
+        for entity in recovered.drain(..) {
+            self.components.confused.remove(entity);
+        }
+        for (entity, charmed) in self.components.charmed.iter_mut() {
+            *charmed = charmed.saturating_sub(1);
+            if *charmed == 0 {
+                recovered.push(entity);
+            }
+        }
+        for &entity in &recovered {
+            self.components.charmed.remove(entity);
+            if let Some(npc) = self.components.npc.get_mut(entity) {
+                npc.disposition = Disposition::Hostile;
+            }
+        }
+        recovered
+    }
+
+    /// A hostile NPC next to a coord, if there are any
+    pub fn adjacent_hostile(&self, coord: Coord) -> Option<Entity> {
+        Direction::all()
+            .filter_map(|direction| self.character_at_coord(coord + direction.coord()))
+            .find(|&character| {
+                self.components
+                    .npc
+                    .get(character)
+                    .is_some_and(|npc| npc.disposition == Disposition::Hostile)
+            })
+    }
+
+    /// Gives a character the energy for another action on top of what it has, so it acts
+    /// twice before anyone else gets a turn
+    pub fn grant_extra_action(&mut self, entity: Entity) {
+        let energy = self.components.energy.get(entity).copied().unwrap_or(0);
+        self.components
+            .energy
+            .insert(entity, (energy + ACTION_COST).min(MAX_ENERGY));
+    }
+
+    /// A character's saved energy
+    pub fn energy(&self, entity: Entity) -> u32 {
+        self.components.energy.get(entity).copied().unwrap_or(0)
+    }
+
+    /// True if the character has enough energy for an action of the given cost
+    pub fn has_energy(&self, entity: Entity, cost: u32) -> bool {
+        self.components
+            .energy
+            .get(entity)
+            .map(|&energy| energy >= cost)
+            .unwrap_or(false)
+    }
+
+    pub fn spend_energy(&mut self, entity: Entity, cost: u32) {
+        if let Some(energy) = self.components.energy.get_mut(entity) {
+            *energy = energy.saturating_sub(cost);
+        }
+    }
+
+    pub fn all_npcs(&self) -> impl '_ + Iterator<Item = (Entity, &Npc)> {
+        self.components.npc.iter()
+    }
+
+    pub fn all_spawners(&self) -> impl '_ + Iterator<Item = (Entity, Spawner)> {
+        self.components
+            .spawner
+            .iter()
+            .map(|(entity, &spawner)| (entity, spawner))
+    }
+
+    fn lights<'a>(
+        &'a self,
+        is_static: bool,
+    ) -> impl 'a + Iterator<Item = (Entity, Coord, &'a Light)> {
         self.components
             .light
             .iter()
+            .filter(move |&(entity, _)| self.components.static_light.contains(entity) == is_static)
             .filter_map(move |(entity, light)| {
                 self.spatial_table
                     .coord_of(entity)
-                    .map(|coord| (coord, light))
+                    .map(|coord| (entity, coord, light))
             })
     }
 
+    /// Lights whose contribution only changes when the map does
+    pub fn static_lights(&self) -> impl '_ + Iterator<Item = (Entity, Coord, &Light)> {
+        self.lights(true)
+    }
+
+    /// Lights which can move or change colour, such as torches and flames
+    pub fn dynamic_lights(&self) -> impl '_ + Iterator<Item = (Entity, Coord, &Light)> {
+        self.lights(false)
+    }
+
     pub fn make_player() -> EntityData {
         EntityData {
             tile: Some(Tile::Player),
+            colour_hint: Some(Tile::Player.colour_hint()),
+            hit_points: Some(HitPoints::new_full(10)),
+            stamina: Some(Stamina::new_full(10)),
+            speed: Some(NORMAL_SPEED),
+            energy: Some(ACTION_COST),
+            damage: Some(2),
+            inventory: Some(Inventory::new(10)),
+            description: Some(Description::new(
+                "yourself",
+                "You look as ready as you'll ever be.",
+            )),
             light: Some(Light {
                 colour: Rgb24::new_grey(63),
                 vision_distance: Circle::new_squared(90),
@@ -60,6 +611,350 @@ impl World {
         }
     }
 
+    pub fn spawn_goblin(&mut self, coord: Coord) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Character),
+                },
+            )
+            .unwrap();
+        self.insert_tile(entity, Tile::Goblin);
+        self.components.description.insert(
+            entity,
+            Description::new("goblin", "A small, vicious humanoid with a rusty blade."),
+        );
+        self.components
+            .hit_points
+            .insert(entity, HitPoints::new_full(3));
+        self.components.damage.insert(entity, 1);
+        self.components.speed.insert(entity, GOBLIN_SPEED);
+        self.components.npc.insert(
+            entity,
+            Npc {
+                disposition: Disposition::Hostile,
+                behaviours: AiBehaviours {
+                    pack_tactics: true,
+                    flee: true,
+                    ..AiBehaviours::NONE
+                },
+                movement: Movement::default(),
+            },
+        );
+        self.components.territory.insert(
+            entity,
+            Territory {
+                home: coord,
+                radius: GOBLIN_TERRITORY_RADIUS,
+            },
+        );
+        entity
+    }
+
+    pub fn spawn_shade(&mut self, coord: Coord) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Character),
+                },
+            )
+            .unwrap();
+        self.insert_tile(entity, Tile::Shade);
+        self.components.description.insert(
+            entity,
+            Description::new(
+                "shade",
+                "A shape cut from the darkness itself. It shuns the light.",
+            ),
+        );
+        self.components
+            .hit_points
+            .insert(entity, HitPoints::new_full(2));
+        self.components.damage.insert(entity, 1);
+        self.components.speed.insert(entity, SHADE_SPEED);
+        self.components.npc.insert(
+            entity,
+            Npc {
+                disposition: Disposition::Hostile,
+                behaviours: AiBehaviours::NONE,
+                // shades would sooner go round a lit room than through it
+                movement: Movement {
+                    floats: true,
+                    shuns_light: true,
+                    risk_tolerance: ai::LIGHT_RISK,
+                },
+            },
+        );
+        entity
+    }
+
+    pub fn spawn_orc(&mut self, coord: Coord) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Character),
+                },
+            )
+            .unwrap();
+        self.insert_tile(entity, Tile::Orc);
+        self.components.description.insert(
+            entity,
+            Description::new("orc", "A hulking brute, smarter than it looks."),
+        );
+        self.components
+            .hit_points
+            .insert(entity, HitPoints::new_full(6));
+        self.components.damage.insert(entity, 2);
+        self.components.inventory.insert(entity, Inventory::new(2));
+        self.components.speed.insert(entity, ORC_SPEED);
+        self.components.npc.insert(
+            entity,
+            Npc {
+                disposition: Disposition::Hostile,
+                behaviours: AiBehaviours::ALL,
+                // orcs pick their way round traps, but will brave one to get at the player
+                movement: Movement {
+                    risk_tolerance: ai::TRAP_RISK,
+                    ..Movement::default()
+                },
+            },
+        );
+        self.spawn_item_in_inventory(entity, Item::Potion);
+        // orcs carry torches, which move around with them
+        self.components.light.insert(
+            entity,
+            Light {
+                colour: TORCH_COLOUR,
+                vision_distance: Circle::new_squared(40),
+                diminish: Rational {
+                    numerator: 1,
+                    denominator: 4,
+                },
+            },
+        );
+        self.components.flicker.insert(
+            entity,
+            Flicker::new(TORCH_COLOUR, 160, FLICKER_FRAME_DURATION),
+        );
+        entity
+    }
+
+    /// Creates an item which isn't on the map or in an inventory
+    fn create_item(&mut self, item: Item) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.insert_tile(entity, item.tile());
+        self.components.item.insert(entity, item);
+        self.components
+            .description
+            .insert(entity, Description::new(item.name(), item.description()));
+        entity
+    }
+
+    pub fn spawn_item(&mut self, coord: Coord, item: Item) -> Entity {
+        let entity = self.create_item(item);
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Item),
+                },
+            )
+            .unwrap();
+        entity
+    }
+
+    /// Coords at increasing chebyshev distance from `centre`, up to `max_distance`
+    fn coords_near(centre: Coord, max_distance: i32) -> impl Iterator<Item = Coord> {
+        (0..=max_distance).flat_map(move |distance| {
+            (-distance..=distance).flat_map(move |y| {
+                (-distance..=distance)
+                    .filter(move |x| x.abs() == distance || y.abs() == distance)
+                    .map(move |x| centre + Coord::new(x, y))
+            })
+        })
+    }
+
+    /// Creates an item on the floor as close as possible to `centre`, since each cell can
+    /// only hold one item. Returns `None` if there's no room nearby.
+    pub fn spawn_item_near(&mut self, centre: Coord, item: Item) -> Option<Entity> {
+        let coord = Self::coords_near(centre, LOOT_SCATTER_DISTANCE)
+            .find(|&coord| self.is_walkable_coord(coord) && self.item_at_coord(coord).is_none())?;
+        Some(self.spawn_item(coord, item))
+    }
+
+    /// Moves an item which isn't on the map to the floor as close as possible to `centre`.
+    /// Returns false if there's no room nearby.
+    pub fn place_item_near(&mut self, item: Entity, centre: Coord) -> bool {
+        Self::coords_near(centre, LOOT_SCATTER_DISTANCE).any(|coord| self.place_item(item, coord))
+    }
+
+    /// Drops coins near `centre`, adding them to a nearby pile of gold if there is one so gold
+    /// stacks rather than taking up a cell per drop
+    pub fn drop_gold(&mut self, centre: Coord, amount: u32) {
+        let existing = Self::coords_near(centre, LOOT_SCATTER_DISTANCE)
+            .filter_map(|coord| self.item_at_coord(coord))
+            .find(|&item| self.components.gold.contains(item));
+        if let Some(pile) = existing {
+            if let Some(gold) = self.components.gold.get_mut(pile) {
+                *gold += amount;
+            }
+            return;
+        }
+        if let Some(pile) = self.spawn_item_near(centre, Item::Gold) {
+            self.components.gold.insert(pile, amount);
+        }
+    }
+
+    /// Removes the pile of gold at the coord, returning how many coins it held
+    pub fn take_gold(&mut self, coord: Coord) -> Option<u32> {
+        let item = self.item_at_coord(coord)?;
+        let amount = self.components.gold.get(item).cloned()?;
+        self.remove_entity(item);
+        Some(amount)
+    }
+
+    /// Empties a character's inventory, returning the items which are no longer on the map
+    pub fn take_all_inventory_items(&mut self, character: Entity) -> Vec<Entity> {
+        let items = self
+            .components
+            .inventory
+            .get_mut(character)
+            .map(|inventory| inventory.take_items())
+            .unwrap_or_default();
+        for &item in &items {
+            self.components.equipped.remove(item);
+        }
+        items
+    }
+
+    /// Creates an item which isn't on the map, for giving directly to a character
+    pub fn spawn_item_in_inventory(&mut self, character: Entity, item: Item) -> Option<Entity> {
+        let entity = self.create_item(item);
+        if let Some(inventory) = self.components.inventory.get_mut(character) {
+            if inventory.insert(entity) {
+                return Some(entity);
+            }
+        }
+        self.remove_entity(entity);
+        None
+    }
+
+    pub fn spawn_door(&mut self, coord: Coord) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.close_door(entity);
+        self.components
+            .description
+            .insert(entity, Description::new("door", "A heavy wooden door."));
+        entity
+    }
+
+    pub fn spawn_trap(&mut self, coord: Coord, trap: Trap) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.insert_tile(entity, Tile::Trap);
+        self.components.trap.insert(entity, trap);
+        self.components.description.insert(
+            entity,
+            Description::new(Tile::Trap.name(), "Rusty spikes set into a pressure plate."),
+        );
+        entity
+    }
+
+    pub fn spawn_spawner(&mut self, coord: Coord, spawner: Spawner) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        let tile = spawner.tile();
+        self.insert_tile(entity, tile);
+        let text = match spawner {
+            Spawner::Nest => "A festering heap of rags and bones. Goblins crawl out of it.",
+            Spawner::Portal => "A shimmering tear in the air. Something is coming through.",
+        };
+        self.components
+            .description
+            .insert(entity, Description::new(tile.name(), text));
+        self.components.solid.insert(entity, ());
+        self.components
+            .hit_points
+            .insert(entity, HitPoints::new_full(spawner.hit_points()));
+        self.components.spawner.insert(entity, spawner);
+        self.components
+            .spawn_countdown
+            .insert(entity, spawner.interval());
+        entity
+    }
+
+    /// Creates a monster of the species with the given tile
+    pub fn spawn_monster(&mut self, coord: Coord, monster: Tile) -> Option<Entity> {
+        match monster {
+            Tile::Goblin => Some(self.spawn_goblin(coord)),
+            Tile::Orc => Some(self.spawn_orc(coord)),
+            Tile::Shade => Some(self.spawn_shade(coord)),
+            _ => None,
+        }
+    }
+
+    pub fn spawn_stairs(&mut self, coord: Coord, tile: Tile) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.insert_tile(entity, tile);
+        self.components
+            .description
+            .insert(entity, Description::new(tile.name(), "Worn stone steps."));
+        entity
+    }
+
+    /// Finds the coord of a feature with the given tile
+    pub fn find_feature(&self, tile: Tile) -> Option<Coord> {
+        self.components
+            .tile
+            .iter()
+            .filter(|&(_, &t)| t == tile)
+            .find_map(|(entity, _)| self.spatial_table.coord_of(entity))
+    }
+
     pub fn spawn_floor(&mut self, coord: Coord) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
@@ -71,10 +966,129 @@ impl World {
                 },
             )
             .unwrap();
-        self.components.tile.insert(entity, Tile::Floor);
+        self.insert_tile(entity, Tile::Floor);
         entity
     }
 
+    /// A pressure plate takes the place of the floor, so things can rest on it
+    pub fn spawn_plate(&mut self, coord: Coord) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Floor),
+                },
+            )
+            .unwrap();
+        self.insert_tile(entity, Tile::Plate);
+        self.components.description.insert(
+            entity,
+            Description::new(Tile::Plate.name(), "A stone slab which sinks under weight."),
+        );
+        entity
+    }
+
+    pub fn spawn_boulder(&mut self, coord: Coord) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.insert_tile(entity, Tile::Boulder);
+        self.components.description.insert(
+            entity,
+            Description::new(Tile::Boulder.name(), "A round boulder. It could be pushed."),
+        );
+        self.components.solid.insert(entity, ());
+        entity
+    }
+
+    pub fn spawn_brazier(&mut self, coord: Coord) -> Entity {
+        let entity = self.entity_allocator.alloc();
+        self.spatial_table
+            .update(
+                entity,
+                Location {
+                    coord,
+                    layer: Some(Layer::Feature),
+                },
+            )
+            .unwrap();
+        self.insert_tile(entity, Tile::Brazier);
+        self.components.description.insert(
+            entity,
+            Description::new("brazier", "An iron bowl of coals on a tripod."),
+        );
+        self.components.solid.insert(entity, ());
+        entity
+    }
+
+    /// Returns the boulder at the coord if there is one
+    pub fn boulder_at_coord(&self, coord: Coord) -> Option<Entity> {
+        self.feature_with_tile_at_coord(coord, Tile::Boulder)
+    }
+
+    /// Returns the unlit brazier at the coord if there is one
+    pub fn unlit_brazier_at_coord(&self, coord: Coord) -> Option<Entity> {
+        self.feature_with_tile_at_coord(coord, Tile::Brazier)
+    }
+
+    fn feature_with_tile_at_coord(&self, coord: Coord, tile: Tile) -> Option<Entity> {
+        self.spatial_table
+            .layers_at(coord)
+            .and_then(|layers| layers.feature)
+            .filter(|&feature| self.components.tile.get(feature) == Some(&tile))
+    }
+
+    /// Rolls a boulder into an empty cell. Returns false if something is in the way.
+    pub fn push_boulder(&mut self, boulder: Entity, destination: Coord) -> bool {
+        let layers = match self.spatial_table.layers_at(destination) {
+            Some(layers) => layers,
+            None => return false,
+        };
+        if layers.floor.is_none()
+            || layers.feature.is_some()
+            || layers.item.is_some()
+            || layers.character.is_some()
+        {
+            return false;
+        }
+        self.spatial_table
+            .update_coord(boulder, destination)
+            .is_ok()
+    }
+
+    pub fn light_brazier(&mut self, brazier: Entity) {
+        self.insert_tile(brazier, Tile::BrazierLit);
+        self.components.light.insert(
+            brazier,
+            Light {
+                colour: Rgb24::new(255, 127, 31),
+                vision_distance: Circle::new_squared(100),
+                diminish: Rational {
+                    numerator: 1,
+                    denominator: 10,
+                },
+            },
+        );
+    }
+
+    /// The coords of every entity with the given tile
+    pub fn coords_with_tile(&self, tile: Tile) -> impl '_ + Iterator<Item = Coord> {
+        self.components
+            .tile
+            .iter()
+            .filter(move |&(_, &t)| t == tile)
+            .filter_map(|(entity, _)| self.spatial_table.coord_of(entity))
+    }
+
     pub fn spawn_wall(&mut self, coord: Coord) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
@@ -86,13 +1100,16 @@ impl World {
                 },
             )
             .unwrap();
-        self.components.tile.insert(entity, Tile::Wall);
+        self.insert_tile(entity, Tile::Wall);
+        self.components
+            .description
+            .insert(entity, Description::new("wall", "Rough-hewn stone."));
         self.components.solid.insert(entity, ());
         self.components.opacity.insert(entity, 255);
         entity
     }
 
-    pub fn spawn_light(&mut self, coord: Coord, colour: Rgb24) -> Entity {
+    fn insert_light(&mut self, coord: Coord, colour: Rgb24) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table
             .update(entity, Location { coord, layer: None })
@@ -111,6 +1128,132 @@ impl World {
         entity
     }
 
+    /// Adds a light which isn't part of the level, such as the glow of something in flight.
+    /// Whoever adds one is responsible for removing it. Returns `None` if the coord isn't on
+    /// the map.
+    pub fn spawn_transient_light(&mut self, coord: Coord, light: Light) -> Option<Entity> {
+        self.checked_coord(coord)?;
+        let entity = self.entity_allocator.alloc();
+        if self
+            .spatial_table
+            .update(entity, Location { coord, layer: None })
+            .is_err()
+        {
+            self.entity_allocator.free(entity);
+            return None;
+        }
+        self.components.light.insert(entity, light);
+        Some(entity)
+    }
+
+    /// Moves a light which isn't attached to anything else. Returns false, leaving the light
+    /// where it was, if the coord isn't on the map.
+    pub fn move_light(&mut self, entity: Entity, coord: Coord) -> bool {
+        // the spatial table only checks the bounds of coords in a layer
+        self.contains_coord(coord)
+            && self
+                .spatial_table
+                .update(entity, Location { coord, layer: None })
+                .is_ok()
+    }
+
+    /// The light a thrown item gives off while it's in flight. Items which are lights carry
+    /// their own, and potions glow faintly.
+    pub fn flight_light(&self, item: Entity) -> Option<Light> {
+        if let Some(&light) = self.components.light.get(item) {
+            return Some(light);
+        }
+        if self.components.item.get(item) != Some(&Item::Potion) {
+            return None;
+        }
+        Some(Light {
+            colour: POTION_GLOW_COLOUR,
+            vision_distance: Circle::new_squared(20),
+            diminish: Rational {
+                numerator: 1,
+                denominator: 4,
+            },
+        })
+    }
+
+    /// A bright light for a moment where something is struck
+    pub fn impact_flash() -> Light {
+        Light {
+            colour: IMPACT_FLASH_COLOUR,
+            vision_distance: Circle::new_squared(30),
+            diminish: Rational {
+                numerator: 1,
+                denominator: 3,
+            },
+        }
+    }
+
+    pub fn spawn_light(&mut self, coord: Coord, colour: Rgb24) -> Entity {
+        let entity = self.insert_light(coord, colour);
+        self.components.static_light.insert(entity, ());
+        entity
+    }
+
+    pub fn spawn_flickering_light(&mut self, coord: Coord, colour: Rgb24) -> Entity {
+        let entity = self.insert_light(coord, colour);
+        self.components
+            .flicker
+            .insert(entity, Flicker::new(colour, 127, FLICKER_FRAME_DURATION));
+        entity
+    }
+
+    /// Lights which aren't carried by a character, such as lamps and braziers
+    pub fn fixed_lights(&self) -> impl '_ + Iterator<Item = Entity> {
+        self.components
+            .light
+            .iter()
+            .map(|(entity, _)| entity)
+            .filter(move |&entity| !self.components.hit_points.contains(entity))
+    }
+
+    /// Scales down the brightness of a light, putting it out once it's too dim to see by. A
+    /// brazier which goes out can be lit again. Returns true if the light went out.
+    pub fn dim_light(&mut self, entity: Entity, brightness: u8) -> bool {
+        let light = match self.components.light.get_mut(entity) {
+            Some(light) => light,
+            None => return false,
+        };
+        light.colour = light.colour.normalised_scalar_mul(brightness);
+        let colour = match self.components.flicker.get_mut(entity) {
+            Some(flicker) => flicker.dim(brightness),
+            None => light.colour,
+        };
+        if u32::from(colour.saturating_channel_total()) >= MIN_LIGHT_CHANNEL_TOTAL {
+            return false;
+        }
+        self.components.light.remove(entity);
+        self.components.flicker.remove(entity);
+        self.components.static_light.remove(entity);
+        if self.components.tile.get(entity) == Some(&Tile::BrazierLit) {
+            self.insert_tile(entity, Tile::Brazier);
+        } else if !self.components.tile.contains(entity) {
+            // the light was all there was to the entity
+            self.components.remove_entity_data(entity);
+            self.spatial_table.remove(entity);
+            self.entity_allocator.free(entity);
+        }
+        true
+    }
+
+    /// Advances every flickering light, returning the ones whose colour changed
+    pub fn update_flickering_lights(&mut self, since_last_tick: Duration) -> Vec<Entity> {
+        let mut changed = Vec::new();
+        for (entity, flicker) in self.components.flicker.iter_mut() {
+            if let Some(colour) = flicker.tick(since_last_tick) {
+                if let Some(light) = self.components.light.get_mut(entity) {
+                    light.colour = colour;
+                    changed.push(entity);
+                }
+            }
+        }
+        changed
+    }
+
     pub fn new(size: Size) -> Self {
         let entity_allocator = EntityAllocator::default();
         let components = Components::default();
@@ -119,9 +1262,56 @@ impl World {
             entity_allocator,
             components,
             spatial_table,
+            opacity_changes: Vec::new(),
         }
     }
 
+    pub fn take_opacity_changes(&mut self) -> Vec<Coord> {
+        std::mem::take(&mut self.opacity_changes)
+    }
+
+    fn record_opacity_change(&mut self, entity: Entity) {
+        if let Some(coord) = self.entity_coord(entity) {
+            self.opacity_changes.push(coord);
+        }
+    }
+
+    pub fn take_character(&mut self, entity: Entity) -> CharacterData {
+        let mut entity_data = self.components.remove_entity_data(entity);
+        self.spatial_table.remove(entity);
+        self.entity_allocator.free(entity);
+        let items = entity_data
+            .inventory
+            .as_mut()
+            .map(|inventory| inventory.take_items())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| {
+                let item_data = self.components.remove_entity_data(item);
+                self.entity_allocator.free(item);
+                item_data
+            })
+            .collect();
+        CharacterData { entity_data, items }
+    }
+
+    pub fn insert_character(&mut self, coord: Coord, character_data: CharacterData) -> Entity {
+        let CharacterData { entity_data, items } = character_data;
+        let location = Location {
+            coord,
+            layer: Some(Layer::Character),
+        };
+        let entity = self.insert_entity_data(location, entity_data);
+        for item_data in items {
+            let item = self.entity_allocator.alloc();
+            self.components.insert_entity_data(item, item_data);
+            if let Some(inventory) = self.components.inventory.get_mut(entity) {
+                inventory.insert(item);
+            }
+        }
+        entity
+    }
+
     pub fn insert_entity_data(&mut self, location: Location, entity_data: EntityData) -> Entity {
         let entity = self.entity_allocator.alloc();
         self.spatial_table.update(entity, location).unwrap();
@@ -129,3 +1319,80 @@ impl World {
         entity
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::visibility;
+
+    const SIZE: Size = Size::new_u16(5, 3);
+    const CORNERS: [Coord; 4] = [
+        Coord::new(0, 0),
+        Coord::new(4, 0),
+        Coord::new(0, 2),
+        Coord::new(4, 2),
+    ];
+    const BEYOND: [Coord; 4] = [
+        Coord::new(-1, 0),
+        Coord::new(5, 0),
+        Coord::new(0, -1),
+        Coord::new(0, 3),
+    ];
+
+    fn floor() -> World {
+        let mut world = World::new(SIZE);
+        for coord in SIZE.coord_iter_row_major() {
+            world.spawn_floor(coord);
+        }
+        world
+    }
+
+    #[test]
+    fn coords_beyond_the_edge_are_off_the_map() {
+        let world = floor();
+        for coord in CORNERS {
+            assert!(world.contains_coord(coord));
+            assert_eq!(world.checked_coord(coord), Some(coord));
+            assert!(world.is_walkable_coord(coord));
+        }
+        for coord in BEYOND {
+            assert!(!world.contains_coord(coord));
+            assert_eq!(world.checked_coord(coord), None);
+            assert!(!world.is_walkable_coord(coord));
+            assert_eq!(world.get_opacity_at_coord(coord), 0);
+        }
+    }
+
+    #[test]
+    fn transient_lights_stay_on_the_map() {
+        let mut world = floor();
+        for coord in BEYOND {
+            assert!(world
+                .spawn_transient_light(coord, World::impact_flash())
+                .is_none());
+        }
+        for coord in CORNERS {
+            let light = world
+                .spawn_transient_light(coord, World::impact_flash())
+                .unwrap();
+            assert_eq!(world.entity_coord(light), Some(coord));
+            let outwards = |c: i32| if c == 0 { -1 } else { 1 };
+            let beyond = coord + Coord::new(outwards(coord.x), outwards(coord.y));
+            assert!(!world.move_light(light, beyond));
+            assert_eq!(world.entity_coord(light), Some(coord));
+        }
+    }
+
+    #[test]
+    fn lights_in_the_corner_light_the_map() {
+        let mut world = floor();
+        world.spawn_light(Coord::new(0, 0), Rgb24::new(255, 255, 255));
+        for coord in CORNERS {
+            let light = visibility::light_level(&world, coord).unwrap();
+            assert!(light.saturating_channel_total() > 0);
+        }
+        for coord in BEYOND {
+            assert_eq!(visibility::light_level(&world, coord), None);
+        }
+    }
+}